@@ -0,0 +1,50 @@
+use std::path::Path;
+use std::process::Command;
+
+/// (GLSL source, prebuilt SPIR-V, output name, preprocessor defines)
+const SHADERS: [(&str, &str, &str, &[&str]); 15] = [
+    ("src/shaders/shader.vert", "shaders/prebuilt/vert.spv", "vert.spv", &[]),
+    ("src/shaders/shader.vert", "shaders/prebuilt/vert_pulling.spv", "vert_pulling.spv", &["VERTEX_PULLING"]),
+    ("src/shaders/shader.frag", "shaders/prebuilt/frag.spv", "frag.spv", &[]),
+    ("src/shaders/shader.frag", "shaders/prebuilt/frag_bindless.spv", "frag_bindless.spv", &["BINDLESS"]),
+    ("src/shaders/post.vert", "shaders/prebuilt/post_vert.spv", "post_vert.spv", &[]),
+    ("src/shaders/tonemap.frag", "shaders/prebuilt/tonemap.spv", "tonemap.spv", &[]),
+    ("src/shaders/output.frag", "shaders/prebuilt/output.spv", "output.spv", &[]),
+    ("src/shaders/fxaa.frag", "shaders/prebuilt/fxaa.spv", "fxaa.spv", &[]),
+    ("src/shaders/debug_line.vert", "shaders/prebuilt/debug_line_vert.spv", "debug_line_vert.spv", &[]),
+    ("src/shaders/debug_line.frag", "shaders/prebuilt/debug_line_frag.spv", "debug_line_frag.spv", &[]),
+    ("src/shaders/text.vert", "shaders/prebuilt/text_vert.spv", "text_vert.spv", &[]),
+    ("src/shaders/text.frag", "shaders/prebuilt/text_frag.spv", "text_frag.spv", &[]),
+    ("src/shaders/gui.vert", "shaders/prebuilt/gui_vert.spv", "gui_vert.spv", &[]),
+    ("src/shaders/gui.frag", "shaders/prebuilt/gui_frag.spv", "gui_frag.spv", &[]),
+    ("src/shaders/cull.comp", "shaders/prebuilt/cull.spv", "cull.spv", &[]),
+];
+
+/// Compiles the built-in shaders with glslc into OUT_DIR, where they are embedded from.
+/// Falls back to the prebuilt SPIR-V in `shaders/prebuilt/` if glslc is not installed,
+/// regenerate them with scripts/compile_shaders.sh, which has the same list.
+fn main() {
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+
+    for (source, prebuilt, name, defines) in SHADERS {
+        println!("cargo:rerun-if-changed={}", source);
+        println!("cargo:rerun-if-changed={}", prebuilt);
+
+        let output = Path::new(&out_dir).join(name);
+        let compiled = Command::new("glslc")
+            .args(defines.iter().map(|define| format!("-D{}", define)))
+            .arg(source)
+            .arg("-o")
+            .arg(&output)
+            .status();
+
+        match compiled {
+            Ok(status) if status.success() => {},
+            Ok(status) => panic!("glslc failed to compile {}: {}", source, status),
+            Err(_) => {
+                println!("cargo:warning=glslc not found, embedding prebuilt {}", prebuilt);
+                std::fs::copy(prebuilt, &output).unwrap();
+            }
+        }
+    }
+}
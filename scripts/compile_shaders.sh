@@ -0,0 +1,28 @@
+#!/bin/sh
+# Regenerates the prebuilt SPIR-V in shaders/prebuilt/ with glslc, run it after changing src/shaders.
+# The shaders are the same as SHADERS in build.rs, which embeds these files when glslc is missing.
+set -e
+cd "$(dirname "$0")/.."
+
+compile() {
+    source=$1
+    output=$2
+    shift 2
+    glslc "$@" "$source" -o "shaders/prebuilt/$output"
+}
+
+compile src/shaders/shader.vert vert.spv
+compile src/shaders/shader.vert vert_pulling.spv -DVERTEX_PULLING
+compile src/shaders/shader.frag frag.spv
+compile src/shaders/shader.frag frag_bindless.spv -DBINDLESS
+compile src/shaders/post.vert post_vert.spv
+compile src/shaders/tonemap.frag tonemap.spv
+compile src/shaders/output.frag output.spv
+compile src/shaders/fxaa.frag fxaa.spv
+compile src/shaders/debug_line.vert debug_line_vert.spv
+compile src/shaders/debug_line.frag debug_line_frag.spv
+compile src/shaders/text.vert text_vert.spv
+compile src/shaders/text.frag text_frag.spv
+compile src/shaders/gui.vert gui_vert.spv
+compile src/shaders/gui.frag gui_frag.spv
+compile src/shaders/cull.comp cull.spv
@@ -0,0 +1,76 @@
+use log::{info, warn};
+
+use crate::time::Time;
+use crate::vulkanapp::VulkanApp;
+
+/// Top level state of the app, decides which systems tick and what is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppState {
+    /// Simulation runs, clicks pick objects
+    Playing,
+    /// Simulation is stopped, the scene is still drawn and pickable
+    Paused,
+    /// Simulation is stopped and the display calibration pattern is drawn instead of the scene
+    Calibrating,
+}
+
+impl AppState {
+    pub fn simulation_runs(self) -> bool {
+        self == AppState::Playing
+    }
+
+    pub fn accepts_picking(self) -> bool {
+        self != AppState::Calibrating
+    }
+}
+
+/// Owns the current state and runs the enter/exit actions of every transition
+pub struct StateMachine {
+    state: AppState,
+    calibration_path: &'static str,
+}
+
+impl StateMachine {
+    /// `calibration_path` is where the calibration is saved when leaving `Calibrating`
+    pub fn new(calibration_path: &'static str) -> Self {
+        Self {
+            state: AppState::Playing,
+            calibration_path,
+        }
+    }
+
+    pub fn state(&self) -> AppState {
+        self.state
+    }
+
+    pub fn transition(&mut self, to: AppState, time: &mut Time, vulkan_app: &mut VulkanApp) {
+        if to == self.state {
+            return;
+        }
+        // nothing else may pause the clock or toggle the pattern behind the state machine's back
+        debug_assert_eq!(time.is_paused(), !self.state.simulation_runs());
+        debug_assert_eq!(vulkan_app.calibration_pattern(), self.state == AppState::Calibrating);
+
+        match self.state {
+            AppState::Calibrating => {
+                vulkan_app.set_calibration_pattern(false);
+                if let Err(e) = vulkan_app.display_calibration().save(self.calibration_path) {
+                    warn!("Failed to save calibration to {}: {}", self.calibration_path, e);
+                }
+            },
+            AppState::Playing | AppState::Paused => {},
+        }
+
+        match to {
+            AppState::Calibrating => {
+                vulkan_app.set_calibration_pattern(true);
+                info!("Calibration: up/down changes exposure, left/right changes gamma, C or Escape saves and exits");
+            },
+            AppState::Playing | AppState::Paused => {},
+        }
+
+        time.set_paused(!to.simulation_runs());
+        info!("State: {:?} -> {:?}", self.state, to);
+        self.state = to;
+    }
+}
@@ -0,0 +1,35 @@
+use log::warn;
+
+use crate::vulkanapp::FrameTimingReport;
+
+pub struct WindowConfig {
+    pub title: &'static str,
+    /// Show live FPS and GPU time in the title instead of printing FPS to stdout
+    pub fps_in_title: bool,
+    pub icon_path: Option<String>,
+}
+
+/// Loads an image file and sets it as the window icon. Returns false if the file can't be loaded.
+pub fn set_window_icon(window: &mut glfw::Window, path: &str) -> bool {
+    let image = match image::open(path) {
+        Ok(image) => image.to_rgba8(),
+        Err(e) => {
+            warn!("Failed to load window icon {}: {}", path, e);
+            return false;
+        }
+    };
+
+    let (width, height) = image.dimensions();
+    // glfw wants RGBA bytes in memory order, packed into u32s
+    let pixels = image.pixels().map(|p| u32::from_ne_bytes(p.0)).collect();
+    window.set_icon_from_pixels(vec![glfw::PixelImage { width, height, pixels }]);
+    true
+}
+
+pub fn update_title(window: &mut glfw::Window, config: &WindowConfig, fps: u32, timing: &FrameTimingReport) {
+    let title = match timing.gpu_ms {
+        Some(gpu_ms) => format!("{} | {} FPS | GPU {:.2}ms", config.title, fps, gpu_ms),
+        None => format!("{} | {} FPS", config.title, fps),
+    };
+    window.set_title(&title);
+}
@@ -0,0 +1,219 @@
+use log::warn;
+use serde::Deserialize;
+
+use crate::vulkanapp::{AntiAliasing, DeviceProfile, GpuSelector};
+
+/// What `main` runs, chosen on the command line only
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Window,
+    /// `--self-test`
+    SelfTest,
+    /// `--headless`, renders a few frames offscreen and saves the last one
+    Headless,
+    /// `--time-lapse <frames>`, game time runs `Config::time_lapse_speed` times faster than the video
+    TimeLapse { frame_count: u32 },
+}
+
+/// Startup settings, loaded from a file and overridden by command line arguments
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Window size when windowed, and the size of headless renders
+    pub width: u32,
+    pub height: u32,
+    /// Fullscreen on the primary monitor at its current video mode
+    pub fullscreen: bool,
+    /// Live FPS and GPU time in the window title
+    pub fps_in_title: bool,
+    /// Image file of the window icon, None keeps the platform default
+    pub icon_path: Option<String>,
+    /// None lets the device profile decide
+    pub vsync: Option<bool>,
+    pub anti_aliasing: AntiAliasing,
+    /// Samples per pixel, 1 disables MSAA
+    pub msaa: u32,
+    /// In world units, None draws everything
+    pub render_distance: Option<f32>,
+    pub gpu: Option<GpuSelector>,
+    /// Overrides the profile detected from the selected GPU
+    pub device_profile: Option<DeviceProfile>,
+    /// See `RendererConfig::sort_draws`
+    pub sort_draws: bool,
+
+    // command line only
+    pub mode: Mode,
+    /// Game seconds per video second of `Mode::TimeLapse`
+    pub time_lapse_speed: f64,
+    /// Logs the usable GPUs after the device is created
+    pub list_gpus: bool,
+    /// See `DebugConfig`
+    pub best_practices: bool,
+    pub gpu_validation: bool,
+    /// Validation errors end the process, for automated runs
+    pub strict_validation: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 600,
+            fullscreen: true,
+            fps_in_title: true,
+            icon_path: Some("img.png".to_string()),
+            vsync: None,
+            anti_aliasing: AntiAliasing::None,
+            msaa: 1,
+            render_distance: None,
+            gpu: None,
+            device_profile: None,
+            sort_draws: false,
+            mode: Mode::Window,
+            time_lapse_speed: 60.0,
+            list_gpus: false,
+            best_practices: false,
+            gpu_validation: false,
+            strict_validation: false,
+        }
+    }
+}
+
+/// Layout of the config file, every key is optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    window: WindowTable,
+    renderer: RendererTable,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct WindowTable {
+    width: Option<u32>,
+    height: Option<u32>,
+    fullscreen: Option<bool>,
+    fps_in_title: Option<bool>,
+    /// Empty for no icon
+    icon_path: Option<String>,
+}
+
+/// String values are parsed as their command line arguments
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RendererTable {
+    vsync: Option<bool>,
+    anti_aliasing: Option<String>,
+    msaa: Option<u32>,
+    render_distance: Option<f32>,
+    gpu: Option<String>,
+    profile: Option<String>,
+    sort_draws: Option<bool>,
+}
+
+impl Config {
+    /// Defaults overridden by the TOML file at `path`, see `ConfigFile`. A missing file means defaults,
+    /// a file that doesn't parse is reported and ignored.
+    pub fn load(path: &str) -> Self {
+        let mut config = Self::default();
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => return config,
+        };
+        let file: ConfigFile = match toml::from_str(&contents) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("Ignoring {}: {}", path, e);
+                return config;
+            }
+        };
+
+        let WindowTable { width, height, fullscreen, fps_in_title, icon_path } = file.window;
+        config.width = width.unwrap_or(config.width);
+        config.height = height.unwrap_or(config.height);
+        config.fullscreen = fullscreen.unwrap_or(config.fullscreen);
+        config.fps_in_title = fps_in_title.unwrap_or(config.fps_in_title);
+        if let Some(icon_path) = icon_path {
+            config.icon_path = (!icon_path.is_empty()).then_some(icon_path);
+        }
+
+        let RendererTable { vsync, anti_aliasing, msaa, render_distance, gpu, profile, sort_draws } = file.renderer;
+        config.vsync = vsync.or(config.vsync);
+        config.msaa = msaa.unwrap_or(config.msaa);
+        config.render_distance = render_distance.or(config.render_distance);
+        config.sort_draws = sort_draws.unwrap_or(config.sort_draws);
+        for (key, value) in [("anti_aliasing", anti_aliasing), ("gpu", gpu), ("profile", profile)] {
+            if let Some(value) = value {
+                if config.set(key, &value).is_none() {
+                    warn!("Ignoring renderer.{} = \"{}\" in {}", key, value, path);
+                }
+            }
+        }
+        config
+    }
+
+    /// `--width`, `--height`, `--gpu`, `--profile`, `--anti-aliasing`, `--msaa`, `--render-distance`, `--time-lapse` and `--time-lapse-speed` followed by a value,
+    /// and the flags `--fullscreen`, `--windowed`, `--vsync`, `--no-vsync`, `--fxaa`, `--sort-draws`, `--self-test`, `--headless`,
+    /// `--list-gpus`, `--best-practices`, `--gpu-validation` and `--strict-validation`. Other arguments are skipped.
+    pub fn apply_args(&mut self, args: &[String]) {
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--fullscreen" => self.fullscreen = true,
+                "--windowed" => self.fullscreen = false,
+                "--vsync" => self.vsync = Some(true),
+                "--no-vsync" => self.vsync = Some(false),
+                "--fxaa" => self.anti_aliasing = AntiAliasing::Fxaa,
+                "--sort-draws" => self.sort_draws = true,
+                "--self-test" => self.mode = Mode::SelfTest,
+                "--headless" => self.mode = Mode::Headless,
+                "--list-gpus" => self.list_gpus = true,
+                "--best-practices" => self.best_practices = true,
+                "--gpu-validation" => self.gpu_validation = true,
+                "--strict-validation" => self.strict_validation = true,
+                "--width" | "--height" | "--gpu" | "--profile" | "--anti-aliasing" | "--msaa" | "--render-distance" | "--time-lapse" | "--time-lapse-speed" => {
+                    let key = arg.trim_start_matches("--").replace('-', "_");
+                    match args.next() {
+                        Some(value) => if self.set(&key, value).is_none() {
+                            warn!("Ignoring {} {}", arg, value);
+                        },
+                        None => warn!("{} expects a value", arg),
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// Parses the value of an argument, `auto` resets the optional settings. None if the key is unknown or the value doesn't parse.
+    fn set(&mut self, key: &str, value: &str) -> Option<()> {
+        match key {
+            "width" => self.width = value.parse().ok()?,
+            "height" => self.height = value.parse().ok()?,
+            "time_lapse" => self.mode = Mode::TimeLapse { frame_count: value.parse().ok()? },
+            "time_lapse_speed" => self.time_lapse_speed = value.parse().ok()?,
+            "msaa" => self.msaa = value.parse().ok()?,
+            "render_distance" => self.render_distance = match value {
+                "auto" => None,
+                _ => Some(value.parse().ok()?),
+            },
+            "anti_aliasing" => self.anti_aliasing = match value {
+                "none" => AntiAliasing::None,
+                "fxaa" => AntiAliasing::Fxaa,
+                _ => return None,
+            },
+            "gpu" => self.gpu = match value {
+                "auto" => None,
+                _ => Some(GpuSelector::parse(value)),
+            },
+            "profile" => self.device_profile = match value {
+                "auto" => None,
+                "desktop" => Some(DeviceProfile::Desktop),
+                "handheld" => Some(DeviceProfile::Handheld),
+                "integrated" => Some(DeviceProfile::Integrated),
+                _ => return None,
+            },
+            _ => return None,
+        }
+        Some(())
+    }
+}
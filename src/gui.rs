@@ -0,0 +1,171 @@
+use std::time::Instant;
+
+use egui::{Event, Modifiers, PointerButton, Pos2, RawInput, Rect, Vec2};
+
+use crate::vulkanapp::GuiOutput;
+
+/// Points scrolled per wheel step
+const SCROLL_POINTS: f32 = 50.0;
+
+/// egui context fed with GLFW input. The window needs key, char, cursor position, mouse button and scroll polling.
+pub struct Gui {
+    context: egui::Context,
+    input: RawInput,
+    modifiers: Modifiers,
+    start: Instant,
+}
+
+impl Gui {
+    pub fn new() -> Self {
+        Self {
+            context: egui::Context::default(),
+            input: RawInput::default(),
+            modifiers: Modifiers::default(),
+            start: Instant::now(),
+        }
+    }
+
+    /// Queues `event` for the next `run`. Returns true if egui uses it, the application should ignore it then.
+    pub fn handle_event(&mut self, window: &glfw::Window, event: &glfw::WindowEvent) -> bool {
+        use glfw::WindowEvent;
+
+        match *event {
+            WindowEvent::CursorPos(x, y) => {
+                self.input.events.push(Event::PointerMoved(cursor_pos(window, x, y, self.context.pixels_per_point())));
+                self.context.wants_pointer_input()
+            },
+            WindowEvent::CursorEnter(false) => {
+                self.input.events.push(Event::PointerGone);
+                false
+            },
+            WindowEvent::MouseButton(button, action, mods) => {
+                let button = match button {
+                    glfw::MouseButtonLeft => PointerButton::Primary,
+                    glfw::MouseButtonRight => PointerButton::Secondary,
+                    glfw::MouseButtonMiddle => PointerButton::Middle,
+                    _ => return false,
+                };
+                self.modifiers = modifiers(mods);
+                let (x, y) = window.get_cursor_pos();
+                self.input.events.push(Event::PointerButton {
+                    pos: cursor_pos(window, x, y, self.context.pixels_per_point()),
+                    button,
+                    pressed: action == glfw::Action::Press,
+                    modifiers: self.modifiers,
+                });
+                self.context.wants_pointer_input()
+            },
+            WindowEvent::Scroll(x, y) => {
+                self.input.events.push(Event::Scroll(Vec2::new(x as f32, y as f32) * SCROLL_POINTS));
+                self.context.wants_pointer_input()
+            },
+            WindowEvent::Char(c) => {
+                // control characters come through `Key`
+                if !c.is_control() {
+                    self.input.events.push(Event::Text(c.to_string()));
+                }
+                self.context.wants_keyboard_input()
+            },
+            WindowEvent::Key(key, _, action, mods) => {
+                self.modifiers = modifiers(mods);
+                let pressed = action != glfw::Action::Release;
+                if pressed && self.modifiers.command {
+                    match key {
+                        glfw::Key::C => self.input.events.push(Event::Copy),
+                        glfw::Key::X => self.input.events.push(Event::Cut),
+                        glfw::Key::V => if let Some(text) = window.get_clipboard_string() {
+                            self.input.events.push(Event::Paste(text));
+                        },
+                        _ => {},
+                    }
+                }
+                if let Some(key) = egui_key(key) {
+                    self.input.events.push(Event::Key {
+                        key,
+                        pressed,
+                        repeat: action == glfw::Action::Repeat,
+                        modifiers: self.modifiers,
+                    });
+                }
+                self.context.wants_keyboard_input()
+            },
+            WindowEvent::Focus(focused) => {
+                self.input.focused = focused;
+                false
+            },
+            _ => false,
+        }
+    }
+
+    /// Runs `ui` on the queued input and tessellates the result for `Frame::set_gui`.
+    /// Must run every frame, and every output must reach a drawn frame, or texture updates are lost.
+    pub fn run(&mut self, window: &mut glfw::Window, ui: impl FnOnce(&egui::Context)) -> GuiOutput {
+        let (scale, _) = window.get_content_scale();
+        let (width, height) = window.get_framebuffer_size();
+        let mut input = std::mem::take(&mut self.input);
+        input.pixels_per_point = Some(scale);
+        input.screen_rect = Some(Rect::from_min_size(Pos2::ZERO, Vec2::new(width as f32, height as f32) / scale));
+        input.time = Some(self.start.elapsed().as_secs_f64());
+        input.modifiers = self.modifiers;
+        self.input.focused = input.focused;
+
+        let output = self.context.run(input, ui);
+        if !output.platform_output.copied_text.is_empty() {
+            window.set_clipboard_string(&output.platform_output.copied_text);
+        }
+        GuiOutput {
+            primitives: self.context.tessellate(output.shapes),
+            textures_delta: output.textures_delta,
+            pixels_per_point: self.context.pixels_per_point(),
+        }
+    }
+}
+
+/// Cursor position in screen coordinates to egui points
+fn cursor_pos(window: &glfw::Window, x: f64, y: f64, pixels_per_point: f32) -> Pos2 {
+    let (window_w, window_h) = window.get_size();
+    let (fb_w, fb_h) = window.get_framebuffer_size();
+    let x = x * fb_w as f64 / window_w as f64;
+    let y = y * fb_h as f64 / window_h as f64;
+    Pos2::new(x as f32 / pixels_per_point, y as f32 / pixels_per_point)
+}
+
+fn modifiers(mods: glfw::Modifiers) -> Modifiers {
+    let ctrl = mods.contains(glfw::Modifiers::Control);
+    let mac_cmd = cfg!(target_os = "macos") && mods.contains(glfw::Modifiers::Super);
+    Modifiers {
+        alt: mods.contains(glfw::Modifiers::Alt),
+        ctrl,
+        shift: mods.contains(glfw::Modifiers::Shift),
+        mac_cmd,
+        command: if cfg!(target_os = "macos") { mac_cmd } else { ctrl },
+    }
+}
+
+/// Keys egui uses for navigation and text editing
+fn egui_key(key: glfw::Key) -> Option<egui::Key> {
+    use glfw::Key as G;
+    use egui::Key as E;
+
+    Some(match key {
+        G::Down => E::ArrowDown,
+        G::Left => E::ArrowLeft,
+        G::Right => E::ArrowRight,
+        G::Up => E::ArrowUp,
+        G::Escape => E::Escape,
+        G::Tab => E::Tab,
+        G::Backspace => E::Backspace,
+        G::Enter | G::KpEnter => E::Enter,
+        G::Space => E::Space,
+        G::Insert => E::Insert,
+        G::Delete => E::Delete,
+        G::Home => E::Home,
+        G::End => E::End,
+        G::PageUp => E::PageUp,
+        G::PageDown => E::PageDown,
+        G::A => E::A,
+        G::Z => E::Z,
+        G::Y => E::Y,
+        _ => return None,
+    })
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use glfw::{Key, MouseButton};
+use log::warn;
+use serde::Deserialize;
+
+/// Named things the user can do, bound to keys and mouse buttons by `KeyBindings`.
+/// Deserialized from `Action::name`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Pick,
+    /// Quits while playing, leaves pause and calibration otherwise
+    Back,
+    TogglePause,
+    Calibrate,
+    ExposureUp,
+    ExposureDown,
+    GammaUp,
+    GammaDown,
+    SlowDown,
+    SpeedUp,
+    CycleGpuPreference,
+    CyclePowerSaving,
+    CycleFrameRateCap,
+    CycleColorFilter,
+    CycleTonemapper,
+    CycleDebugView,
+    ReloadShaders,
+    ToggleHud,
+    ToggleSettings,
+    ToggleBounds,
+}
+
+impl Action {
+    pub const ALL: [Action; 23] = [
+        Action::MoveLeft, Action::MoveRight, Action::Jump, Action::Pick, Action::Back, Action::TogglePause,
+        Action::Calibrate, Action::ExposureUp, Action::ExposureDown, Action::GammaUp, Action::GammaDown,
+        Action::SlowDown, Action::SpeedUp, Action::CycleGpuPreference, Action::CyclePowerSaving,
+        Action::CycleFrameRateCap, Action::CycleColorFilter, Action::CycleTonemapper, Action::CycleDebugView,
+        Action::ReloadShaders, Action::ToggleHud, Action::ToggleSettings, Action::ToggleBounds,
+    ];
+
+    /// Name in the bindings file
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::Jump => "jump",
+            Action::Pick => "pick",
+            Action::Back => "back",
+            Action::TogglePause => "toggle_pause",
+            Action::Calibrate => "calibrate",
+            Action::ExposureUp => "exposure_up",
+            Action::ExposureDown => "exposure_down",
+            Action::GammaUp => "gamma_up",
+            Action::GammaDown => "gamma_down",
+            Action::SlowDown => "slow_down",
+            Action::SpeedUp => "speed_up",
+            Action::CycleGpuPreference => "cycle_gpu_preference",
+            Action::CyclePowerSaving => "cycle_power_saving",
+            Action::CycleFrameRateCap => "cycle_frame_rate_cap",
+            Action::CycleColorFilter => "cycle_color_filter",
+            Action::CycleTonemapper => "cycle_tonemapper",
+            Action::CycleDebugView => "cycle_debug_view",
+            Action::ReloadShaders => "reload_shaders",
+            Action::ToggleHud => "toggle_hud",
+            Action::ToggleSettings => "toggle_settings",
+            Action::ToggleBounds => "toggle_bounds",
+        }
+    }
+
+    fn default_bindings(self) -> Vec<Binding> {
+        use Binding::Key as K;
+
+        match self {
+            Action::MoveLeft => vec![K(Key::A)],
+            Action::MoveRight => vec![K(Key::D)],
+            Action::Jump => vec![K(Key::W), K(Key::Space)],
+            Action::Pick => vec![Binding::Mouse(glfw::MouseButtonLeft)],
+            Action::Back => vec![K(Key::Escape)],
+            Action::TogglePause => vec![K(Key::P)],
+            Action::Calibrate => vec![K(Key::C)],
+            Action::ExposureUp => vec![K(Key::Up)],
+            Action::ExposureDown => vec![K(Key::Down)],
+            Action::GammaUp => vec![K(Key::Right)],
+            Action::GammaDown => vec![K(Key::Left)],
+            Action::SlowDown => vec![K(Key::LeftBracket)],
+            Action::SpeedUp => vec![K(Key::RightBracket)],
+            Action::CycleGpuPreference => vec![K(Key::G)],
+            Action::CyclePowerSaving => vec![K(Key::B)],
+            Action::CycleFrameRateCap => vec![K(Key::F)],
+            Action::CycleColorFilter => vec![K(Key::V)],
+            Action::CycleTonemapper => vec![K(Key::T)],
+            Action::CycleDebugView => vec![K(Key::F3)],
+            Action::ReloadShaders => vec![K(Key::F5)],
+            Action::ToggleHud => vec![K(Key::F1)],
+            Action::ToggleSettings => vec![K(Key::F2)],
+            Action::ToggleBounds => vec![K(Key::F4)],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Key(Key),
+    Mouse(MouseButton),
+}
+
+impl Binding {
+    /// Names as in `KEY_NAMES`, plus `MouseLeft`, `MouseRight` and `MouseMiddle`
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "MouseLeft" => Some(Binding::Mouse(glfw::MouseButtonLeft)),
+            "MouseRight" => Some(Binding::Mouse(glfw::MouseButtonRight)),
+            "MouseMiddle" => Some(Binding::Mouse(glfw::MouseButtonMiddle)),
+            _ => KEY_NAMES.iter().find(|(n, _)| *n == name).map(|(_, key)| Binding::Key(*key)),
+        }
+    }
+}
+
+/// Key names accepted in the bindings file
+const KEY_NAMES: [(&str, Key); 66] = [
+    ("A", Key::A), ("B", Key::B), ("C", Key::C), ("D", Key::D), ("E", Key::E), ("F", Key::F), ("G", Key::G),
+    ("H", Key::H), ("I", Key::I), ("J", Key::J), ("K", Key::K), ("L", Key::L), ("M", Key::M), ("N", Key::N),
+    ("O", Key::O), ("P", Key::P), ("Q", Key::Q), ("R", Key::R), ("S", Key::S), ("T", Key::T), ("U", Key::U),
+    ("V", Key::V), ("W", Key::W), ("X", Key::X), ("Y", Key::Y), ("Z", Key::Z),
+    ("0", Key::Num0), ("1", Key::Num1), ("2", Key::Num2), ("3", Key::Num3), ("4", Key::Num4),
+    ("5", Key::Num5), ("6", Key::Num6), ("7", Key::Num7), ("8", Key::Num8), ("9", Key::Num9),
+    ("F1", Key::F1), ("F2", Key::F2), ("F3", Key::F3), ("F4", Key::F4), ("F5", Key::F5), ("F6", Key::F6),
+    ("F7", Key::F7), ("F8", Key::F8), ("F9", Key::F9), ("F10", Key::F10), ("F11", Key::F11), ("F12", Key::F12),
+    ("Up", Key::Up), ("Down", Key::Down), ("Left", Key::Left), ("Right", Key::Right),
+    ("Space", Key::Space), ("Escape", Key::Escape), ("Enter", Key::Enter), ("Tab", Key::Tab),
+    ("Backspace", Key::Backspace), ("LeftShift", Key::LeftShift), ("LeftControl", Key::LeftControl),
+    ("LeftAlt", Key::LeftAlt), ("LeftBracket", Key::LeftBracket), ("RightBracket", Key::RightBracket),
+    ("Minus", Key::Minus), ("Equal", Key::Equal), ("Comma", Key::Comma), ("Period", Key::Period),
+];
+
+/// Value of an action in the bindings file
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum BindingNames {
+    One(String),
+    List(Vec<String>),
+}
+
+/// Maps keys and mouse buttons to actions, an input may trigger several actions
+pub struct KeyBindings {
+    bindings: Vec<(Binding, Action)>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            bindings: Action::ALL.iter()
+                .flat_map(|action| action.default_bindings().into_iter().map(move |binding| (binding, *action)))
+                .collect(),
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Defaults overridden by the TOML file at `path`, which maps action names to a key name or a list of them.
+    /// An empty list unbinds the action. A missing file means defaults, a file that doesn't parse is reported and ignored.
+    pub fn load(path: &str) -> Self {
+        let mut bindings = Self::default();
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => return bindings,
+        };
+        let file: HashMap<Action, BindingNames> = match toml::from_str(&contents) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("Ignoring {}: {}", path, e);
+                return bindings;
+            }
+        };
+
+        for (action, names) in file {
+            let names = match names {
+                BindingNames::One(name) => vec![name],
+                BindingNames::List(names) => names,
+            };
+            let parsed: Option<Vec<Binding>> = names.iter().map(|name| Binding::parse(name)).collect();
+            match parsed {
+                Some(parsed) => bindings.bind(action, &parsed),
+                None => warn!("Ignoring {} = {:?} in {}, unknown key", action.name(), names, path),
+            }
+        }
+        bindings
+    }
+
+    /// Replaces all bindings of `action`
+    pub fn bind(&mut self, action: Action, bindings: &[Binding]) {
+        self.bindings.retain(|(_, a)| *a != action);
+        self.bindings.extend(bindings.iter().map(|binding| (*binding, action)));
+    }
+
+    pub fn actions(&self, binding: Binding) -> impl Iterator<Item = Action> + '_ {
+        self.bindings.iter().filter(move |(b, _)| *b == binding).map(|(_, action)| *action)
+    }
+}
@@ -1,115 +1,820 @@
 mod vulkanapp;
-use vulkanapp::VulkanApp;
-
+mod app_window;
+mod time;
+mod self_test;
+mod app_state;
+mod power_saving;
+mod time_of_day;
+mod player;
+mod input;
+mod config;
+#[cfg(feature = "egui")]
+mod gui;
+use app_window::WindowConfig;
+use vulkanapp::{VulkanApp, GpuPreference, RendererConfig, DisplayColorSpace, FrameUniforms, DisplayCalibration, ColorFilter, Tonemapper, DebugConfig, DebugMessage, DebugCallback, DebugView, DebugDraw, TextDraw, FrameTimingReport, EntityId, CpuStageReport, ScopeReport, Frame, Mesh, Sprite, Transform2D, Camera2D, Material, MaterialParams, MaterialId, MeshData, MeshHandle, Vertex, Scene, NodeId, Drawable, BatchStats, HeapStats};
+use ash::vk;
+use glam::{Vec2, Vec3, Vec4};
+use std::sync::Arc;
 use std::time::Instant;
 
+use time::{Time, AnimationClock};
+use app_state::{AppState, StateMachine};
+use power_saving::{PowerSaving, PowerSavingMode};
+use time_of_day::TimeOfDay;
+use player::{Aabb, Player, PlayerInput};
+use input::{Action, Binding, KeyBindings};
+use config::{Config, Mode};
+
 use glfw;
+use log::{debug, error, info, warn};
+
+/// Startup settings, `--config <path>` loads another file
+const CONFIG_PATH: &str = "app.toml";
+/// Exposure and gamma, see `DisplayCalibration::save`
+const CALIBRATION_PATH: &str = "calibration.toml";
+/// Overrides of the default key bindings, see `KeyBindings::load`
+const BINDINGS_PATH: &str = "bindings.toml";
+
+/// Game seconds per day/night cycle, and the hour the app starts at
+const DAY_LENGTH: f64 = 120.0;
+const START_HOUR: f64 = 8.0;
+
+/// Color of the bounding boxes drawn by `draw_bounds`
+const BOUNDS_COLOR: [f32; 4] = [1.0, 1.0, 0.0, 1.0];
+
+/// Color of the outline around the object under the crosshair
+const TARGET_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// Bounding box of a triangle of `vertex_data`
+fn triangle_bounds(triangle: &[Vertex]) -> Aabb {
+    let (mut min, mut max) = (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN));
+    for vertex in triangle {
+        min = min.min(vertex.position.truncate());
+        max = max.max(vertex.position.truncate());
+    }
+    Aabb { min: min.to_array(), max: max.to_array() }
+}
+
+/// Outlines the bounding box of every triangle of `vertex_data`
+fn draw_bounds(debug_draw: &mut DebugDraw, vertex_data: &[Vertex]) {
+    for triangle in vertex_data.chunks_exact(3) {
+        let bounds = triangle_bounds(triangle);
+        debug_draw.aabb(bounds.min, bounds.max, BOUNDS_COLOR);
+    }
+}
+
+/// Outlines the triangle picked as `target`, entities are numbered as in `scene_frame`
+fn draw_target(debug_draw: &mut DebugDraw, vertex_data: &[Vertex], target: EntityId) {
+    if let Some(triangle) = (target.0 as usize).checked_sub(1).and_then(|i| vertex_data.chunks_exact(3).nth(i)) {
+        let bounds = triangle_bounds(triangle);
+        debug_draw.aabb(bounds.min, bounds.max, TARGET_COLOR);
+    }
+}
+
+/// Floor below the scene, the player can't fall out of the world
+const GROUND: Aabb = Aabb { min: [-100.0, 1.0], max: [100.0, 2.0] };
+/// Side of a ground checker square in world units, and its two sRGB colors
+const GROUND_TILE: f32 = 0.25;
+const GROUND_COLORS: [[u8; 4]; 2] = [[110, 84, 60, 255], [92, 68, 48, 255]];
+
+/// Where the windmill stands on the ground, its tower height and how fast the rotor turns in radians per second
+const WINDMILL_POSITION: Vec2 = Vec2::new(0.6, 1.0);
+const WINDMILL_HEIGHT: f32 = 0.5;
+const WINDMILL_SPEED: f32 = 0.8;
+const WINDMILL_COLOR: Vec4 = Vec4::new(0.9, 0.88, 0.8, 1.0);
+
+/// Uploaded once and drawn in every scene frame: the ground and a windmill behind the triangles,
+/// and the quad and material the player is drawn with
+struct Scenery {
+    scene: Scene,
+    rotor: NodeId,
+    quad: MeshHandle,
+    player_material: MaterialId,
+}
+
+/// Every part is the same unit quad centered on the origin, stretched by its node.
+/// The ground has a checker texture of `GROUND_TILE` squares, two by two of them repeated by the material's texture coordinate scale.
+fn create_scenery(vulkan_app: &mut VulkanApp) -> Scenery {
+    let corner = |x: f32, y: f32| Vertex { position: Vec3::new(x - 0.5, y - 0.5, 0.0), tex_coord: Vec2::new(x, y) };
+    let quad = vulkan_app.upload_mesh(&MeshData::new(
+        vec![corner(0.0, 0.0), corner(1.0, 0.0), corner(1.0, 1.0), corner(0.0, 1.0)],
+        vec![0, 1, 2, 0, 2, 3],
+    ));
+
+    const SIZE: u32 = 16;
+    let pixels = (0..SIZE * SIZE)
+        .flat_map(|i| GROUND_COLORS[((i % SIZE < SIZE / 2) != (i / SIZE < SIZE / 2)) as usize])
+        .collect();
+    let texture = vulkan_app.create_texture(SIZE, SIZE, pixels);
+    let (ground_min, ground_max) = (Vec2::from(GROUND.min), Vec2::from(GROUND.max));
+    let size = ground_max - ground_min;
+    let ground_material = vulkan_app.create_material(Material {
+        texture,
+        params: MaterialParams {
+            uv_scale: size / (GROUND_TILE * 2.0),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let white = vulkan_app.create_texture(1, 1, vec![255; 4]);
+    let windmill_material = vulkan_app.create_material(Material {
+        texture: white,
+        params: MaterialParams { color: WINDMILL_COLOR, ..Default::default() },
+        ..Default::default()
+    });
+    let player_material = vulkan_app.create_material(Material {
+        texture: white,
+        params: MaterialParams { color: PLAYER_COLOR, ..Default::default() },
+        ..Default::default()
+    });
+
+    let mut scene = Scene::new();
+    let part = |material| Some(Drawable { mesh: quad, material, entity: None });
+    scene.add_node(None, Transform2D {
+        translation: (ground_min + ground_max) / 2.0,
+        scale: size,
+        ..Default::default()
+    }, part(ground_material));
+
+    // up is -y, the tower stands on its base and the rotor turns at its top
+    let windmill = scene.add_node(None, Transform2D { translation: WINDMILL_POSITION, ..Default::default() }, None);
+    scene.add_node(Some(windmill), Transform2D {
+        translation: Vec2::new(0.0, -WINDMILL_HEIGHT / 2.0),
+        scale: Vec2::new(0.05, WINDMILL_HEIGHT),
+        ..Default::default()
+    }, part(windmill_material));
+    let rotor = scene.add_node(Some(windmill), Transform2D { translation: Vec2::new(0.0, -WINDMILL_HEIGHT), ..Default::default() }, None);
+    for i in 0..3 {
+        let blade = scene.add_node(Some(rotor), Transform2D { rotation: i as f32 * std::f32::consts::TAU / 3.0, ..Default::default() }, None);
+        scene.add_node(Some(blade), Transform2D { translation: Vec2::new(0.15, 0.0), scale: Vec2::new(0.3, 0.04), ..Default::default() }, part(windmill_material));
+    }
+    Scenery { scene, rotor, quad, player_material }
+}
+
+/// Turns the rotor, its blades follow as children
+fn animate_scenery(scenery: &mut Scenery, game_time: f64) {
+    let mut rotor = scenery.scene.transform(scenery.rotor);
+    rotor.rotation = (game_time as f32 * WINDMILL_SPEED) % std::f32::consts::TAU;
+    scenery.scene.set_transform(scenery.rotor, rotor);
+}
+
+/// Where the player starts, left of the triangles
+const PLAYER_START: [f32; 2] = [-0.8, 0.0];
+const PLAYER_COLOR: Vec4 = Vec4::new(0.2, 1.0, 0.4, 1.0);
+
+/// Blocks the player collides with, the ground and the bounding box of every triangle of `vertex_data`
+fn solid_blocks(vertex_data: &[Vertex]) -> Vec<Aabb> {
+    std::iter::once(GROUND)
+        .chain(vertex_data.chunks_exact(3).map(triangle_bounds))
+        .collect()
+}
+
+/// Position, glyph scale and color of the HUD text
+const HUD_POSITION: [f32; 2] = [8.0, 8.0];
+const HUD_SCALE: u32 = 2;
+const HUD_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.9];
+
+/// Crosshair arm length and thickness in pixels
+const CROSSHAIR_SIZE: f32 = 10.0;
+const CROSSHAIR_THICKNESS: f32 = 2.0;
+const CROSSHAIR_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.8];
+
+/// Cross at `center` in framebuffer pixels
+fn draw_crosshair(hud: &mut TextDraw, center: (u32, u32)) {
+    let (x, y) = (center.0 as f32, center.1 as f32);
+    let half = CROSSHAIR_THICKNESS / 2.0;
+    hud.rect([x - CROSSHAIR_SIZE, y - half], [x + CROSSHAIR_SIZE, y + half], CROSSHAIR_COLOR);
+    hud.rect([x - half, y - CROSSHAIR_SIZE], [x + half, y - half], CROSSHAIR_COLOR);
+    hud.rect([x - half, y + half], [x + half, y + CROSSHAIR_SIZE], CROSSHAIR_COLOR);
+}
+
+/// Renderer statistics shown by `draw_hud`
+struct HudStats<'a> {
+    /// Frames of the last second
+    fps: u32,
+    timing: FrameTimingReport,
+    /// Updated with `timing`
+    cpu_stages: &'a [CpuStageReport],
+    gpu_scopes: &'a [ScopeReport],
+    batches: BatchStats,
+    memory: Vec<HeapStats>,
+    power_saving: &'a PowerSaving,
+}
+
+/// Frame rate and timings of the last second with the CPU stage bar and GPU scopes, power saving, camera, time of day,
+/// scene size, draw batching and VRAM usage
+fn draw_hud(hud: &mut TextDraw, stats: &HudStats, camera: &Camera2D, time_of_day: &TimeOfDay, vertex_data: &[Vertex]) {
+    let HudStats { fps, timing, cpu_stages, gpu_scopes, batches, memory, power_saving } = stats;
+    let gpu_ms = timing.gpu_ms.map_or("-".to_string(), |ms| format!("{:.2}", ms));
+    let hour = time_of_day.hour();
+    let (vram_allocated, vram_used, vram_budget) = memory.iter().filter(|heap| heap.device_local)
+        .fold((0, 0, 0), |(allocated, used, budget), heap| (allocated + heap.allocated, used + heap.used, budget + heap.budget));
+    let cpu_stage_legend: Vec<_> = cpu_stages.iter().enumerate()
+        .map(|(i, stage)| format!("{} {} {:.2}ms", CPU_STAGE_GLYPHS[i % CPU_STAGE_GLYPHS.len()], stage.name, stage.avg_ms))
+        .collect();
+    let gpu_scope_times: Vec<_> = gpu_scopes.iter().map(|scope| format!("{} {:.2}ms", scope.name, scope.avg_ms)).collect();
+    let text = format!("FPS {}  power saving {} ({:?})\nframe {:.2}ms  cpu {:.2}ms  gpu {}ms\ncpu [{}]\n{}\ngpu {}\ncamera ({:.2}, {:.2})  zoom {:.2}\ntime {:02}:{:02}  light {:.2}\nobjects {}  vertices {}\ndraws {}  batches {}  pipelines {}  materials {}\nvram {} / {} MiB  renderer {} MiB",
+        fps, if power_saving.is_active() { "on" } else { "off" }, power_saving.mode(), timing.frame_ms, timing.cpu_ms, gpu_ms,
+        cpu_stage_bar(cpu_stages), cpu_stage_legend.join("  "), gpu_scope_times.join("  "),
+        camera.position.x, camera.position.y, camera.zoom,
+        hour as u32, (hour.fract() * 60.0) as u32, time_of_day.light_intensity(),
+        vertex_data.len() / 3, vertex_data.len(),
+        batches.draws, batches.batches, batches.pipeline_binds, batches.material_binds,
+        vram_used >> 20, vram_budget >> 20, vram_allocated >> 20);
+    hud.text(HUD_POSITION, HUD_SCALE, HUD_COLOR, &text);
+}
+
+/// Combo box over `options`, labeled with their Debug names
+#[cfg(feature = "egui")]
+fn combo<T: Copy + PartialEq + std::fmt::Debug>(ui: &mut egui::Ui, label: &str, value: &mut T, options: &[T]) {
+    egui::ComboBox::from_label(label)
+        .selected_text(format!("{:?}", value))
+        .show_ui(ui, |ui| {
+            for option in options {
+                ui.selectable_value(value, *option, format!("{:?}", option));
+            }
+        });
+}
+
+/// Renderer and simulation settings that can change at runtime
+#[cfg(feature = "egui")]
+fn settings_window(ctx: &egui::Context, vulkan_app: &mut VulkanApp, time: &mut Time, show_hud: &mut bool, show_bounds: &mut bool) {
+    egui::Window::new("Settings").show(ctx, |ui| {
+        let mut tonemapper = vulkan_app.tonemapper();
+        combo(ui, "Tonemapper", &mut tonemapper, &Tonemapper::ALL);
+        vulkan_app.set_tonemapper(tonemapper);
+
+        let mut color_filter = vulkan_app.color_filter();
+        combo(ui, "Color filter", &mut color_filter, &ColorFilter::ALL);
+        vulkan_app.set_color_filter(color_filter);
+
+        let mut debug_view = vulkan_app.debug_view();
+        combo(ui, "Debug view", &mut debug_view, &DebugView::ALL);
+        if debug_view != vulkan_app.debug_view() {
+            vulkan_app.set_debug_view(debug_view);
+        }
+
+        let mut calibration = vulkan_app.display_calibration();
+        let (min, max) = DisplayCalibration::EXPOSURE_RANGE;
+        ui.add(egui::Slider::new(&mut calibration.exposure, min..=max).text("Exposure"));
+        let (min, max) = DisplayCalibration::GAMMA_RANGE;
+        ui.add(egui::Slider::new(&mut calibration.gamma, min..=max).text("Gamma"));
+        vulkan_app.set_display_calibration(calibration);
+
+        ui.separator();
+        let mut cap = vulkan_app.frame_rate_cap();
+        let mut capped = cap.is_some();
+        ui.checkbox(&mut capped, "Frame rate cap");
+        let mut fps = cap.unwrap_or(60.0);
+        ui.add_enabled(capped, egui::Slider::new(&mut fps, 15.0..=240.0).text("FPS"));
+        cap = capped.then_some(fps);
+        if cap != vulkan_app.frame_rate_cap() {
+            vulkan_app.set_frame_rate_cap(cap);
+        }
+
+        let mut scale = time.scale();
+        ui.add(egui::Slider::new(&mut scale, 0.0..=8.0).logarithmic(true).text("Time scale"));
+        time.set_scale(scale);
+
+        ui.separator();
+        ui.checkbox(show_hud, "HUD");
+        ui.checkbox(show_bounds, "Bounding boxes");
+    });
+}
+
+/// Every triangle of `vertex_data` is a separate pickable object, drawn in front of the sky and the ground
+fn scene_frame(vertex_data: &[Vertex], scenery: &Scenery, camera: Camera2D, time_of_day: &TimeOfDay) -> Frame {
+    let mut frame = Frame::new();
+    frame.set_camera(camera);
+    frame.set_clear_color(time_of_day.sky_color());
+    scenery.scene.draw(&mut frame);
+    for (i, triangle) in vertex_data.chunks(3).enumerate() {
+        frame.draw_mesh(&Mesh::new(triangle.to_vec()), MaterialId::DEFAULT, Transform2D::default(), Some(EntityId(i as u32 + 1)));
+    }
+    frame
+}
+
+/// Width of the text bar that shows each stage's share of the frame's CPU time
+const CPU_STAGE_BAR_WIDTH: usize = 60;
+
+/// Fill characters of the stacked bar, by stage index
+const CPU_STAGE_GLYPHS: [char; 8] = ['#', '=', '+', '-', '*', '~', ':', '.'];
+
+/// Stacked bar of the CPU stages, each stage is drawn with its glyph from `CPU_STAGE_GLYPHS`
+fn cpu_stage_bar(stages: &[CpuStageReport]) -> String {
+    let total: f64 = stages.iter().map(|s| s.avg_ms).sum();
+    if total <= 0.0 {
+        return " ".repeat(CPU_STAGE_BAR_WIDTH);
+    }
+    let mut bar = String::with_capacity(CPU_STAGE_BAR_WIDTH);
+    for (i, stage) in stages.iter().enumerate() {
+        let width = (stage.avg_ms / total * CPU_STAGE_BAR_WIDTH as f64).round() as usize;
+        bar.extend(std::iter::repeat_n(CPU_STAGE_GLYPHS[i % CPU_STAGE_GLYPHS.len()], width));
+    }
+    bar
+}
+
+/// Fullscreen quad with texture coordinates over the whole screen, drawn while the calibration pattern is shown
+fn calibration_frame() -> Frame {
+    let mut frame = Frame::new();
+    frame.draw_sprite(&Sprite { size: Vec2::splat(2.0), ..Default::default() }, MaterialId::DEFAULT, None);
+    frame
+}
+
+/// Renderer settings of every run mode, from the startup config
+fn renderer_config(config: &Config) -> RendererConfig {
+    RendererConfig {
+        // triple buffering
+        swapchain_image_count: Some(3),
+        vsync: config.vsync,
+        device_profile: config.device_profile,
+        color_space: DisplayColorSpace::Sdr,
+        calibration: DisplayCalibration::load(CALIBRATION_PATH).unwrap_or_default(),
+        color_filter: ColorFilter::None,
+        tonemapper: Tonemapper::Aces,
+        post_process: Vec::new(),
+        anti_aliasing: config.anti_aliasing,
+        msaa: config.msaa,
+        render_distance: config.render_distance,
+        debug_view: DebugView::Shaded,
+        gpu: config.gpu.clone(),
+        sort_draws: config.sort_draws,
+        debug: DebugConfig {
+            best_practices: config.best_practices,
+            gpu_assisted: config.gpu_validation,
+            callback: config.strict_validation.then(|| {
+                // validation errors are fatal, useful for automated runs
+                let callback: DebugCallback = Arc::new(|msg: &DebugMessage| {
+                    if msg.severity == vk::DebugUtilsMessageSeverityFlagsEXT::ERROR {
+                        error!(target: "validation", "{:?} {} ({}): {}", msg.message_type, msg.id_name, msg.id_number, msg.message);
+                        std::process::exit(1);
+                    }
+                    false
+                });
+                callback
+            }),
+            ..Default::default()
+        },
+    }
+}
 
-const WIDTH: u32 = 800;
-const HEIGHT: u32 = 600;
 const TITLE: &str = "Hello... Vulkan?";
 
+/// Renders a few frames without a window and saves the last one, for screenshot tests
+fn run_headless(vertex_data: &[Vertex], config: &Config) {
+    let mut vulkan_app = VulkanApp::new_headless(config.width, config.height, vertex_data, renderer_config(config));
+    let scenery = create_scenery(&mut vulkan_app);
+    for _ in 0..3 {
+        vulkan_app.draw_offscreen_frame(&scene_frame(vertex_data, &scenery, Camera2D::default(), &TimeOfDay::new(DAY_LENGTH, START_HOUR)));
+    }
+
+    let pixels = vulkan_app.read_back_frame();
+    let path = "headless.png";
+    image::save_buffer(path, &pixels, config.width, config.height, image::ColorType::Rgba8).unwrap();
+    info!("Saved offscreen frame to {}", path);
+}
+
+/// Video frame rate the time-lapse is captured for
+const TIME_LAPSE_FPS: f64 = 30.0;
+const TIME_LAPSE_DIR: &str = "time_lapse";
+
+fn animate_triangles(vertex_data: &mut [Vertex], phase: f32) {
+    vertex_data[0].position.x = f32::sin(phase) * 0.5;
+    vertex_data[0].position.y = f32::cos(phase) * 0.2 - 0.7;
+
+    vertex_data[5].tex_coord.y = f32::sin(phase) * 0.5;
+}
+
+/// Renders `frame_count` frames without a window, game time advances `config.time_lapse_speed` times faster than the
+/// video plays back, and saves them as a numbered image sequence
+fn run_time_lapse(vertex_data: &[Vertex], config: &Config, frame_count: u32) {
+    let mut vulkan_app = VulkanApp::new_headless(config.width, config.height, vertex_data, renderer_config(config));
+    let mut scenery = create_scenery(&mut vulkan_app);
+    let mut vertex_data = vertex_data.to_vec();
+    let mut time = Time::new();
+    time.set_scale(config.time_lapse_speed);
+    let mut triangle_clock = AnimationClock::new(15.0);
+    let mut time_of_day = TimeOfDay::new(DAY_LENGTH, START_HOUR);
+
+    std::fs::create_dir_all(TIME_LAPSE_DIR).unwrap();
+    for i in 0..frame_count {
+        time.step(1.0 / TIME_LAPSE_FPS);
+        triangle_clock.advance(&time);
+        time_of_day.advance(&time);
+        animate_triangles(&mut vertex_data, triangle_clock.time() as f32);
+        animate_scenery(&mut scenery, time.game_time());
+        vulkan_app.set_frame_uniforms(FrameUniforms {
+            game_time: time.game_time() as f32,
+            real_time: time.real_time() as f32,
+            delta_time: time.delta() as f32,
+            light_intensity: time_of_day.light_intensity(),
+            ..Default::default()
+        });
+
+        // a frame dropped by device loss is drawn again
+        while !vulkan_app.draw_offscreen_frame(&scene_frame(&vertex_data, &scenery, Camera2D::default(), &time_of_day)) {}
+        let pixels = vulkan_app.read_back_frame();
+        let path = format!("{}/frame_{:05}.png", TIME_LAPSE_DIR, i);
+        image::save_buffer(&path, &pixels, config.width, config.height, image::ColorType::Rgba8).unwrap();
+    }
+    info!("Saved {} frames covering {:.1}s of game time to {}/", frame_count, time.game_time(), TIME_LAPSE_DIR);
+}
+
 fn main() {
+    // RUST_LOG overrides the default level, e.g. RUST_LOG=debug or RUST_LOG=validation=error
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
     if cfg!(debug_assertions) {
-        println!("Development build");
+        info!("Development build");
     }
     else {
-        println!("Release build.");
+        info!("Release build.");
+    }
+
+    let vertex = |x: f32, y: f32, u: f32, v: f32| Vertex { position: Vec3::new(x, y, 0.0), tex_coord: Vec2::new(u, v) };
+    let mut vertex_data = vec![
+        vertex(0.0, -0.5, 1.0, 0.0),
+        vertex(0.5, 0.5, 0.0, 1.0),
+        vertex(-0.5, 0.5, 1.0, 1.0),
+
+        vertex(0.5, 0.5, 0.0, 1.0),
+        vertex(-0.5, 0.5, 1.0, 1.0),
+        vertex(0.8, 0.9, 0.0, 0.0),
+    ];
+
+    let args: Vec<String> = std::env::args().collect();
+    let config_path = args.iter().skip_while(|arg| *arg != "--config").nth(1).map_or(CONFIG_PATH, |path| path.as_str());
+    let mut config = Config::load(config_path);
+    config.apply_args(&args);
+
+    match config.mode {
+        Mode::Window => {},
+        Mode::SelfTest => {
+            let passed = self_test::run();
+            info!("Self-test {}", if passed { "passed" } else { "failed" });
+            std::process::exit(if passed { 0 } else { 1 });
+        },
+        Mode::Headless => {
+            run_headless(&vertex_data, &config);
+            return;
+        },
+        Mode::TimeLapse { frame_count } => {
+            run_time_lapse(&vertex_data, &config, frame_count);
+            return;
+        },
     }
 
     let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS).unwrap();
     glfw.window_hint(glfw::WindowHint::ClientApi(glfw::ClientApiHint::NoApi));
     if !glfw.vulkan_supported() {
-        println!("Vulkan not supported");
+        error!("Vulkan not supported");
         panic!("glfw: vulkan not supported");
     }
-    
-    let fullscreen = true;
 
-    let (mut window, events) = match fullscreen {
-        false => glfw.create_window(WIDTH, HEIGHT, TITLE, glfw::WindowMode::Windowed).unwrap(),
+    let (mut window, events) = match config.fullscreen {
+        false => glfw.create_window(config.width, config.height, TITLE, glfw::WindowMode::Windowed).unwrap(),
         true => glfw.with_primary_monitor(|glfw, m| {
             match m {
                 Some(m) => {
                     let vidmode = m.get_video_mode().unwrap();
                     let (w,h) = (vidmode.width, vidmode.height);
 
-                    println!("Monitor size: {}x{}", w, h);
+                    info!("Monitor size: {}x{}", w, h);
 
                     glfw.create_window(w, h, TITLE, glfw::WindowMode::FullScreen(m))
                 },
                 None => {
-                    println!("No monitor found");
-                    glfw.create_window(WIDTH, HEIGHT, TITLE, glfw::WindowMode::Windowed)
+                    warn!("No monitor found");
+                    glfw.create_window(config.width, config.height, TITLE, glfw::WindowMode::Windowed)
                 }
             }
         }).expect("Failed to create GLFW window")
     };
+    let window_config = WindowConfig {
+        title: TITLE,
+        fps_in_title: config.fps_in_title,
+        icon_path: config.icon_path.clone(),
+    };
+    if let Some(icon_path) = &window_config.icon_path {
+        app_window::set_window_icon(&mut window, icon_path);
+    }
+
     let (screen_width, screen_height) =  window.get_framebuffer_size();
 
 
-    println!("Screen size: {}x{}", screen_width, screen_height);
+    info!("Screen size: {}x{}", screen_width, screen_height);
     
     window.set_key_polling(true);
     window.set_framebuffer_size_polling(true);
+    window.set_mouse_button_polling(true);
+    window.set_scroll_polling(true);
+    #[cfg(feature = "egui")]
+    {
+        window.set_cursor_pos_polling(true);
+        window.set_char_polling(true);
+        window.set_cursor_enter_polling(true);
+        window.set_focus_polling(true);
+    }
 
-    let mut vertex_data = vec![
-        0.0_f32, -0.5, 0.0, 1.0, 0.0,
-        0.5, 0.5, 0.0, 0.0, 1.0,
-        -0.5, 0.5, 0.0, 1.0, 1.0,
-
-        0.5, 0.5, 0.0, 0.0, 1.0,
-        -0.5, 0.5, 0.0, 1.0, 1.0,
-        0.8, 0.9, 0.0, 0.0, 0.0,
-    ];
-    let mut vulkan_app = VulkanApp::new(&glfw, &window, &vertex_data);
+    let mut vulkan_app = VulkanApp::new(&glfw, &window, &vertex_data, renderer_config(&config));
+    if config.list_gpus {
+        for gpu in vulkan_app.list_gpus() {
+            info!("GPU {}: {} ({:?}, {} MB), score {:?}", gpu.index, gpu.name, gpu.device_type, gpu.device_local_memory_mb, gpu.score);
+        }
+    }
+    info!("Frame rate cap for the {:?} profile: {:?}", vulkan_app.device_profile(), vulkan_app.frame_rate_cap());
+    info!("Swapchain image count: {}", vulkan_app.swapchain_image_count());
+    info!("Display color space: {:?}", vulkan_app.display_color_space());
+    let mut scenery = create_scenery(&mut vulkan_app);
     
     //set window resize callback
     let mut frames = 0;
-    let start_time =  Instant::now();
+    let mut time = Time::new();
+    let mut triangle_clock = AnimationClock::new(15.0);
+    let mut time_of_day = TimeOfDay::new(DAY_LENGTH, START_HOUR);
     let mut prev_sec = 0;
+    let mut camera = Camera2D::default();
+    let mut player = Player::new(PLAYER_START);
+    let mut player_input = PlayerInput::default();
+    let key_bindings = KeyBindings::load(BINDINGS_PATH);
+    let mut show_bounds = false;
+    let mut show_hud = true;
+    // object under the crosshair, picked every frame
+    let mut target: Option<EntityId> = None;
+    // pixel clicked with Action::Pick, its pick is requested until the result lands
+    let mut pending_click: Option<(u32, u32)> = None;
+    #[cfg(feature = "egui")]
+    let mut gui = gui::Gui::new();
+    #[cfg(feature = "egui")]
+    let mut show_settings = false;
+    let mut fps = 0;
+    let mut timing = vulkan_app.frame_timing();
+    let mut cpu_stages = vulkan_app.cpu_stage_report();
+    let mut gpu_scopes = vulkan_app.gpu_profile_report();
+    let mut state_machine = StateMachine::new(CALIBRATION_PATH);
+    let mut power_saving = PowerSaving::new(PowerSavingMode::Auto);
+    power_saving.update(&mut vulkan_app);
 
     
     // let frame_seed = rand::random::<f32>();
     while !window.should_close() {
+        let events_start = Instant::now();
         {
             use glfw::WindowEvent as Event;
-            use glfw::Key;
-            use glfw::Action;
+            use glfw::Action as KeyAction;
             glfw.poll_events();
             for (_, event) in glfw::flush_messages(&events) {
-                match event {
-                    Event::Key(Key::Escape, _, Action::Press, _) => {
-                        window.set_should_close(true);
+                #[cfg(feature = "egui")]
+                if gui.handle_event(&window, &event) {
+                    continue;
+                }
+                let (binding, key_action) = match event {
+                    Event::Key(key, _, action, _) => (Binding::Key(key), action),
+                    Event::MouseButton(button, action, _) => (Binding::Mouse(button), action),
+                    Event::Scroll(_, y) => {
+                        camera.zoom = (camera.zoom * 1.1_f32.powf(y as f32)).clamp(0.1, 10.0);
+                        continue;
                     },
                     Event::FramebufferSize(w, h) => {
                         vulkan_app.framebuffer_resize(w as u32, h as u32, &window);
+                        continue;
                     },
-                    _ => {},
+                    _ => continue,
+                };
+                for action in key_bindings.actions(binding) {
+                    match (action, key_action) {
+                        (Action::MoveLeft | Action::MoveRight | Action::Jump, KeyAction::Press | KeyAction::Release) => {
+                            let held = key_action == KeyAction::Press;
+                            match action {
+                                Action::MoveLeft => player_input.left = held,
+                                Action::MoveRight => player_input.right = held,
+                                _ => player_input.jump = held,
+                            }
+                        },
+                        (Action::Back, KeyAction::Press) => {
+                            match state_machine.state() {
+                                AppState::Playing => window.set_should_close(true),
+                                AppState::Paused | AppState::Calibrating => state_machine.transition(AppState::Playing, &mut time, &mut vulkan_app),
+                            }
+                        },
+                        (Action::CycleGpuPreference, KeyAction::Press) => {
+                            let preference = match vulkan_app.gpu_preference() {
+                                GpuPreference::Auto => GpuPreference::HighPerformance,
+                                GpuPreference::HighPerformance => GpuPreference::LowPower,
+                                GpuPreference::LowPower => GpuPreference::Auto,
+                            };
+                            info!("GPU preference: {:?}", preference);
+                            vulkan_app.set_gpu_preference(&window, preference);
+                        },
+                        (Action::CyclePowerSaving, KeyAction::Press) => {
+                            let mode = power_saving.mode().next();
+                            info!("Power saving mode: {:?}", mode);
+                            power_saving.set_mode(mode, &mut vulkan_app);
+                        },
+                        (Action::CycleFrameRateCap, KeyAction::Press) => {
+                            let cap = match vulkan_app.frame_rate_cap() {
+                                None => Some(30.0),
+                                Some(fps) if fps < 60.0 => Some(60.0),
+                                Some(fps) if fps < 144.0 => Some(144.0),
+                                Some(_) => None,
+                            };
+                            info!("Frame rate cap: {:?}", cap);
+                            vulkan_app.set_frame_rate_cap(cap);
+                        },
+                        (Action::TogglePause, KeyAction::Press) => {
+                            let to = match state_machine.state() {
+                                AppState::Playing => AppState::Paused,
+                                AppState::Paused => AppState::Playing,
+                                AppState::Calibrating => AppState::Calibrating,
+                            };
+                            state_machine.transition(to, &mut time, &mut vulkan_app);
+                        },
+                        (Action::SlowDown, KeyAction::Press) => {
+                            time.set_scale(time.scale() * 0.5);
+                            info!("Time scale: {}", time.scale());
+                        },
+                        (Action::SpeedUp, KeyAction::Press) => {
+                            time.set_scale(time.scale() * 2.0);
+                            info!("Time scale: {}", time.scale());
+                        },
+                        (Action::CycleColorFilter, KeyAction::Press) => {
+                            let color_filter = vulkan_app.color_filter().next();
+                            info!("Color filter: {:?}", color_filter);
+                            vulkan_app.set_color_filter(color_filter);
+                        },
+                        (Action::CycleTonemapper, KeyAction::Press) => {
+                            let tonemapper = vulkan_app.tonemapper().next();
+                            info!("Tonemapper: {:?}", tonemapper);
+                            vulkan_app.set_tonemapper(tonemapper);
+                        },
+                        (Action::CycleDebugView, KeyAction::Press) => {
+                            let debug_view = vulkan_app.debug_view().next();
+                            info!("Debug view: {:?}", debug_view);
+                            vulkan_app.set_debug_view(debug_view);
+                        },
+                        (Action::ReloadShaders, KeyAction::Press) => {
+                            vulkan_app.reload_shaders();
+                        },
+                        (Action::ToggleHud, KeyAction::Press) => {
+                            show_hud = !show_hud;
+                        },
+                        #[cfg(feature = "egui")]
+                        (Action::ToggleSettings, KeyAction::Press) => {
+                            show_settings = !show_settings;
+                        },
+                        (Action::ToggleBounds, KeyAction::Press) => {
+                            show_bounds = !show_bounds;
+                            info!("Bounding boxes: {}", show_bounds);
+                        },
+                        (Action::Calibrate, KeyAction::Press) => {
+                            let to = match state_machine.state() {
+                                AppState::Calibrating => AppState::Playing,
+                                AppState::Playing | AppState::Paused => AppState::Calibrating,
+                            };
+                            state_machine.transition(to, &mut time, &mut vulkan_app);
+                        },
+                        (Action::ExposureUp | Action::ExposureDown | Action::GammaUp | Action::GammaDown, KeyAction::Press | KeyAction::Repeat)
+                            if state_machine.state() == AppState::Calibrating => {
+                            let mut calibration = vulkan_app.display_calibration();
+                            match action {
+                                Action::ExposureUp => calibration.exposure += 0.1,
+                                Action::ExposureDown => calibration.exposure -= 0.1,
+                                Action::GammaUp => calibration.gamma += 0.02,
+                                _ => calibration.gamma -= 0.02,
+                            }
+                            vulkan_app.set_display_calibration(calibration);
+                            let calibration = vulkan_app.display_calibration();
+                            info!("Exposure: {:+.1}, gamma: {:.2}", calibration.exposure, calibration.gamma);
+                        },
+                        (Action::Pick, KeyAction::Press) if state_machine.state().accepts_picking() => {
+                            // cursor position is in screen coordinates, scale it to framebuffer pixels
+                            let (cursor_x, cursor_y) = window.get_cursor_pos();
+                            let (window_w, window_h) = window.get_size();
+                            let (fb_w, fb_h) = window.get_framebuffer_size();
+                            let x = (cursor_x * fb_w as f64 / window_w as f64).max(0.0) as u32;
+                            let y = (cursor_y * fb_h as f64 / window_h as f64).max(0.0) as u32;
+                            vulkan_app.request_depth_readback(x, y);
+                            pending_click = Some((x, y));
+                        },
+                        _ => {},
+                    }
                 }
             }
         }
+        vulkan_app.add_cpu_stage("events", events_start.elapsed());
+
+        let (fb_w, fb_h) = window.get_framebuffer_size();
+        let crosshair = (fb_w.max(0) as u32 / 2, fb_h.max(0) as u32 / 2);
+        // a click pick takes the single pick slot until it lands, the target is kept meanwhile
+        match pending_click {
+            Some((x, y)) => vulkan_app.request_pick(x, y),
+            None if state_machine.state().accepts_picking() => target = vulkan_app.pick(crosshair.0, crosshair.1),
+            None => {},
+        }
 
 
-        let timestamp = Instant::now().duration_since(start_time).as_secs_f32();
+        let simulation_start = Instant::now();
+        time.update();
+        triangle_clock.advance(&time);
+        time_of_day.advance(&time);
+        animate_scenery(&mut scenery, time.game_time());
+        if state_machine.state().simulation_runs() {
+            player.update(&time, player_input, &solid_blocks(&vertex_data));
+        }
+        camera.position = player.position().into();
+        vulkan_app.set_frame_uniforms(FrameUniforms {
+            game_time: time.game_time() as f32,
+            real_time: time.real_time() as f32,
+            delta_time: time.delta() as f32,
+            light_intensity: time_of_day.light_intensity(),
+            ..Default::default()
+        });
+        vulkan_app.add_cpu_stage("simulation", simulation_start.elapsed());
 
         //draw
-        vulkan_app.draw_frame(&vertex_data);
+        let frame = match state_machine.state() {
+            AppState::Calibrating => calibration_frame(),
+            AppState::Playing | AppState::Paused => {
+                let mut frame = scene_frame(&vertex_data, &scenery, camera, &time_of_day);
+                if show_bounds {
+                    draw_bounds(frame.debug_draw(), &vertex_data);
+                }
+                if let Some(target) = target {
+                    draw_target(frame.debug_draw(), &vertex_data, target);
+                }
+                let player_bounds = player.bounds();
+                frame.draw(scenery.quad, scenery.player_material, Transform2D {
+                    translation: (Vec2::from(player_bounds.min) + Vec2::from(player_bounds.max)) / 2.0,
+                    scale: Vec2::from(player_bounds.max) - Vec2::from(player_bounds.min),
+                    ..Default::default()
+                }, None);
+                if show_hud {
+                    let stats = HudStats { fps, timing, cpu_stages: &cpu_stages, gpu_scopes: &gpu_scopes, batches: vulkan_app.batch_stats(), memory: vulkan_app.memory_stats(), power_saving: &power_saving };
+                    draw_hud(frame.hud(), &stats, &camera, &time_of_day, &vertex_data);
+                }
+                draw_crosshair(frame.hud(), crosshair);
+                frame
+            },
+        };
+        // runs in every state, each output carries texture updates that must be drawn
+        #[cfg(feature = "egui")]
+        let frame = {
+            let mut frame = frame;
+            frame.set_gui(gui.run(&mut window, |ctx| {
+                if show_settings {
+                    settings_window(ctx, &mut vulkan_app, &mut time, &mut show_hud, &mut show_bounds);
+                }
+            }));
+            frame
+        };
+        vulkan_app.draw_frame(&window, &frame);
 
         //draw end
+        if let Some(readback) = vulkan_app.take_depth_readback() {
+            let (cx, cy) = (readback.x + readback.width / 2, readback.y + readback.height / 2);
+            info!("Depth around ({}, {}): {:?}", cx, cy, readback.depth_at(cx, cy));
+        }
+        // crosshair picks landing meanwhile are dropped, the click was requested after them
+        if let Some(click) = pending_click {
+            if let Some(pick) = vulkan_app.take_pick_result().filter(|pick| (pick.x, pick.y) == click) {
+                info!("Picked at ({}, {}): {:?}", pick.x, pick.y, pick.entity);
+                pending_click = None;
+            }
+        }
         //delay 1ms
         // std::thread::sleep(std::time::Duration::from_millis(1));
 
         frames += 1;
-        vertex_data[0] = f32::sin(timestamp * 15.0) * 0.5;
-        vertex_data[1] = f32::cos(timestamp * 15.0) * 0.2 - 0.7;
-
-        vertex_data[29] = f32::sin(timestamp * 15.0) * 0.5;
-        let end = Instant::now().duration_since(start_time).as_secs();
+        let simulation_start = Instant::now();
+        animate_triangles(&mut vertex_data, triangle_clock.time() as f32);
+        vulkan_app.add_cpu_stage("simulation", simulation_start.elapsed());
+        let end = time.real_time() as u64;
         if end != prev_sec {
-            println!("FPS: {}", frames);
+            timing = vulkan_app.frame_timing();
+            fps = frames;
+            if window_config.fps_in_title {
+                app_window::update_title(&mut window, &window_config, frames, &timing);
+            }
+            // frame rate, stage timings and time of day are on the HUD
+            debug!("  present latency {:.3}ms", timing.present_latency_ms);
+            cpu_stages = vulkan_app.cpu_stage_report();
+            gpu_scopes = vulkan_app.gpu_profile_report();
+            debug!("  CPU [{}]", cpu_stage_bar(&cpu_stages));
+            for (i, stage) in cpu_stages.iter().enumerate() {
+                debug!("  {} CPU {}: avg {:.3}ms", CPU_STAGE_GLYPHS[i % CPU_STAGE_GLYPHS.len()], stage.name, stage.avg_ms);
+            }
+            for scope in &gpu_scopes {
+                debug!("  GPU {}: avg {:.3}ms, p50 {:.3}ms, p95 {:.3}ms, max {:.3}ms", scope.name, scope.avg_ms, scope.p50_ms, scope.p95_ms, scope.max_ms);
+            }
+            if let Some(stats) = vulkan_app.pipeline_statistics() {
+                debug!("  vertex invocations: {}, clipping invocations: {}, clipped primitives: {}, fragment invocations: {}",
+                    stats.vertex_shader_invocations, stats.clipping_invocations, stats.clipping_primitives, stats.fragment_shader_invocations);
+            }
             frames = 0;
             prev_sec = end;
+
+            vulkan_app.check_gpu_switch(&window);
+            power_saving.update(&mut vulkan_app);
         }
 
     }
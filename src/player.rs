@@ -0,0 +1,122 @@
+use crate::time::Time;
+
+/// World units per second squared, world y points down
+const GRAVITY: f32 = 6.0;
+const MAX_FALL_SPEED: f32 = 4.0;
+/// Horizontal speed while walking, in world units per second
+const WALK_SPEED: f32 = 0.8;
+/// Upwards speed at the start of a jump, reaches about 0.75 units
+const JUMP_SPEED: f32 = 3.0;
+
+/// Longest step `Player::update` simulates at once, longer frames are split so fast falls don't tunnel through thin blocks
+const MAX_STEP: f32 = 1.0 / 120.0;
+
+/// Rounding tolerance when deciding whether the player was outside a block before a move
+const SKIN: f32 = 1e-4;
+
+/// Axis aligned box in world units
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+impl Aabb {
+    /// Touching boxes don't overlap, so a player standing on a block can still walk
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        (0..2).all(|axis| self.min[axis] < other.max[axis] && other.min[axis] < self.max[axis])
+    }
+}
+
+/// Movement keys held this frame
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlayerInput {
+    pub left: bool,
+    pub right: bool,
+    pub jump: bool,
+}
+
+/// Side view character that walks, jumps and falls, and stops at solid blocks
+pub struct Player {
+    /// Center of the bounding box
+    position: [f32; 2],
+    velocity: [f32; 2],
+    on_ground: bool,
+}
+
+impl Player {
+    pub const SIZE: [f32; 2] = [0.1, 0.2];
+
+    pub fn new(position: [f32; 2]) -> Self {
+        Self {
+            position,
+            velocity: [0.0, 0.0],
+            on_ground: false,
+        }
+    }
+
+    pub fn position(&self) -> [f32; 2] {
+        self.position
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        let half = [Self::SIZE[0] / 2.0, Self::SIZE[1] / 2.0];
+        Aabb {
+            min: [self.position[0] - half[0], self.position[1] - half[1]],
+            max: [self.position[0] + half[0], self.position[1] + half[1]],
+        }
+    }
+
+    /// Moves by `time.delta()`, stands still while paused. Starting inside a block doesn't push the player out.
+    pub fn update(&mut self, time: &Time, input: PlayerInput, solids: &[Aabb]) {
+        self.velocity[0] = match (input.left, input.right) {
+            (true, false) => -WALK_SPEED,
+            (false, true) => WALK_SPEED,
+            _ => 0.0,
+        };
+        if input.jump && self.on_ground {
+            self.velocity[1] = -JUMP_SPEED;
+        }
+
+        let mut remaining = time.delta() as f32;
+        while remaining > 0.0 {
+            let dt = remaining.min(MAX_STEP);
+            remaining -= dt;
+
+            self.velocity[1] = (self.velocity[1] + GRAVITY * dt).min(MAX_FALL_SPEED);
+            self.move_axis(0, self.velocity[0] * dt, solids);
+            self.on_ground = false;
+            self.move_axis(1, self.velocity[1] * dt, solids);
+        }
+    }
+
+    /// Moves along `axis` and pushes back out of every block entered on the way
+    fn move_axis(&mut self, axis: usize, distance: f32, solids: &[Aabb]) {
+        if distance == 0.0 {
+            return;
+        }
+        let before = self.bounds();
+        self.position[axis] += distance;
+        let half = Self::SIZE[axis] / 2.0;
+        for solid in solids {
+            // only blocks the leading edge crossed, blocks that already overlapped before the move don't stop it
+            let entered = if distance > 0.0 {
+                before.max[axis] <= solid.min[axis] + SKIN
+            } else {
+                before.min[axis] >= solid.max[axis] - SKIN
+            };
+            if !entered || !self.bounds().overlaps(solid) {
+                continue;
+            }
+            if distance > 0.0 {
+                self.position[axis] = self.position[axis].min(solid.min[axis] - half);
+            } else {
+                self.position[axis] = self.position[axis].max(solid.max[axis] + half);
+            }
+            self.velocity[axis] = 0.0;
+            if axis == 1 && distance > 0.0 {
+                self.on_ground = true;
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use log::info;
+
+use crate::vulkanapp::{self, VulkanApp};
+
+/// Frame rate cap while power saving, a lower cap set by the user is kept
+const POWER_SAVING_FRAME_RATE_CAP: f32 = 30.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerSavingMode {
+    /// Active while running on battery
+    Auto,
+    On,
+    Off,
+}
+
+impl PowerSavingMode {
+    pub fn next(self) -> Self {
+        match self {
+            PowerSavingMode::Auto => PowerSavingMode::On,
+            PowerSavingMode::On => PowerSavingMode::Off,
+            PowerSavingMode::Off => PowerSavingMode::Auto,
+        }
+    }
+}
+
+/// Caps the frame rate while on battery, restores the previous cap when back on mains
+pub struct PowerSaving {
+    mode: PowerSavingMode,
+    /// Frame rate cap from before power saving was activated, Some while active
+    saved_cap: Option<Option<f32>>,
+}
+
+impl PowerSaving {
+    pub fn new(mode: PowerSavingMode) -> Self {
+        Self {
+            mode,
+            saved_cap: None,
+        }
+    }
+
+    pub fn mode(&self) -> PowerSavingMode {
+        self.mode
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.saved_cap.is_some()
+    }
+
+    pub fn set_mode(&mut self, mode: PowerSavingMode, vulkan_app: &mut VulkanApp) {
+        self.mode = mode;
+        self.update(vulkan_app);
+    }
+
+    /// Reads the power source, meant to be polled periodically
+    pub fn update(&mut self, vulkan_app: &mut VulkanApp) {
+        let active = match self.mode {
+            // unknown power source is treated as mains
+            PowerSavingMode::Auto => vulkanapp::on_battery_power() == Some(true),
+            PowerSavingMode::On => true,
+            PowerSavingMode::Off => false,
+        };
+        if active == self.is_active() {
+            return;
+        }
+
+        if active {
+            let cap = vulkan_app.frame_rate_cap();
+            self.saved_cap = Some(cap);
+            vulkan_app.set_frame_rate_cap(Some(cap.map_or(POWER_SAVING_FRAME_RATE_CAP, |fps| fps.min(POWER_SAVING_FRAME_RATE_CAP))));
+            info!("Power saving on ({:?}), frame rate cap: {:?}", self.mode, vulkan_app.frame_rate_cap());
+        } else {
+            vulkan_app.set_frame_rate_cap(self.saved_cap.take().unwrap());
+            info!("Power saving off ({:?}), frame rate cap: {:?}", self.mode, vulkan_app.frame_rate_cap());
+        }
+    }
+}
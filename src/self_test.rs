@@ -0,0 +1,77 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use glam::Vec2;
+use log::{error, info};
+
+use crate::vulkanapp::{Frame, MaterialId, RendererConfig, Sprite, Vertex, VulkanApp};
+
+const SIZE: u32 = 64;
+
+/// Clear color of the main render pass as RGBA8, a covered pixel must differ from it
+const CLEAR_COLOR: [u8; 4] = [204, 102, 178, 255];
+
+/// Runs one step, turning a panic into a failure with its message
+fn step<T>(name: &str, f: impl FnOnce() -> Result<T, String>) -> Option<T> {
+    let res = catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_owned());
+        Err(format!("panicked: {}", message))
+    });
+
+    match res {
+        Ok(value) => {
+            info!("[PASS] {}", name);
+            Some(value)
+        },
+        Err(e) => {
+            error!("[FAIL] {}: {}", name, e);
+            None
+        }
+    }
+}
+
+/// Creates a headless device with the built-in pipelines, renders one frame and checks the result.
+/// Returns true if every step passed.
+pub fn run() -> bool {
+    info!("Self-test: {} {}, working directory {:?}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), std::env::current_dir().ok());
+
+    let app = step("create device, shaders and resources", || {
+        // the vertex data only sizes the initial vertex buffers
+        Ok(VulkanApp::new_headless(SIZE, SIZE, &[Vertex::default(); 6], RendererConfig::default()))
+    });
+    let mut app = match app {
+        Some(app) => app,
+        None => return false,
+    };
+
+    for gpu in app.list_gpus() {
+        info!("  GPU {}: {} ({:?}, {} MB), score {:?}", gpu.index, gpu.name, gpu.device_type, gpu.device_local_memory_mb, gpu.score);
+    }
+
+    let rendered = step("render offscreen frame", || {
+        let mut frame = Frame::new();
+        frame.draw_sprite(&Sprite { size: Vec2::splat(2.0), ..Default::default() }, MaterialId::DEFAULT, None);
+        if app.draw_offscreen_frame(&frame) {
+            Ok(())
+        } else {
+            Err("frame was dropped, the device was lost".to_owned())
+        }
+    });
+    if rendered.is_none() {
+        return false;
+    }
+
+    step("read back frame", || {
+        let pixels = app.read_back_frame();
+        if pixels.len() != (SIZE * SIZE * 4) as usize {
+            return Err(format!("expected {} bytes, got {}", SIZE * SIZE * 4, pixels.len()));
+        }
+        let center = ((SIZE / 2 * SIZE + SIZE / 2) * 4) as usize;
+        let pixel = &pixels[center..center + 4];
+        if pixel == CLEAR_COLOR {
+            return Err("center pixel has the clear color, the fullscreen sprite was not drawn".to_owned());
+        }
+        Ok(())
+    }).is_some()
+}
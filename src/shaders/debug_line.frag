@@ -0,0 +1,14 @@
+#version 450 core
+
+layout(location = 0) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 srgbToLinear(vec3 c) {
+    return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), greaterThan(c, vec3(0.04045)));
+}
+
+// unlit, debug colors are not affected by the day/night light level
+void main() {
+    outColor = vec4(srgbToLinear(fragColor.rgb), fragColor.a);
+}
@@ -0,0 +1,12 @@
+#version 450 core
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 fragColor;
+
+// positions are already in normalized device coordinates, see debug_draw.rs
+void main() {
+    gl_Position = vec4(position, 1.0);
+    fragColor = color;
+}
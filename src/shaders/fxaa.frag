@@ -0,0 +1,133 @@
+#version 450 core
+
+layout(location = 0) in vec2 uv;
+layout(location = 0) out vec4 outColor;
+
+// tonemapped linear color, values above 1 are clipped by the luma estimate
+layout(binding = 0) uniform sampler2D source;
+
+// contrast below max(EDGE_THRESHOLD_MIN, EDGE_THRESHOLD * local max luma) is not treated as an edge
+const float EDGE_THRESHOLD = 0.125;
+const float EDGE_THRESHOLD_MIN = 0.0312;
+// how much of the subpixel aliasing is removed, 0 keeps it, 1 blurs it away
+const float SUBPIXEL_QUALITY = 0.75;
+// steps taken along the edge in each direction, and their length in texels
+const int SEARCH_STEPS = 10;
+const float SEARCH_STEP_SIZES[SEARCH_STEPS] = float[](1.0, 1.0, 1.0, 1.0, 1.5, 2.0, 2.0, 2.0, 4.0, 8.0);
+
+// perceptual luma, sqrt approximates the sRGB encoding the edge thresholds are tuned for
+float luma(vec3 linear) {
+    return sqrt(dot(clamp(linear, 0.0, 1.0), vec3(0.299, 0.587, 0.114)));
+}
+
+float lumaAt(vec2 coord) {
+    return luma(textureLod(source, coord, 0.0).rgb);
+}
+
+// FXAA 3.11 quality preset, after Timothy Lottes
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(source, 0));
+    vec4 color = textureLod(source, uv, 0.0);
+
+    float lumaCenter = luma(color.rgb);
+    float lumaDown = lumaAt(uv + texel * vec2(0.0, -1.0));
+    float lumaUp = lumaAt(uv + texel * vec2(0.0, 1.0));
+    float lumaLeft = lumaAt(uv + texel * vec2(-1.0, 0.0));
+    float lumaRight = lumaAt(uv + texel * vec2(1.0, 0.0));
+
+    float lumaMin = min(lumaCenter, min(min(lumaDown, lumaUp), min(lumaLeft, lumaRight)));
+    float lumaMax = max(lumaCenter, max(max(lumaDown, lumaUp), max(lumaLeft, lumaRight)));
+    float lumaRange = lumaMax - lumaMin;
+    if (lumaRange < max(EDGE_THRESHOLD_MIN, lumaMax * EDGE_THRESHOLD)) {
+        outColor = color;
+        return;
+    }
+
+    float lumaDownLeft = lumaAt(uv + texel * vec2(-1.0, -1.0));
+    float lumaUpRight = lumaAt(uv + texel * vec2(1.0, 1.0));
+    float lumaUpLeft = lumaAt(uv + texel * vec2(-1.0, 1.0));
+    float lumaDownRight = lumaAt(uv + texel * vec2(1.0, -1.0));
+
+    float lumaDownUp = lumaDown + lumaUp;
+    float lumaLeftRight = lumaLeft + lumaRight;
+    float lumaLeftCorners = lumaDownLeft + lumaUpLeft;
+    float lumaDownCorners = lumaDownLeft + lumaDownRight;
+    float lumaRightCorners = lumaDownRight + lumaUpRight;
+    float lumaUpCorners = lumaUpRight + lumaUpLeft;
+
+    float edgeHorizontal = abs(-2.0 * lumaLeft + lumaLeftCorners) + abs(-2.0 * lumaCenter + lumaDownUp) * 2.0 + abs(-2.0 * lumaRight + lumaRightCorners);
+    float edgeVertical = abs(-2.0 * lumaUp + lumaUpCorners) + abs(-2.0 * lumaCenter + lumaLeftRight) * 2.0 + abs(-2.0 * lumaDown + lumaDownCorners);
+    bool isHorizontal = edgeHorizontal >= edgeVertical;
+
+    // pick the side of the edge with the steeper gradient
+    float luma1 = isHorizontal ? lumaDown : lumaLeft;
+    float luma2 = isHorizontal ? lumaUp : lumaRight;
+    float gradient1 = luma1 - lumaCenter;
+    float gradient2 = luma2 - lumaCenter;
+    bool is1Steepest = abs(gradient1) >= abs(gradient2);
+    float gradientScaled = 0.25 * max(abs(gradient1), abs(gradient2));
+
+    float stepLength = isHorizontal ? texel.y : texel.x;
+    float lumaLocalAverage;
+    if (is1Steepest) {
+        stepLength = -stepLength;
+        lumaLocalAverage = 0.5 * (luma1 + lumaCenter);
+    } else {
+        lumaLocalAverage = 0.5 * (luma2 + lumaCenter);
+    }
+
+    // walk along the edge, half a texel towards the steeper side, until its ends are found
+    vec2 edgeCoord = uv;
+    if (isHorizontal) {
+        edgeCoord.y += stepLength * 0.5;
+    } else {
+        edgeCoord.x += stepLength * 0.5;
+    }
+    vec2 offset = isHorizontal ? vec2(texel.x, 0.0) : vec2(0.0, texel.y);
+
+    vec2 coord1 = edgeCoord - offset * SEARCH_STEP_SIZES[0];
+    vec2 coord2 = edgeCoord + offset * SEARCH_STEP_SIZES[0];
+    float lumaEnd1 = lumaAt(coord1) - lumaLocalAverage;
+    float lumaEnd2 = lumaAt(coord2) - lumaLocalAverage;
+    bool reached1 = abs(lumaEnd1) >= gradientScaled;
+    bool reached2 = abs(lumaEnd2) >= gradientScaled;
+
+    for (int i = 1; i < SEARCH_STEPS && !(reached1 && reached2); i++) {
+        if (!reached1) {
+            coord1 -= offset * SEARCH_STEP_SIZES[i];
+            lumaEnd1 = lumaAt(coord1) - lumaLocalAverage;
+            reached1 = abs(lumaEnd1) >= gradientScaled;
+        }
+        if (!reached2) {
+            coord2 += offset * SEARCH_STEP_SIZES[i];
+            lumaEnd2 = lumaAt(coord2) - lumaLocalAverage;
+            reached2 = abs(lumaEnd2) >= gradientScaled;
+        }
+    }
+
+    float distance1 = isHorizontal ? uv.x - coord1.x : uv.y - coord1.y;
+    float distance2 = isHorizontal ? coord2.x - uv.x : coord2.y - uv.y;
+    bool isDirection1 = distance1 < distance2;
+    float distanceFinal = min(distance1, distance2);
+    float edgeLength = distance1 + distance2;
+
+    // only blend if the closer end bends away from the center's side of the edge
+    bool isLumaCenterSmaller = lumaCenter < lumaLocalAverage;
+    bool correctVariation = ((isDirection1 ? lumaEnd1 : lumaEnd2) < 0.0) != isLumaCenterSmaller;
+    float pixelOffset = correctVariation ? -distanceFinal / edgeLength + 0.5 : 0.0;
+
+    // subpixel aliasing, from the 3x3 neighbourhood contrast
+    float lumaAverage = (1.0 / 12.0) * (2.0 * (lumaDownUp + lumaLeftRight) + lumaLeftCorners + lumaRightCorners);
+    float subPixelOffset1 = clamp(abs(lumaAverage - lumaCenter) / lumaRange, 0.0, 1.0);
+    float subPixelOffset2 = (-2.0 * subPixelOffset1 + 3.0) * subPixelOffset1 * subPixelOffset1;
+    float subPixelOffset = subPixelOffset2 * subPixelOffset2 * SUBPIXEL_QUALITY;
+    pixelOffset = max(pixelOffset, subPixelOffset);
+
+    vec2 finalCoord = uv;
+    if (isHorizontal) {
+        finalCoord.y += pixelOffset * stepLength;
+    } else {
+        finalCoord.x += pixelOffset * stepLength;
+    }
+    outColor = vec4(textureLod(source, finalCoord, 0.0).rgb, color.a);
+}
@@ -0,0 +1,51 @@
+#version 450 core
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+
+// sRGB with premultiplied alpha, like the vertex colors
+layout(binding = 0) uniform sampler2D guiTexture;
+
+// transfer function of the swapchain color space, same as in output.frag
+// 0: sRGB nonlinear, 1: HDR10 PQ, 2: scRGB linear
+layout(constant_id = 0) const uint transferFunction = 0;
+
+// brightness of SDR white on HDR displays, in nits
+const float SDR_WHITE_NITS = 200.0;
+
+vec3 srgbToLinear(vec3 c) {
+    return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), greaterThan(c, vec3(0.04045)));
+}
+
+vec3 linearToPq(vec3 nits) {
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+    vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(m1));
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+}
+
+// egui blends in sRGB space, so SDR outputs take the colors as they are
+void main() {
+    vec4 color = fragColor * texture(guiTexture, fragTexCoord);
+
+    if (transferFunction != 0 && color.a > 0.0) {
+        vec3 linear = srgbToLinear(color.rgb / color.a);
+        if (transferFunction == 1) {
+            const mat3 bt709ToBt2020 = mat3(
+                0.6274, 0.0691, 0.0164,
+                0.3293, 0.9195, 0.0880,
+                0.0433, 0.0114, 0.8956);
+            color.rgb = linearToPq(bt709ToBt2020 * linear * SDR_WHITE_NITS) * color.a;
+        } else {
+            // scRGB: 1.0 is 80 nits
+            color.rgb = linear * (SDR_WHITE_NITS / 80.0) * color.a;
+        }
+    }
+
+    outColor = color;
+}
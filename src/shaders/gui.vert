@@ -0,0 +1,20 @@
+#version 450 core
+
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 texCoord;
+layout(location = 2) in vec4 color;
+
+layout(location = 0) out vec2 fragTexCoord;
+layout(location = 1) out vec4 fragColor;
+
+// size of the output image in egui points
+layout(push_constant) uniform ScreenSize {
+    vec2 screenSize;
+};
+
+// egui positions are in points from the top-left corner, see gui_renderer.rs
+void main() {
+    gl_Position = vec4(position / screenSize * 2.0 - 1.0, 0.0, 1.0);
+    fragTexCoord = texCoord;
+    fragColor = color;
+}
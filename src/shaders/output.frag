@@ -0,0 +1,96 @@
+#version 450 core
+
+layout(location = 0) in vec2 uv;
+layout(location = 0) out vec4 outColor;
+
+// tonemapped linear color
+layout(binding = 0) uniform sampler2D source;
+
+// see uniforms.rs
+layout(binding = 1) uniform FrameUniforms {
+    float gameTime;
+    float realTime;
+    float deltaTime;
+    float exposure;
+    float gamma;
+    uint calibrationPattern;
+    uint colorFilter;
+    uint tonemapper;
+    float lightIntensity;
+    uint debugView;
+} frame;
+
+// transfer function of the swapchain color space, see surface_format.rs
+// 0: sRGB nonlinear, 1: HDR10 PQ, 2: scRGB linear
+layout(constant_id = 0) const uint transferFunction = 0;
+
+// brightness of SDR white on HDR displays, in nits
+const float SDR_WHITE_NITS = 200.0;
+
+vec3 linearToSrgb(vec3 c) {
+    return mix(c * 12.92, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, greaterThan(c, vec3(0.0031308)));
+}
+
+// Machado et al. 2009, full severity, linear RGB. Column-major, each line is a column.
+const mat3 simulateProtanopia = mat3(
+    0.152286, 0.114503, -0.003882,
+    1.052583, 0.786281, -0.048116,
+    -0.204868, 0.099216, 1.051998);
+const mat3 simulateDeuteranopia = mat3(
+    0.367322, 0.280085, -0.011820,
+    0.860646, 0.672501, 0.042940,
+    -0.227968, 0.047413, 0.968881);
+const mat3 simulateTritanopia = mat3(
+    1.255528, -0.078411, 0.004733,
+    -0.076749, 0.930809, 0.691367,
+    -0.178779, 0.147602, 0.303900);
+
+// colorFilter: 0 none, 1-3 simulate protanopia/deuteranopia/tritanopia, 4-6 compensate them
+vec3 applyColorFilter(vec3 linear, uint mode) {
+    if (mode == 0) {
+        return linear;
+    }
+    uint deficiency = (mode - 1) % 3;
+    mat3 simulate = deficiency == 0 ? simulateProtanopia : deficiency == 1 ? simulateDeuteranopia : simulateTritanopia;
+    vec3 simulated = simulate * linear;
+    if (mode <= 3) {
+        return simulated;
+    }
+    // daltonization: move the lost information into channels that are still seen
+    vec3 error = linear - simulated;
+    vec3 shift = vec3(0.0, 0.7 * error.r + error.g, 0.7 * error.r + error.b);
+    return clamp(linear + shift, 0.0, 1.0);
+}
+
+vec3 linearToPq(vec3 nits) {
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+    vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(m1));
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+}
+
+void main() {
+    vec4 color = texture(source, uv);
+
+    // display gamma from the calibration, then the accessibility filter
+    vec3 linear = pow(max(color.rgb, 0.0), vec3(frame.gamma));
+    linear = applyColorFilter(linear, frame.colorFilter);
+
+    if (transferFunction == 1) {
+        const mat3 bt709ToBt2020 = mat3(
+            0.6274, 0.0691, 0.0164,
+            0.3293, 0.9195, 0.0880,
+            0.0433, 0.0114, 0.8956);
+        color.rgb = linearToPq(bt709ToBt2020 * linear * SDR_WHITE_NITS);
+    } else if (transferFunction == 2) {
+        // scRGB: 1.0 is 80 nits
+        color.rgb = linear * (SDR_WHITE_NITS / 80.0);
+    } else {
+        color.rgb = linearToSrgb(linear);
+    }
+
+    outColor = color;
+}
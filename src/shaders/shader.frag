@@ -1,11 +1,110 @@
 #version 450 core
+#ifdef BINDLESS
+#extension GL_EXT_nonuniform_qualifier : require
+#endif
 
 layout(location = 0) out vec4 outColor;
+layout(location = 1) out uint outObjectId;
 layout(location = 0) in vec2 fragTexCoord;
 
-layout(binding = 0) uniform sampler2D tex;
+// see material.rs. Built a second time with BINDLESS defined as frag_bindless.spv,
+// which indexes every texture from set 1 and takes the material parameters as push constants.
+#ifdef BINDLESS
+layout(set = 1, binding = 0) uniform sampler2D textures[];
 
+layout(push_constant) uniform PushConstants {
+    uint objectId;
+    uint textureIndex;
+    vec4 color;
+    vec2 uvScale;
+    vec2 uvOffset;
+} pushConstants;
+
+#define TEXTURE textures[nonuniformEXT(pushConstants.textureIndex)]
+#define MATERIAL pushConstants
+#else
+layout(set = 1, binding = 0) uniform sampler2D tex;
+layout(set = 1, binding = 1) uniform MaterialParams {
+    vec4 color;
+    vec2 uvScale;
+    vec2 uvOffset;
+} material;
+
+layout(push_constant) uniform PushConstants {
+    uint objectId;
+} pushConstants;
+
+#define TEXTURE tex
+#define MATERIAL material
+#endif
+
+// see uniforms.rs
+layout(binding = 1) uniform FrameUniforms {
+    float gameTime;
+    float realTime;
+    float deltaTime;
+    float exposure;
+    float gamma;
+    uint calibrationPattern;
+    uint colorFilter;
+    uint tonemapper;
+    float lightIntensity;
+    uint debugView;
+} frame;
+
+vec3 srgbToLinear(vec3 c) {
+    return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), greaterThan(c, vec3(0.04045)));
+}
+
+vec3 linearToSrgb(vec3 c) {
+    return mix(c * 12.92, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, greaterThan(c, vec3(0.0031308)));
+}
+
+// three columns in sRGB, each with a hint for the user:
+// dark: patches at 1-5% on black, raise exposure until all of them are just visible
+// mid: 50% gray next to a pixel checkerboard, change gamma until they look the same from a distance
+// bright: patches at 95-99% on white, lower exposure until all of them are distinguishable
+vec3 calibrationPattern(vec2 uv) {
+    int column = int(uv.x * 3.0);
+    int row = int(uv.y * 5.0);
+    bool inPatch = fract(uv.x * 3.0) > 0.25 && fract(uv.x * 3.0) < 0.75 && fract(uv.y * 5.0) > 0.2 && fract(uv.y * 5.0) < 0.8;
+
+    if (column == 0) {
+        return vec3(inPatch ? 0.01 * float(row + 1) : 0.0);
+    } else if (column == 1) {
+        if (fract(uv.x * 3.0) < 0.5) {
+            return vec3(linearToSrgb(vec3(0.5)));
+        }
+        ivec2 pixel = ivec2(gl_FragCoord.xy);
+        return vec3(float((pixel.x + pixel.y) & 1));
+    } else {
+        return vec3(inPatch ? 0.95 + 0.01 * float(row) : 1.0);
+    }
+}
 
 void main() {
-    outColor = texture(tex, fragTexCoord);
-}
\ No newline at end of file
+    vec4 color = texture(TEXTURE, fragTexCoord * MATERIAL.uvScale + MATERIAL.uvOffset);
+    vec3 linear = srgbToLinear(color.rgb) * MATERIAL.color.rgb * frame.lightIntensity;
+    color.a *= MATERIAL.color.a;
+    if (frame.calibrationPattern != 0) {
+        linear = srgbToLinear(calibrationPattern(fragTexCoord));
+        color.a = 1.0;
+    }
+
+    // debugView: 1 texture coordinates, 2 depth, 3 light level, see debug_view.rs.
+    // Encoded like the calibration pattern, so the displayed value is the inspected one.
+    if (frame.debugView == 1) {
+        linear = srgbToLinear(vec3(fract(fragTexCoord), 0.0));
+    } else if (frame.debugView == 2) {
+        linear = srgbToLinear(vec3(gl_FragCoord.z));
+    } else if (frame.debugView == 3) {
+        linear = srgbToLinear(vec3(clamp(frame.lightIntensity, 0.0, 1.0)));
+    }
+    if (frame.debugView != 0) {
+        color.a = 1.0;
+    }
+
+    // linear scene color, exposure, tonemapping and the output encoding are post-processing stages
+    outColor = vec4(linear, color.a);
+    outObjectId = pushConstants.objectId;
+}
@@ -1,11 +1,61 @@
 #version 450 core
+#ifdef VERTEX_PULLING
+#extension GL_EXT_buffer_reference : require
+#endif
 
+// Built a second time with VERTEX_PULLING defined as vert_pulling.spv, which has no vertex inputs
+// and reads the vertices of each draw through a device address instead.
+#ifdef VERTEX_PULLING
+// see vertex.rs, position xyz and texture coordinates uv of each vertex
+layout(buffer_reference, std430, buffer_reference_align = 4) readonly buffer Vertices {
+    float data[];
+};
+#else
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec2 texPos;
+#endif
 
 layout(location = 0) out vec2 fragTexCoord;
 
+// see uniforms.rs, also visible to the fragment stage
+layout(binding = 1) uniform FrameUniforms {
+    float gameTime;
+    float realTime;
+    float deltaTime;
+    float exposure;
+    float gamma;
+    uint calibrationPattern;
+    uint colorFilter;
+    uint tonemapper;
+    float lightIntensity;
+    uint debugView;
+} frame;
+
+// see draw_list.rs, one per draw, indexed by its first instance
+struct DrawData {
+    // rows of the affine matrix from positions to normalized device coordinates
+    vec4 ndcRowX;
+    vec4 ndcRowY;
+#ifdef VERTEX_PULLING
+    Vertices vertices;
+#else
+    uvec2 vertices;
+#endif
+};
+
+layout(std430, binding = 2) readonly buffer Draws {
+    DrawData draws[];
+};
+
 void main() {
-    gl_Position = vec4(position, 1.0);
+    DrawData draw = draws[gl_InstanceIndex];
+#ifdef VERTEX_PULLING
+    // gl_VertexIndex includes the first vertex of the draw
+    uint base = gl_VertexIndex * 5;
+    vec3 position = vec3(draw.vertices.data[base], draw.vertices.data[base + 1], draw.vertices.data[base + 2]);
+    vec2 texPos = vec2(draw.vertices.data[base + 3], draw.vertices.data[base + 4]);
+#endif
+    vec3 xy1 = vec3(position.xy, 1.0);
+    gl_Position = vec4(dot(draw.ndcRowX.xyz, xy1), dot(draw.ndcRowY.xyz, xy1), position.z, 1.0);
     fragTexCoord = texPos;
-}
\ No newline at end of file
+}
@@ -0,0 +1,15 @@
+#version 450 core
+
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 texCoord;
+layout(location = 2) in vec4 color;
+
+layout(location = 0) out vec2 fragTexCoord;
+layout(location = 1) out vec4 fragColor;
+
+// positions are already in normalized device coordinates, see text.rs
+void main() {
+    gl_Position = vec4(position, 0.0, 1.0);
+    fragTexCoord = texCoord;
+    fragColor = color;
+}
@@ -0,0 +1,40 @@
+#version 450 core
+
+layout(location = 0) in vec2 uv;
+layout(location = 0) out vec4 outColor;
+
+// output of the previous stage, the HDR scene color for the first one
+layout(binding = 0) uniform sampler2D source;
+
+// see uniforms.rs
+layout(binding = 1) uniform FrameUniforms {
+    float gameTime;
+    float realTime;
+    float deltaTime;
+    float exposure;
+    float gamma;
+    uint calibrationPattern;
+    uint colorFilter;
+    uint tonemapper;
+    float lightIntensity;
+    uint debugView;
+} frame;
+
+// Narkowicz 2015, fit of the ACES filmic curve
+vec3 aces(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+void main() {
+    vec4 color = texture(source, uv);
+    vec3 linear = color.rgb * exp2(frame.exposure);
+
+    // tonemapper: 0 none, 1 Reinhard, 2 ACES, see post_process.rs
+    if (frame.tonemapper == 1) {
+        linear = linear / (1.0 + linear);
+    } else if (frame.tonemapper == 2) {
+        linear = aces(linear);
+    }
+
+    outColor = vec4(linear, color.a);
+}
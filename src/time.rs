@@ -0,0 +1,109 @@
+use std::time::Instant;
+
+/// Central clock of the app, updated once per frame.
+/// Game time is scaled and stops while paused, real time always follows the wall clock.
+pub struct Time {
+    start: Instant,
+    last_update: Instant,
+
+    real_time: f64,
+    real_delta: f64,
+    game_time: f64,
+    delta: f64,
+
+    scale: f64,
+    paused: bool,
+}
+
+impl Time {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            last_update: now,
+
+            real_time: 0.0,
+            real_delta: 0.0,
+            game_time: 0.0,
+            delta: 0.0,
+
+            scale: 1.0,
+            paused: false,
+        }
+    }
+
+    /// Advances both clocks, call once at the start of every frame
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        self.real_delta = (now - self.last_update).as_secs_f64();
+        self.real_time = (now - self.start).as_secs_f64();
+        self.last_update = now;
+
+        self.delta = if self.paused { 0.0 } else { self.real_delta * self.scale };
+        self.game_time += self.delta;
+    }
+
+    /// Advances both clocks by exactly `real_delta` seconds instead of the wall clock time, for offline rendering
+    pub fn step(&mut self, real_delta: f64) {
+        self.real_delta = real_delta;
+        self.real_time += real_delta;
+        self.last_update = Instant::now();
+
+        self.delta = if self.paused { 0.0 } else { self.real_delta * self.scale };
+        self.game_time += self.delta;
+    }
+
+    /// Seconds since start, ignores pause and scale
+    pub fn real_time(&self) -> f64 {
+        self.real_time
+    }
+
+    /// Scaled seconds spent unpaused
+    pub fn game_time(&self) -> f64 {
+        self.game_time
+    }
+
+    /// Scaled time since the previous frame, 0 while paused
+    pub fn delta(&self) -> f64 {
+        self.delta
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: f64) {
+        self.scale = scale.max(0.0);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+}
+
+/// Local clock of a single animation, driven by the game time delta
+pub struct AnimationClock {
+    time: f64,
+    speed: f64,
+}
+
+impl AnimationClock {
+    pub fn new(speed: f64) -> Self {
+        Self {
+            time: 0.0,
+            speed,
+        }
+    }
+
+    pub fn advance(&mut self, time: &Time) {
+        self.time += time.delta() * self.speed;
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+}
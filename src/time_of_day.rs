@@ -0,0 +1,65 @@
+use crate::time::Time;
+
+/// Linear sky colors at midnight, at the horizon crossings and at noon
+const NIGHT_SKY: [f32; 3] = [0.005, 0.007, 0.02];
+const TWILIGHT_SKY: [f32; 3] = [0.6, 0.2, 0.08];
+const DAY_SKY: [f32; 3] = [0.25, 0.45, 0.9];
+
+/// Light intensity while the sun is down
+const MOONLIGHT: f32 = 0.15;
+
+/// Day/night cycle driven by game time, stops while paused and follows the time scale
+pub struct TimeOfDay {
+    /// Game seconds per full day
+    day_length: f64,
+    /// Hours since midnight, 0 to 24
+    hour: f64,
+}
+
+impl TimeOfDay {
+    pub fn new(day_length: f64, hour: f64) -> Self {
+        Self {
+            day_length,
+            hour: hour.rem_euclid(24.0),
+        }
+    }
+
+    pub fn advance(&mut self, time: &Time) {
+        self.hour = (self.hour + time.delta() / self.day_length * 24.0).rem_euclid(24.0);
+    }
+
+    pub fn hour(&self) -> f64 {
+        self.hour
+    }
+
+    /// Unit vector towards the sun, x east and y up. Rises at 6:00, culminates at noon, sets at 18:00.
+    pub fn sun_direction(&self) -> [f32; 3] {
+        let angle = ((self.hour - 6.0) / 24.0 * std::f64::consts::TAU) as f32;
+        [angle.cos(), angle.sin(), 0.0]
+    }
+
+    /// 0 while the sun is well below the horizon, 1 once it is well above
+    fn daylight(&self) -> f32 {
+        let elevation = self.sun_direction()[1];
+        let t = ((elevation + 0.1) / 0.4).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+
+    /// Scene brightness multiplier, see `FrameUniforms::light_intensity`
+    pub fn light_intensity(&self) -> f32 {
+        MOONLIGHT + (1.0 - MOONLIGHT) * self.daylight()
+    }
+
+    /// Linear RGBA sky color, used as the clear color
+    pub fn sky_color(&self) -> [f32; 4] {
+        let daylight = self.daylight();
+        // strongest while the sun crosses the horizon
+        let twilight = (1.0 - self.sun_direction()[1].abs() / 0.25).clamp(0.0, 1.0) * 0.6;
+        let mut color = [0.0, 0.0, 0.0, 1.0];
+        for i in 0..3 {
+            let sky = NIGHT_SKY[i] + (DAY_SKY[i] - NIGHT_SKY[i]) * daylight;
+            color[i] = sky + (TWILIGHT_SKY[i] - sky) * twilight;
+        }
+        color
+    }
+}
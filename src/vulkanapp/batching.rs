@@ -0,0 +1,61 @@
+use super::draw_list::{DrawCommand, Geometry};
+use super::material::MaterialId;
+use super::picking::EntityId;
+
+/// Scene draws of the last recorded frame and the commands they took, see `VulkanApp::batch_stats`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BatchStats {
+    /// Draws added to the `Frame`
+    pub draws: u32,
+    /// Draw commands after merging
+    pub batches: u32,
+    pub pipeline_binds: u32,
+    /// Material descriptor set binds, 1 with bindless textures
+    pub material_binds: u32,
+}
+
+/// Consecutive draws recorded with one draw command
+pub(super) struct Batch {
+    /// Draw buffer index of the first draw, the others are its instances and read the `DrawData` that follow
+    pub first_draw: u32,
+    pub instance_count: u32,
+    pub geometry: Geometry,
+    pub material: MaterialId,
+    pub entity: Option<EntityId>,
+}
+
+/// Stable sort key that groups draws by pipeline, then material, then vertex and index buffers
+pub(super) fn sort_key(draw: &DrawCommand, fragment_shader: &'static str) -> (&'static str, MaterialId, Geometry) {
+    (fragment_shader, draw.material, draw.geometry)
+}
+
+/// Merges neighbours of `draws`, given in draw buffer order, that only differ in their `DrawData`:
+/// draws of the same mesh become instances, immediate draws with adjacent vertices share the first draw's data
+/// which is the same for all of them
+pub(super) fn batches(draws: &[&DrawCommand]) -> Vec<Batch> {
+    let mut batches: Vec<Batch> = Vec::new();
+    for (i, draw) in draws.iter().enumerate() {
+        if let Some(batch) = batches.last_mut().filter(|b| b.material == draw.material && b.entity == draw.entity) {
+            match (&mut batch.geometry, draw.geometry) {
+                (Geometry::Mesh(mesh), Geometry::Mesh(next)) if *mesh == next => {
+                    batch.instance_count += 1;
+                    continue;
+                },
+                (Geometry::Immediate { first_vertex, vertex_count }, Geometry::Immediate { first_vertex: next, vertex_count: count })
+                    if *first_vertex + *vertex_count == next => {
+                    *vertex_count += count;
+                    continue;
+                },
+                _ => {},
+            }
+        }
+        batches.push(Batch {
+            first_draw: i as u32,
+            instance_count: 1,
+            geometry: draw.geometry,
+            material: draw.material,
+            entity: draw.entity,
+        });
+    }
+    batches
+}
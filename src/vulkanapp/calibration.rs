@@ -0,0 +1,50 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// User adjustments of the final output, applied in the fragment shader before encoding for the display
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayCalibration {
+    /// Exposure offset in stops, 0 keeps the brightness as is
+    pub exposure: f32,
+    /// Exponent applied to linear color, 1 keeps the response curve as is
+    pub gamma: f32,
+}
+
+impl Default for DisplayCalibration {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+impl DisplayCalibration {
+    pub const EXPOSURE_RANGE: (f32, f32) = (-4.0, 4.0);
+    pub const GAMMA_RANGE: (f32, f32) = (0.5, 2.0);
+
+    pub fn clamped(self) -> Self {
+        Self {
+            exposure: self.exposure.clamp(Self::EXPOSURE_RANGE.0, Self::EXPOSURE_RANGE.1),
+            gamma: self.gamma.clamp(Self::GAMMA_RANGE.0, Self::GAMMA_RANGE.1),
+        }
+    }
+
+    /// Reads a TOML file written by `save`. Missing keys keep their defaults, None if the file can't be read or parsed.
+    pub fn load(path: &str) -> Option<Self> {
+        let contents = std::fs::read_to_string(path).ok()?;
+        match toml::from_str::<Self>(&contents) {
+            Ok(calibration) => Some(calibration.clamped()),
+            Err(e) => {
+                warn!("Ignoring {}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let contents = toml::to_string(self).map_err(std::io::Error::other)?;
+        std::fs::write(path, contents)
+    }
+}
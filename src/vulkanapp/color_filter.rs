@@ -0,0 +1,49 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorVisionDeficiency {
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+/// Accessibility filter applied to the final color, after calibration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorFilter {
+    #[default]
+    None,
+    /// Shows how the image looks with the deficiency, for checking palettes
+    Simulate(ColorVisionDeficiency),
+    /// Shifts colors the deficiency can't distinguish into ones it can (daltonization)
+    Compensate(ColorVisionDeficiency),
+}
+
+impl ColorFilter {
+    pub const ALL: [ColorFilter; 7] = [
+        ColorFilter::None,
+        ColorFilter::Simulate(ColorVisionDeficiency::Protanopia),
+        ColorFilter::Simulate(ColorVisionDeficiency::Deuteranopia),
+        ColorFilter::Simulate(ColorVisionDeficiency::Tritanopia),
+        ColorFilter::Compensate(ColorVisionDeficiency::Protanopia),
+        ColorFilter::Compensate(ColorVisionDeficiency::Deuteranopia),
+        ColorFilter::Compensate(ColorVisionDeficiency::Tritanopia),
+    ];
+
+    /// Value of `colorFilter` in the `FrameUniforms` block of output.frag
+    pub fn shader_value(self) -> u32 {
+        let deficiency = |d: ColorVisionDeficiency| match d {
+            ColorVisionDeficiency::Protanopia => 1,
+            ColorVisionDeficiency::Deuteranopia => 2,
+            ColorVisionDeficiency::Tritanopia => 3,
+        };
+        match self {
+            ColorFilter::None => 0,
+            ColorFilter::Simulate(d) => deficiency(d),
+            ColorFilter::Compensate(d) => 3 + deficiency(d),
+        }
+    }
+
+    /// Next filter in `ALL`, wraps around to None
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|f| *f == self).unwrap();
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
@@ -0,0 +1,82 @@
+use super::surface_format::DisplayColorSpace;
+use super::calibration::DisplayCalibration;
+use super::color_filter::ColorFilter;
+use super::debug_config::DebugConfig;
+use super::debug_view::DebugView;
+use super::gpu_selection::GpuSelector;
+use super::device_profile::DeviceProfile;
+use super::post_process::{AntiAliasing, PostProcessStage, Tonemapper};
+
+/// Renderer settings chosen by the application
+#[derive(Debug, Clone)]
+pub struct RendererConfig {
+    /// Desired number of swapchain images (3 for triple buffering), clamped to the surface capabilities.
+    /// None requests one more than the surface minimum.
+    pub swapchain_image_count: Option<u32>,
+
+    /// Present with FIFO instead of preferring MAILBOX. None follows the device profile.
+    pub vsync: Option<bool>,
+
+    /// Overrides the profile detected from the selected GPU
+    pub device_profile: Option<DeviceProfile>,
+
+    /// Requested output color space, falls back to Sdr if the surface doesn't offer it
+    pub color_space: DisplayColorSpace,
+
+    /// Exposure and gamma offsets, can be changed later with `VulkanApp::set_display_calibration`
+    pub calibration: DisplayCalibration,
+
+    /// Colorblind simulation or compensation, can be changed later with `VulkanApp::set_color_filter`
+    pub color_filter: ColorFilter,
+
+    /// Operator of the built-in tonemap stage, can be changed later with `VulkanApp::set_tonemapper`
+    pub tonemapper: Tonemapper,
+
+    /// Stages run on the linear HDR image between the built-in tonemap and output stages
+    pub post_process: Vec<PostProcessStage>,
+
+    /// Wireframe or shader input visualization, can be changed later with `VulkanApp::set_debug_view`
+    pub debug_view: DebugView,
+
+    /// Anti-aliasing stage added to the post-processing chain
+    pub anti_aliasing: AntiAliasing,
+
+    /// Samples per pixel of the main pass, 1 disables MSAA. Lowered to what the device supports.
+    /// Depth readback is unavailable with MSAA.
+    pub msaa: u32,
+
+    /// Mesh draws whose origin is farther from the camera, in world units, are skipped. Immediate draws are always drawn.
+    pub render_distance: Option<f32>,
+
+    /// Device to use instead of the best scoring one, if it's usable
+    pub gpu: Option<GpuSelector>,
+
+    /// Reorders the scene draws by pipeline, material and mesh so more of them merge into one draw command.
+    /// Overlapping draws at the same depth may then cover each other in a different order.
+    pub sort_draws: bool,
+
+    /// Validation layer and debug messenger settings, used only at instance creation
+    pub debug: DebugConfig,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            swapchain_image_count: None,
+            vsync: None,
+            device_profile: None,
+            color_space: DisplayColorSpace::Sdr,
+            calibration: DisplayCalibration::default(),
+            color_filter: ColorFilter::None,
+            tonemapper: Tonemapper::None,
+            post_process: Vec::new(),
+            anti_aliasing: AntiAliasing::None,
+            msaa: 1,
+            render_distance: None,
+            debug_view: DebugView::Shaded,
+            gpu: None,
+            sort_draws: false,
+            debug: DebugConfig::default(),
+        }
+    }
+}
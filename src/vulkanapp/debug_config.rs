@@ -0,0 +1,97 @@
+use std::sync::Arc;
+
+use ash::vk;
+use log::warn;
+
+/// Validation message passed to `DebugConfig::callback`
+#[derive(Debug)]
+pub struct DebugMessage<'a> {
+    pub severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    pub id_number: i32,
+    pub id_name: &'a str,
+    pub message: &'a str,
+}
+
+/// Called for every message that passed the filters. Returning true marks the message as handled, so it is not logged.
+pub type DebugCallback = Arc<dyn Fn(&DebugMessage) -> bool + Send + Sync>;
+
+/// Validation layer and debug messenger settings
+#[derive(Clone)]
+pub struct DebugConfig {
+    /// Enables the Khronos validation layer, the debug messenger and object names
+    pub validation: bool,
+    /// Severities delivered to the messenger
+    pub severities: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_types: vk::DebugUtilsMessageTypeFlagsEXT,
+    /// GPU-assisted validation through VK_EXT_validation_features, slow
+    pub gpu_assisted: bool,
+    /// Best practices warnings through VK_EXT_validation_features
+    pub best_practices: bool,
+    /// Messages with these ids are dropped
+    pub muted_message_ids: Vec<i32>,
+    pub callback: Option<DebugCallback>,
+}
+
+impl Default for DebugConfig {
+    /// Validation in debug builds, warnings and errors only, muted ids from VK_MUTE_MESSAGE_IDS
+    fn default() -> Self {
+        Self {
+            validation: cfg!(debug_assertions),
+            severities: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            message_types: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            gpu_assisted: false,
+            best_practices: false,
+            muted_message_ids: muted_message_ids_from_env(),
+            callback: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for DebugConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DebugConfig")
+            .field("validation", &self.validation)
+            .field("severities", &self.severities)
+            .field("message_types", &self.message_types)
+            .field("gpu_assisted", &self.gpu_assisted)
+            .field("best_practices", &self.best_practices)
+            .field("muted_message_ids", &self.muted_message_ids)
+            .field("callback", &self.callback.is_some())
+            .finish()
+    }
+}
+
+impl DebugConfig {
+    pub fn uses_validation_features(&self) -> bool {
+        self.validation && (self.gpu_assisted || self.best_practices)
+    }
+
+    /// Features for VkValidationFeaturesEXT
+    pub fn enabled_validation_features(&self) -> Vec<vk::ValidationFeatureEnableEXT> {
+        let mut features = Vec::new();
+        if self.gpu_assisted {
+            features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED);
+            features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED_RESERVE_BINDING_SLOT);
+        }
+        if self.best_practices {
+            features.push(vk::ValidationFeatureEnableEXT::BEST_PRACTICES);
+        }
+        features
+    }
+}
+
+/// Comma separated ids from VK_MUTE_MESSAGE_IDS, decimal or 0x prefixed hex
+fn muted_message_ids_from_env() -> Vec<i32> {
+    let ids = std::env::var("VK_MUTE_MESSAGE_IDS").unwrap_or_default();
+    ids.split(',').map(str::trim).filter(|id| !id.is_empty()).filter_map(|id| {
+        let parsed = match id.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).map(|id| id as i32).ok(),
+            None => id.parse::<i32>().ok(),
+        };
+        if parsed.is_none() {
+            warn!("Ignoring invalid message id {} in VK_MUTE_MESSAGE_IDS", id);
+        }
+        parsed
+    }).collect()
+}
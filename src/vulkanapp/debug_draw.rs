@@ -0,0 +1,65 @@
+use std::mem;
+
+use ash::vk;
+use glam::Vec2;
+
+use super::draw_list::Camera2D;
+
+/// Line list vertex of `PipelineKey::DEBUG_LINE`
+#[repr(C)]
+pub(super) struct DebugVertex {
+    pub position: [f32; 3],
+    /// sRGB with straight alpha
+    pub color: [f32; 4],
+}
+
+crate::vertex_format!(DebugVertex {
+    position: vk::Format::R32G32B32_SFLOAT,
+    color: vk::Format::R32G32B32A32_SFLOAT,
+});
+
+/// Floats per `DebugVertex`
+const DEBUG_VERTEX_FLOATS: usize = 7;
+
+/// Initial size of each in-flight frame's debug vertex buffer, room for 1024 lines
+pub(super) const INITIAL_BUFFER_SIZE: vk::DeviceSize = (1024 * 2 * mem::size_of::<DebugVertex>()) as vk::DeviceSize;
+
+/// Immediate-mode lines in world units, drawn over the scene without depth testing and not pickable.
+/// Collected per `Frame`, so nothing persists to the next frame.
+#[derive(Debug, Clone, Default)]
+pub struct DebugDraw {
+    vertices: Vec<f32>,
+}
+
+impl DebugDraw {
+    /// `color` is sRGB with alpha
+    pub fn line(&mut self, a: [f32; 2], b: [f32; 2], color: [f32; 4]) {
+        for [x, y] in [a, b] {
+            self.vertices.extend_from_slice(&[x, y, 0.0]);
+            self.vertices.extend_from_slice(&color);
+        }
+    }
+
+    /// Outline of the axis aligned box between the corners `min` and `max`
+    pub fn aabb(&mut self, min: [f32; 2], max: [f32; 2], color: [f32; 4]) {
+        let corners = [min, [max[0], min[1]], max, [min[0], max[1]]];
+        for i in 0..4 {
+            self.line(corners[i], corners[(i + 1) % 4], color);
+        }
+    }
+
+    pub(super) fn vertex_count(&self) -> u32 {
+        (self.vertices.len() / DEBUG_VERTEX_FLOATS) as u32
+    }
+
+    /// Vertices in normalized device coordinates
+    pub(super) fn vertex_data(&self, camera: &Camera2D) -> Vec<f32> {
+        let mut vertices = self.vertices.clone();
+        let ndc = camera.matrix();
+        for vertex in vertices.chunks_exact_mut(DEBUG_VERTEX_FLOATS) {
+            let position = ndc.transform_point2(Vec2::new(vertex[0], vertex[1]));
+            vertex[..2].copy_from_slice(&position.to_array());
+        }
+        vertices
+    }
+}
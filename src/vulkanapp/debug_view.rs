@@ -0,0 +1,34 @@
+/// Alternative renderings of the scene for inspecting geometry and shader inputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DebugView {
+    #[default]
+    Shaded,
+    /// Triangle edges only, needs the `fillModeNonSolid` device feature
+    Wireframe,
+    /// Texture coordinates as red and green
+    TexCoords,
+    /// Depth buffer value, near is black
+    Depth,
+    /// `FrameUniforms::light_intensity` as gray
+    LightLevel,
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 5] = [DebugView::Shaded, DebugView::Wireframe, DebugView::TexCoords, DebugView::Depth, DebugView::LightLevel];
+
+    /// Value of `debugView` in the `FrameUniforms` block of shader.frag, the wireframe is shaded normally
+    pub fn shader_value(self) -> u32 {
+        match self {
+            DebugView::Shaded | DebugView::Wireframe => 0,
+            DebugView::TexCoords => 1,
+            DebugView::Depth => 2,
+            DebugView::LightLevel => 3,
+        }
+    }
+
+    /// Next view in `ALL`, wraps around to Shaded
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|v| *v == self).unwrap();
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
@@ -0,0 +1,111 @@
+use ash::prelude::VkResult;
+use ash::vk;
+
+use super::resourceManager::{BufferHandle, ResourceManager};
+
+pub const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+/// Side length of the square region copied around the requested pixel
+pub const DEPTH_READBACK_SIZE: u32 = 5;
+
+#[derive(Debug)]
+pub struct DepthReadback {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+
+    /// Row-major, `width * height` values in the [0, 1] depth range
+    pub depths: Vec<f32>,
+}
+
+impl DepthReadback {
+    /// Depth at framebuffer coordinates, None if outside of the copied region
+    pub fn depth_at(&self, x: u32, y: u32) -> Option<f32> {
+        if x < self.x || y < self.y || x >= self.x + self.width || y >= self.y + self.height {
+            return None;
+        }
+        Some(self.depths[((y - self.y) * self.width + (x - self.x)) as usize])
+    }
+}
+
+/// Readback buffer of a single in-flight frame and the region that was copied into it
+pub struct DepthReadbackSlot {
+    buffer: BufferHandle,
+    pending: Option<vk::Rect2D>,
+}
+
+impl DepthReadbackSlot {
+    /// `index` is the in-flight frame, used only in the debug name
+    pub fn new(resource_manager: &mut ResourceManager, index: usize) -> Self {
+        let size = (DEPTH_READBACK_SIZE * DEPTH_READBACK_SIZE * 4) as vk::DeviceSize;
+        let buffer = resource_manager.create_readback_buffer(size);
+        resource_manager.set_buffer_name(&buffer, &format!("depth_readback_buffer[{}]", index));
+        Self {
+            buffer,
+            pending: None,
+        }
+    }
+
+    /// Records a copy of the region around `pixel`. `depth_image` must be in TRANSFER_SRC_OPTIMAL layout.
+    pub fn cmd_copy(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, resource_manager: &ResourceManager, depth_image: vk::Image, extent: vk::Extent2D, pixel: (u32, u32)) {
+        let (x, y) = pixel;
+        let half = DEPTH_READBACK_SIZE / 2;
+        let x = x.min(extent.width.saturating_sub(1));
+        let y = y.min(extent.height.saturating_sub(1));
+        let x0 = x.saturating_sub(half);
+        let y0 = y.saturating_sub(half);
+        let width = (x + half + 1).min(extent.width) - x0;
+        let height = (y + half + 1).min(extent.height) - y0;
+
+        let copy_region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(width)
+            .buffer_image_height(height)
+            .image_subresource(vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                .mip_level(0)
+                .base_array_layer(0)
+                .layer_count(1)
+                .build())
+            .image_offset(vk::Offset3D { x: x0 as i32, y: y0 as i32, z: 0 })
+            .image_extent(vk::Extent3D { width, height, depth: 1 });
+
+        unsafe {
+            device.cmd_copy_image_to_buffer(command_buffer, depth_image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, resource_manager.buffer(&self.buffer), &[copy_region.build()]);
+
+            //make transfer write visible to host read
+            let buffer_memory_barrier = vk::BufferMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::HOST_READ)
+                .buffer(resource_manager.buffer(&self.buffer))
+                .offset(0)
+                .size(vk::WHOLE_SIZE);
+            device.cmd_pipeline_barrier(command_buffer, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::HOST, vk::DependencyFlags::empty(), &[], &[buffer_memory_barrier.build()], &[]);
+        }
+
+        self.pending = Some(vk::Rect2D {
+            offset: vk::Offset2D { x: x0 as i32, y: y0 as i32 },
+            extent: vk::Extent2D { width, height },
+        });
+    }
+
+    /// Must be called only after the fence of the frame that recorded the copy is signaled
+    pub fn take_result(&mut self, resource_manager: &ResourceManager) -> VkResult<Option<DepthReadback>> {
+        let region = match self.pending.take() {
+            Some(region) => region,
+            None => return Ok(None),
+        };
+
+        let count = (region.extent.width * region.extent.height) as usize;
+        let depths = resource_manager.read_buffer::<f32>(&self.buffer, count)?;
+
+        Ok(Some(DepthReadback {
+            x: region.offset.x as u32,
+            y: region.offset.y as u32,
+            width: region.extent.width,
+            height: region.extent.height,
+            depths,
+        }))
+    }
+}
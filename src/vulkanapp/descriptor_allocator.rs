@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use ash::vk;
+use log::debug;
+
+use super::resourceManager::ResourceManager;
+
+/// Sets of the first pool, every new pool holds twice as many as the previous one
+const INITIAL_SETS_PER_POOL: u32 = 16;
+
+/// Descriptors of each type a pool reserves per set. Sets of this renderer hold at most one of each,
+/// except the GPU culling sets with four storage buffers.
+const DESCRIPTORS_PER_SET: [(vk::DescriptorType, u32); 3] = [
+    (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1),
+    (vk::DescriptorType::UNIFORM_BUFFER, 1),
+    (vk::DescriptorType::STORAGE_BUFFER, 4),
+];
+
+/// Allocates descriptor sets of any layout, adding a larger pool whenever the existing ones run out.
+/// Lives as long as the device, so sets of swapchain sized resources are freed and allocated again on recreation.
+pub(super) struct DescriptorAllocator {
+    pools: Vec<vk::DescriptorPool>,
+    sets_per_pool: u32,
+    /// Pool of every allocated set, for `free`
+    owners: HashMap<vk::DescriptorSet, vk::DescriptorPool>,
+}
+
+impl DescriptorAllocator {
+    pub fn new() -> Self {
+        Self {
+            pools: Vec::new(),
+            sets_per_pool: INITIAL_SETS_PER_POOL,
+            owners: HashMap::new(),
+        }
+    }
+
+    /// Tries the newest pools first, older ones only have room where sets were freed
+    pub fn allocate(&mut self, device: &ash::Device, resource_manager: &ResourceManager, layout: vk::DescriptorSetLayout) -> vk::DescriptorSet {
+        for pool in self.pools.iter().rev() {
+            let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(*pool)
+                .set_layouts(std::slice::from_ref(&layout));
+            match unsafe { device.allocate_descriptor_sets(&allocate_info) } {
+                Ok(sets) => {
+                    self.owners.insert(sets[0], *pool);
+                    return sets[0];
+                },
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY) | Err(vk::Result::ERROR_FRAGMENTED_POOL) => {},
+                Err(e) => panic!("Failed to allocate descriptor set: {}", e),
+            }
+        }
+
+        let pool = self.create_pool(device, resource_manager);
+        let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(std::slice::from_ref(&layout));
+        let set = unsafe { device.allocate_descriptor_sets(&allocate_info).unwrap()[0] };
+        self.owners.insert(set, pool);
+        set
+    }
+
+    /// `sets` must not be in use by the GPU anymore
+    pub fn free(&mut self, device: &ash::Device, sets: &[vk::DescriptorSet]) {
+        for set in sets {
+            let pool = self.owners.remove(set).unwrap_or_else(|| panic!("Descriptor set {:?} was not allocated here", set));
+            unsafe { device.free_descriptor_sets(pool, std::slice::from_ref(set)).unwrap() };
+        }
+    }
+
+    fn create_pool(&mut self, device: &ash::Device, resource_manager: &ResourceManager) -> vk::DescriptorPool {
+        if !self.pools.is_empty() {
+            self.sets_per_pool *= 2;
+        }
+        debug!("Adding descriptor pool [{}] for {} sets", self.pools.len(), self.sets_per_pool);
+        let pool_sizes = DESCRIPTORS_PER_SET.map(|(ty, count)| vk::DescriptorPoolSize { ty, descriptor_count: count * self.sets_per_pool });
+        let pool_create_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+            .max_sets(self.sets_per_pool)
+            .pool_sizes(&pool_sizes);
+        let pool = unsafe { device.create_descriptor_pool(&pool_create_info, None).unwrap() };
+        resource_manager.set_name(pool, &format!("descriptor_pool[{}]", self.pools.len()));
+        self.pools.push(pool);
+        pool
+    }
+
+    /// Frees every set that is still allocated
+    pub fn destroy(self, device: &ash::Device) {
+        for pool in self.pools {
+            unsafe { device.destroy_descriptor_pool(pool, None) };
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use ash::vk;
+
+/// DMI product names of the Steam Deck LCD and OLED models
+const STEAM_DECK_PRODUCT_NAMES: [&str; 2] = ["Jupiter", "Galileo"];
+
+/// Substrings of the Steam Deck APU device name, as reported by RADV and the proprietary driver
+const STEAM_DECK_DEVICE_NAMES: [&str; 3] = ["VANGOGH", "SEPHIROTH", "AMD Custom GPU 0405"];
+
+/// Tuned defaults for known hardware, used where `RendererConfig` leaves a setting unset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceProfile {
+    Desktop,
+    /// Steam Deck and similar handhelds: battery powered, fixed 60 Hz panel
+    Handheld,
+    /// Integrated GPU sharing power and memory bandwidth with the CPU
+    Integrated,
+}
+
+impl DeviceProfile {
+    pub fn detect(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> Self {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        let name = unsafe { std::ffi::CStr::from_ptr(properties.device_name.as_ptr()) }.to_string_lossy();
+
+        // the DMI product name is more reliable, the APU name alone may change between driver versions
+        let product_name = std::fs::read_to_string("/sys/devices/virtual/dmi/id/product_name").unwrap_or_default();
+        if STEAM_DECK_PRODUCT_NAMES.contains(&product_name.trim()) || STEAM_DECK_DEVICE_NAMES.iter().any(|n| name.contains(n)) {
+            DeviceProfile::Handheld
+        } else if properties.device_type == vk::PhysicalDeviceType::INTEGRATED_GPU {
+            DeviceProfile::Integrated
+        } else {
+            DeviceProfile::Desktop
+        }
+    }
+
+    /// FIFO instead of MAILBOX, so the GPU doesn't render frames that are never shown
+    pub fn vsync(self) -> bool {
+        self != DeviceProfile::Desktop
+    }
+
+    /// Frame rate cap applied at startup
+    pub fn frame_rate_cap(self) -> Option<f32> {
+        match self {
+            // half the panel's refresh rate saves battery, with FIFO every frame is shown for exactly two vblanks
+            DeviceProfile::Handheld => Some(30.0),
+            DeviceProfile::Integrated | DeviceProfile::Desktop => None,
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use ash::vk;
+use glam::{Affine2, Vec2, Vec4};
+
+use super::picking::EntityId;
+use super::material::MaterialId;
+use super::mesh::MeshHandle;
+use super::vertex::Vertex;
+use super::debug_draw::DebugDraw;
+use super::text::TextDraw;
+#[cfg(feature = "egui")]
+use super::gui_renderer::GuiOutput;
+
+/// Initial size of each in-flight frame's draw buffer, room for 256 draws
+pub(super) const INITIAL_DRAW_BUFFER_SIZE: vk::DeviceSize = (256 * std::mem::size_of::<DrawData>()) as vk::DeviceSize;
+
+/// Linear clear color of frames that don't set one, (0.8, 0.4, 0.7) once sRGB encoded
+const DEFAULT_CLEAR_COLOR: [f32; 4] = [0.6038, 0.1329, 0.448, 1.0];
+
+/// Triangle list in world units, three vertices per triangle
+#[derive(Debug, Clone, Default)]
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+}
+
+impl Mesh {
+    pub fn new(vertices: Vec<Vertex>) -> Self {
+        assert!(vertices.len().is_multiple_of(3), "Mesh must be a list of whole triangles");
+        Self { vertices }
+    }
+}
+
+/// Applied to mesh positions in order scale, rotation, translation
+#[derive(Debug, Clone, Copy)]
+pub struct Transform2D {
+    pub translation: Vec2,
+    /// Radians, counter-clockwise
+    pub rotation: f32,
+    pub scale: Vec2,
+}
+
+impl Default for Transform2D {
+    fn default() -> Self {
+        Self {
+            translation: Vec2::ZERO,
+            rotation: 0.0,
+            scale: Vec2::ONE,
+        }
+    }
+}
+
+impl Transform2D {
+    pub(super) fn matrix(&self) -> Affine2 {
+        Affine2::from_scale_angle_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+/// Maps world units to normalized device coordinates, a zoom of 1 shows world [-1, 1] on both axes
+#[derive(Debug, Clone, Copy)]
+pub struct Camera2D {
+    pub position: Vec2,
+    pub zoom: f32,
+}
+
+impl Camera2D {
+    /// World units to normalized device coordinates
+    pub(super) fn matrix(&self) -> Affine2 {
+        Affine2::from_scale(Vec2::splat(self.zoom)) * Affine2::from_translation(-self.position)
+    }
+}
+
+impl Default for Camera2D {
+    fn default() -> Self {
+        Self {
+            position: Vec2::ZERO,
+            zoom: 1.0,
+        }
+    }
+}
+
+/// Axis aligned textured rectangle
+#[derive(Debug, Clone, Copy)]
+pub struct Sprite {
+    /// Center in world units
+    pub position: Vec2,
+    pub size: Vec2,
+    pub depth: f32,
+    pub uv_min: Vec2,
+    pub uv_max: Vec2,
+}
+
+impl Default for Sprite {
+    fn default() -> Self {
+        Self {
+            position: Vec2::ZERO,
+            size: Vec2::ONE,
+            depth: 0.0,
+            uv_min: Vec2::ZERO,
+            uv_max: Vec2::ONE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) enum Geometry {
+    /// Vertices of the frame, already transformed to world units
+    Immediate { first_vertex: u32, vertex_count: u32 },
+    /// Uploaded with `VulkanApp::upload_mesh`
+    Mesh(MeshHandle),
+}
+
+pub(super) struct DrawCommand {
+    pub geometry: Geometry,
+    /// Model to world units, applied on the GPU. Identity for immediate geometry.
+    pub model: Affine2,
+    pub material: MaterialId,
+    pub entity: Option<EntityId>,
+}
+
+/// Per-draw data of the scene vertex shaders, std430 like `DrawData` in shader.vert.
+/// Read at the draw's first instance.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub(super) struct DrawData {
+    /// Rows of the affine matrix from positions to normalized device coordinates, w unused
+    pub ndc_rows: [Vec4; 2],
+    /// Device address of the draw's vertices for vert_pulling.spv, 0 otherwise
+    pub vertices: vk::DeviceAddress,
+    pub _padding: [u32; 2],
+}
+
+crate::assert_block_size!(DrawData, 48);
+
+/// Everything drawn in one frame, built by the application and consumed by `VulkanApp::draw_frame`
+#[derive(Default)]
+pub struct Frame {
+    camera: Camera2D,
+    /// World space vertices of the immediate draws, the camera is applied on the GPU
+    vertices: Vec<Vertex>,
+    draws: Vec<DrawCommand>,
+    /// Linear RGBA, None keeps `DEFAULT_CLEAR_COLOR`
+    clear_color: Option<[f32; 4]>,
+    debug_draw: DebugDraw,
+    hud: TextDraw,
+    #[cfg(feature = "egui")]
+    gui: Option<GuiOutput>,
+}
+
+impl Frame {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Camera of the whole frame, can be set before or after the draws
+    pub fn set_camera(&mut self, camera: Camera2D) {
+        self.camera = camera;
+    }
+
+    /// Background of the frame, linear RGBA
+    pub fn set_clear_color(&mut self, color: [f32; 4]) {
+        self.clear_color = Some(color);
+    }
+
+    /// Lines drawn over this frame, in the frame's camera
+    pub fn debug_draw(&mut self) -> &mut DebugDraw {
+        &mut self.debug_draw
+    }
+
+    pub(super) fn debug_lines(&self) -> &DebugDraw {
+        &self.debug_draw
+    }
+
+    /// Screen space text drawn over this frame after post-processing
+    pub fn hud(&mut self) -> &mut TextDraw {
+        &mut self.hud
+    }
+
+    pub(super) fn hud_text(&self) -> &TextDraw {
+        &self.hud
+    }
+
+    /// GUI drawn over everything else, its texture changes are applied even if the GUI is not drawn again
+    #[cfg(feature = "egui")]
+    pub fn set_gui(&mut self, gui: GuiOutput) {
+        self.gui = Some(gui);
+    }
+
+    #[cfg(feature = "egui")]
+    pub(super) fn gui(&self) -> Option<&GuiOutput> {
+        self.gui.as_ref()
+    }
+
+    pub(super) fn camera(&self) -> &Camera2D {
+        &self.camera
+    }
+
+    pub(super) fn clear_color(&self) -> [f32; 4] {
+        self.clear_color.unwrap_or(DEFAULT_CLEAR_COLOR)
+    }
+
+    /// `entity` is written to the object id attachment for picking, None draws an unpickable object
+    pub fn draw_mesh(&mut self, mesh: &Mesh, material: MaterialId, transform: Transform2D, entity: Option<EntityId>) {
+        let first_vertex = self.vertices.len() as u32;
+        let model = transform.matrix();
+        self.vertices.extend(mesh.vertices.iter().map(|vertex| {
+            let xy = model.transform_point2(vertex.position.truncate());
+            Vertex { position: xy.extend(vertex.position.z), ..*vertex }
+        }));
+        self.push_draw(first_vertex, material, entity);
+    }
+
+    pub fn draw_sprite(&mut self, sprite: &Sprite, material: MaterialId, entity: Option<EntityId>) {
+        let first_vertex = self.vertices.len() as u32;
+        let (min, max) = (sprite.position - sprite.size / 2.0, sprite.position + sprite.size / 2.0);
+        let corner = |x: usize, y: usize| Vertex {
+            position: Vec2::new([min.x, max.x][x], [min.y, max.y][y]).extend(sprite.depth),
+            tex_coord: Vec2::new([sprite.uv_min.x, sprite.uv_max.x][x], [sprite.uv_min.y, sprite.uv_max.y][y]),
+        };
+        for (x, y) in [(0, 0), (1, 0), (1, 1), (0, 0), (1, 1), (0, 1)] {
+            self.vertices.push(corner(x, y));
+        }
+        self.push_draw(first_vertex, material, entity);
+    }
+
+    /// Draws a mesh uploaded with `VulkanApp::upload_mesh`, transformed on the GPU
+    pub fn draw(&mut self, mesh: MeshHandle, material: MaterialId, transform: Transform2D, entity: Option<EntityId>) {
+        self.draw_model(mesh, material, transform.matrix(), entity);
+    }
+
+    pub(super) fn draw_model(&mut self, mesh: MeshHandle, material: MaterialId, model: Affine2, entity: Option<EntityId>) {
+        self.draws.push(DrawCommand { geometry: Geometry::Mesh(mesh), model, material, entity });
+    }
+
+    fn push_draw(&mut self, first_vertex: u32, material: MaterialId, entity: Option<EntityId>) {
+        let vertex_count = self.vertices.len() as u32 - first_vertex;
+        if vertex_count > 0 {
+            let geometry = Geometry::Immediate { first_vertex, vertex_count };
+            self.draws.push(DrawCommand { geometry, model: Affine2::IDENTITY, material, entity });
+        }
+    }
+
+    /// World space vertices of the immediate draws
+    pub(super) fn vertex_data(&self) -> &[Vertex] {
+        &self.vertices
+    }
+
+    /// `DrawData` of `draw` without the vertex address
+    pub(super) fn draw_data(&self, draw: &DrawCommand) -> DrawData {
+        let ndc = self.camera.matrix() * draw.model;
+        let (x_axis, y_axis, translation) = (ndc.matrix2.x_axis, ndc.matrix2.y_axis, ndc.translation);
+        DrawData {
+            ndc_rows: [
+                Vec4::new(x_axis.x, y_axis.x, translation.x, 0.0),
+                Vec4::new(x_axis.y, y_axis.y, translation.y, 0.0),
+            ],
+            vertices: 0,
+            _padding: [0; 2],
+        }
+    }
+
+    pub(super) fn draws(&self) -> &[DrawCommand] {
+        &self.draws
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Number of frames kept for the rolling averages
+const TIMING_WINDOW: usize = 120;
+
+/// Sleep granularity of the OS scheduler, the rest of the wait is spun
+const SPIN_THRESHOLD: Duration = Duration::from_millis(2);
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameTimingReport {
+    /// Time between the starts of consecutive frames, includes pacing
+    pub frame_ms: f64,
+    /// Time spent inside `draw_frame`, excluding pacing
+    pub cpu_ms: f64,
+    /// GPU time of the whole frame, None if no timestamps were collected yet
+    pub gpu_ms: Option<f64>,
+    /// Time from present until the CPU saw the frame's fence signaled, an upper bound of the real latency
+    pub present_latency_ms: f64,
+}
+
+/// Average CPU time of a named stage, summed over each frame
+#[derive(Debug, Clone, Copy)]
+pub struct CpuStageReport {
+    pub name: &'static str,
+    pub avg_ms: f64,
+}
+
+struct RollingAverage {
+    samples: VecDeque<f64>,
+}
+
+impl RollingAverage {
+    fn new() -> Self {
+        Self { samples: VecDeque::with_capacity(TIMING_WINDOW) }
+    }
+
+    fn push(&mut self, sample: f64) {
+        if self.samples.len() == TIMING_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    fn average(&self) -> f64 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        self.samples.iter().sum::<f64>() / self.samples.len() as f64
+    }
+}
+
+/// CPU side frame timing and optional frame rate cap
+pub struct FrameTimer {
+    frame_rate_cap: Option<f32>,
+
+    frame_start: Option<Instant>,
+    work_start: Instant,
+    /// Present time of the frame that last used each in-flight slot
+    presented_at: Vec<Option<Instant>>,
+
+    frame_ms: RollingAverage,
+    cpu_ms: RollingAverage,
+    present_latency_ms: RollingAverage,
+
+    /// Stages recorded since the last `begin_frame`, in order of first appearance
+    cur_stages: Vec<(&'static str, f64)>,
+    stages: Vec<(&'static str, RollingAverage)>,
+}
+
+impl FrameTimer {
+    pub fn new(in_flight_frames: usize) -> Self {
+        Self {
+            frame_rate_cap: None,
+
+            frame_start: None,
+            work_start: Instant::now(),
+            presented_at: vec![None; in_flight_frames],
+
+            frame_ms: RollingAverage::new(),
+            cpu_ms: RollingAverage::new(),
+            present_latency_ms: RollingAverage::new(),
+
+            cur_stages: Vec::new(),
+            stages: Vec::new(),
+        }
+    }
+
+    pub fn frame_rate_cap(&self) -> Option<f32> {
+        self.frame_rate_cap
+    }
+
+    /// None or a non-positive value removes the cap
+    pub fn set_frame_rate_cap(&mut self, cap: Option<f32>) {
+        self.frame_rate_cap = cap.filter(|fps| *fps > 0.0);
+    }
+
+    /// Waits until the frame rate cap allows the next frame to start
+    pub fn begin_frame(&mut self) {
+        if let (Some(fps), Some(prev_start)) = (self.frame_rate_cap, self.frame_start) {
+            let target = prev_start + Duration::from_secs_f32(1.0 / fps);
+            let now = Instant::now();
+            if target > now + SPIN_THRESHOLD {
+                std::thread::sleep(target - now - SPIN_THRESHOLD);
+            }
+            while Instant::now() < target {
+                std::hint::spin_loop();
+            }
+        }
+
+        for (name, ms) in self.cur_stages.drain(..) {
+            match self.stages.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, average)) => average.push(ms),
+                None => {
+                    let mut average = RollingAverage::new();
+                    average.push(ms);
+                    self.stages.push((name, average));
+                }
+            }
+        }
+
+        let now = Instant::now();
+        if let Some(prev_start) = self.frame_start {
+            self.frame_ms.push((now - prev_start).as_secs_f64() * 1000.0);
+        }
+        self.frame_start = Some(now);
+        self.work_start = now;
+    }
+
+    pub fn end_frame(&mut self) {
+        self.cpu_ms.push(self.work_start.elapsed().as_secs_f64() * 1000.0);
+    }
+
+    /// Adds `duration` to the named stage of the current frame, a stage may be recorded several times per frame
+    pub fn add_stage(&mut self, name: &'static str, duration: Duration) {
+        let ms = duration.as_secs_f64() * 1000.0;
+        match self.cur_stages.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, total)) => *total += ms,
+            None => self.cur_stages.push((name, ms)),
+        }
+    }
+
+    /// Ends the stage that started at `stage_start` and starts the next one
+    pub fn lap(&mut self, name: &'static str, stage_start: &mut Instant) {
+        let now = Instant::now();
+        self.add_stage(name, now - *stage_start);
+        *stage_start = now;
+    }
+
+    pub fn stage_report(&self) -> Vec<CpuStageReport> {
+        self.stages.iter().map(|(name, average)| CpuStageReport { name, avg_ms: average.average() }).collect()
+    }
+
+    pub fn presented(&mut self, in_flight_frame: usize) {
+        self.presented_at[in_flight_frame] = Some(Instant::now());
+    }
+
+    /// Call right after the fence of `in_flight_frame` was waited on
+    pub fn fence_signaled(&mut self, in_flight_frame: usize) {
+        if let Some(presented_at) = self.presented_at[in_flight_frame].take() {
+            self.present_latency_ms.push(presented_at.elapsed().as_secs_f64() * 1000.0);
+        }
+    }
+
+    /// Forgets frames in flight, their fences no longer exist after device recreation
+    pub fn reset_in_flight(&mut self) {
+        self.presented_at.iter_mut().for_each(|p| *p = None);
+    }
+
+    pub fn report(&self, gpu_ms: Option<f64>) -> FrameTimingReport {
+        FrameTimingReport {
+            frame_ms: self.frame_ms.average(),
+            cpu_ms: self.cpu_ms.average(),
+            gpu_ms,
+            present_latency_ms: self.present_latency_ms.average(),
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::mem;
+
+use ash::prelude::VkResult;
+use ash::vk;
+use glam::Vec2;
+use log::debug;
+
+use super::descriptor_allocator::DescriptorAllocator;
+use super::reflection;
+use super::resourceManager::{BufferHandle, ResourceManager};
+use super::shaders;
+
+/// Mesh draws each in-flight frame's buffers have room for at first, they grow on demand
+const INITIAL_DRAWS: usize = 256;
+
+/// Invocations per workgroup of cull.comp
+const WORKGROUP_SIZE: u32 = 64;
+
+/// One mesh draw tested by cull.comp, std430 like `CullDraw` there
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub(super) struct CullDraw {
+    /// Model space bounds of the mesh
+    pub bounds_min: Vec2,
+    pub bounds_max: Vec2,
+    /// Index in the draw buffer, the first instance of the indirect command
+    pub draw: u32,
+    pub batch: u32,
+    /// Indirect commands of the batch start here, one slot per draw of the batch
+    pub first_command: u32,
+    pub index_count: u32,
+}
+
+crate::assert_block_size!(CullDraw, 32);
+
+struct FrameBuffers {
+    cull_draws: BufferHandle,
+    /// `vk::DrawIndexedIndirectCommand` per draw, written by cull.comp
+    commands: BufferHandle,
+    /// Visible draws per batch, cleared before the dispatch
+    counts: BufferHandle,
+    descriptor_set: vk::DescriptorSet,
+}
+
+/// Culls the mesh draws of a frame against the view on the GPU, the visible ones of each batch are drawn
+/// with one `vkCmdDrawIndexedIndirectCount`. Needs VK_KHR_draw_indirect_count and the `drawIndirectFirstInstance` feature.
+pub(super) struct GpuCulling {
+    device: ash::Device,
+    loader: ash::extensions::khr::DrawIndirectCount,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    frames: Vec<FrameBuffers>,
+}
+
+impl GpuCulling {
+    pub fn new(instance: &ash::Instance, device: &ash::Device, resource_manager: &mut ResourceManager, descriptor_allocator: &mut DescriptorAllocator, in_flight_frames: usize) -> Self {
+        let shader_code = shaders::load_spirv("cull.spv");
+        let shader = reflection::reflect(&shader_code);
+        let descriptor_set_layout_bindings = reflection::descriptor_set_layout_bindings(&[&shader], 0);
+        let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&descriptor_set_layout_bindings);
+        let descriptor_set_layout = unsafe { device.create_descriptor_set_layout(&descriptor_set_layout_create_info, None).unwrap() };
+        resource_manager.set_name(descriptor_set_layout, "cull_descriptor_set_layout");
+
+        let push_constant_ranges = reflection::push_constant_ranges(&[&shader]);
+        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(std::slice::from_ref(&descriptor_set_layout))
+            .push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout = unsafe { device.create_pipeline_layout(&pipeline_layout_create_info, None).unwrap() };
+        resource_manager.set_name(pipeline_layout, "cull_pipeline_layout");
+
+        let shader_module_create_info = vk::ShaderModuleCreateInfo::builder()
+            .code(&shader_code);
+        let shader_module = unsafe { device.create_shader_module(&shader_module_create_info, None).unwrap() };
+        let pipeline_create_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::COMPUTE)
+                .module(shader_module)
+                .name(c"main")
+                .build())
+            .layout(pipeline_layout)
+            .build();
+        let pipeline = unsafe { device.create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_create_info], None).unwrap()[0] };
+        resource_manager.set_name(pipeline, "cull_pipeline");
+        unsafe { device.destroy_shader_module(shader_module, None) };
+
+        let frames = (0..in_flight_frames).map(|i| {
+            let descriptor_set = descriptor_allocator.allocate(device, resource_manager, descriptor_set_layout);
+            resource_manager.set_name(descriptor_set, &format!("cull_descriptor_set[{}]", i));
+            FrameBuffers {
+                cull_draws: create_buffer(resource_manager, BufferKind::CullDraws, INITIAL_DRAWS, i),
+                commands: create_buffer(resource_manager, BufferKind::Commands, INITIAL_DRAWS, i),
+                counts: create_buffer(resource_manager, BufferKind::Counts, INITIAL_DRAWS, i),
+                descriptor_set,
+            }
+        }).collect();
+
+        Self {
+            device: device.clone(),
+            loader: ash::extensions::khr::DrawIndirectCount::new(instance, device),
+            descriptor_set_layout,
+            pipeline_layout,
+            pipeline,
+            frames,
+        }
+    }
+
+    /// The descriptor sets go with the `DescriptorAllocator`
+    pub fn destroy(self, resource_manager: &mut ResourceManager) {
+        unsafe {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+        for frame in self.frames {
+            resource_manager.destroy_buffer(frame.cull_draws);
+            resource_manager.destroy_buffer(frame.commands);
+            resource_manager.destroy_buffer(frame.counts);
+        }
+    }
+
+    /// Writes `cull_draws` for the next `cmd_cull` of `in_flight_frame`, growing the buffers to `draw_count` commands
+    /// and `batch_count` counts. `draw_buffer` holds the `DrawData` the draws index.
+    pub fn upload(&mut self, resource_manager: &mut ResourceManager, in_flight_frame: usize, cull_draws: &[CullDraw],
+                  draw_count: usize, batch_count: usize, draw_buffer: &BufferHandle) -> VkResult<()> {
+        let frame = &mut self.frames[in_flight_frame];
+        for (kind, buffer, count) in [(BufferKind::CullDraws, &mut frame.cull_draws, cull_draws.len()), (BufferKind::Commands, &mut frame.commands, draw_count), (BufferKind::Counts, &mut frame.counts, batch_count)] {
+            let size = resource_manager.buffer_size(buffer);
+            if (count * kind.element_size()) as vk::DeviceSize > size {
+                let count = count.max(size as usize / kind.element_size() * 2);
+                debug!("Growing {} [{}] to {} elements", kind.name(), in_flight_frame, count);
+                let old_buffer = mem::replace(buffer, create_buffer(resource_manager, kind, count, in_flight_frame));
+                resource_manager.destroy_buffer(old_buffer);
+            }
+        }
+        if !cull_draws.is_empty() {
+            resource_manager.fill_buffer(&frame.cull_draws, cull_draws)?;
+        }
+
+        // the set is only used by this frame, which the caller waited for
+        let buffer_infos = [draw_buffer, &frame.cull_draws, &frame.commands, &frame.counts].map(|buffer| vk::DescriptorBufferInfo::builder()
+            .buffer(resource_manager.buffer(buffer))
+            .offset(0)
+            .range(vk::WHOLE_SIZE)
+            .build());
+        let writes: Vec<_> = buffer_infos.iter().enumerate().map(|(binding, buffer_info)| vk::WriteDescriptorSet::builder()
+            .dst_set(frame.descriptor_set)
+            .dst_binding(binding as u32)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(std::slice::from_ref(buffer_info))
+            .build()).collect();
+        unsafe { self.device.update_descriptor_sets(&writes, &[]) };
+        Ok(())
+    }
+
+    /// Clears the counts and runs cull.comp over the `cull_draw_count` draws of the last upload, outside a render pass.
+    /// The commands and counts are ready for `cmd_draw` afterwards.
+    pub fn cmd_cull(&self, command_buffer: vk::CommandBuffer, resource_manager: &ResourceManager, in_flight_frame: usize, cull_draw_count: u32) {
+        let device = &self.device;
+        let frame = &self.frames[in_flight_frame];
+        unsafe {
+            device.cmd_fill_buffer(command_buffer, resource_manager.buffer(&frame.counts), 0, vk::WHOLE_SIZE, 0);
+            let cleared = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+                .build();
+            device.cmd_pipeline_barrier(command_buffer, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(), &[cleared], &[], &[]);
+
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+            device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline_layout, 0, &[frame.descriptor_set], &[]);
+            device.cmd_push_constants(command_buffer, self.pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, &cull_draw_count.to_ne_bytes());
+            device.cmd_dispatch(command_buffer, cull_draw_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+
+            let culled = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::INDIRECT_COMMAND_READ)
+                .build();
+            device.cmd_pipeline_barrier(command_buffer, vk::PipelineStageFlags::COMPUTE_SHADER, vk::PipelineStageFlags::DRAW_INDIRECT,
+                vk::DependencyFlags::empty(), &[culled], &[], &[]);
+        }
+    }
+
+    /// Draws the visible draws of `batch`, `instance_count` of them at most. The batch's vertex and index buffers must be bound.
+    pub fn cmd_draw(&self, command_buffer: vk::CommandBuffer, resource_manager: &ResourceManager, in_flight_frame: usize, batch: u32, first_draw: u32, instance_count: u32) {
+        let frame = &self.frames[in_flight_frame];
+        let stride = mem::size_of::<vk::DrawIndexedIndirectCommand>();
+        unsafe {
+            self.loader.cmd_draw_indexed_indirect_count(command_buffer,
+                resource_manager.buffer(&frame.commands), first_draw as vk::DeviceSize * stride as vk::DeviceSize,
+                resource_manager.buffer(&frame.counts), batch as vk::DeviceSize * mem::size_of::<u32>() as vk::DeviceSize,
+                instance_count, stride as u32);
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum BufferKind {
+    CullDraws,
+    Commands,
+    Counts,
+}
+
+impl BufferKind {
+    fn element_size(self) -> usize {
+        match self {
+            BufferKind::CullDraws => mem::size_of::<CullDraw>(),
+            BufferKind::Commands => mem::size_of::<vk::DrawIndexedIndirectCommand>(),
+            BufferKind::Counts => mem::size_of::<u32>(),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            BufferKind::CullDraws => "cull_draw_buffer",
+            BufferKind::Commands => "indirect_command_buffer",
+            BufferKind::Counts => "indirect_count_buffer",
+        }
+    }
+
+    fn usage(self) -> vk::BufferUsageFlags {
+        match self {
+            BufferKind::CullDraws => vk::BufferUsageFlags::STORAGE_BUFFER,
+            BufferKind::Commands => vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::INDIRECT_BUFFER,
+            BufferKind::Counts => vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+        }
+    }
+}
+
+fn create_buffer(resource_manager: &mut ResourceManager, kind: BufferKind, count: usize, in_flight_frame: usize) -> BufferHandle {
+    let buffer = resource_manager.create_buffer((count.max(1) * kind.element_size()) as vk::DeviceSize, kind.usage());
+    resource_manager.set_buffer_name(&buffer, &format!("{}[{}]", kind.name(), in_flight_frame));
+    buffer
+}
@@ -0,0 +1,178 @@
+use ash::vk;
+use log::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuPreference {
+    /// LowPower on battery, HighPerformance otherwise
+    Auto,
+    HighPerformance,
+    LowPower,
+}
+
+impl GpuPreference {
+    fn device_type_order(self) -> [vk::PhysicalDeviceType; 3] {
+        let resolved = match self {
+            GpuPreference::Auto => match on_battery_power() {
+                Some(true) => GpuPreference::LowPower,
+                _ => GpuPreference::HighPerformance,
+            },
+            preference => preference,
+        };
+
+        match resolved {
+            GpuPreference::LowPower => [vk::PhysicalDeviceType::INTEGRATED_GPU, vk::PhysicalDeviceType::DISCRETE_GPU, vk::PhysicalDeviceType::CPU],
+            _ => [vk::PhysicalDeviceType::DISCRETE_GPU, vk::PhysicalDeviceType::INTEGRATED_GPU, vk::PhysicalDeviceType::CPU],
+        }
+    }
+}
+
+/// Returns None if the power source can't be determined (desktop, non-linux platforms)
+pub fn on_battery_power() -> Option<bool> {
+    let entries = std::fs::read_dir("/sys/class/power_supply").ok()?;
+
+    let mut found_mains = false;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let supply_type = std::fs::read_to_string(path.join("type")).unwrap_or_default();
+        if supply_type.trim() != "Mains" {
+            continue;
+        }
+        found_mains = true;
+
+        let online = std::fs::read_to_string(path.join("online")).unwrap_or_default();
+        if online.trim() == "1" {
+            return Some(false);
+        }
+    }
+
+    if found_mains {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+/// Picks a specific device, overriding the preference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GpuSelector {
+    /// Index in `list_gpus` order
+    Index(usize),
+    /// Case insensitive substring of the device name
+    Name(String),
+}
+
+impl GpuSelector {
+    /// Numbers are indices, anything else is a name
+    pub fn parse(value: &str) -> Self {
+        match value.parse() {
+            Ok(index) => GpuSelector::Index(index),
+            Err(_) => GpuSelector::Name(value.to_owned()),
+        }
+    }
+
+    fn matches(&self, gpu: &GpuInfo) -> bool {
+        match self {
+            GpuSelector::Index(index) => gpu.index == *index,
+            GpuSelector::Name(name) => gpu.name.to_lowercase().contains(&name.to_lowercase()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GpuInfo {
+    pub index: usize,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub device_local_memory_mb: u64,
+    /// Higher is better, None if the device can't be used (no graphics queue, can't present to the surface)
+    pub score: Option<u32>,
+    physical_device: vk::PhysicalDevice,
+}
+
+/// Surface the device has to present to, None in headless mode
+pub type SurfaceSupport<'a> = Option<(&'a ash::extensions::khr::Surface, vk::SurfaceKHR)>;
+
+/// First queue family with graphics support that can also present to the surface
+pub fn graphics_queue_family(instance: &ash::Instance, physical_device: vk::PhysicalDevice, surface: SurfaceSupport) -> Option<u32> {
+    let queue_family_properties = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+    queue_family_properties.iter().enumerate().find(|(i, p)| {
+        let present = match surface {
+            Some((loader, surface)) => unsafe { loader.get_physical_device_surface_support(physical_device, *i as u32, surface).unwrap_or(false) },
+            None => true,
+        };
+        p.queue_flags.contains(vk::QueueFlags::GRAPHICS) && present
+    }).map(|(i, _)| i as u32)
+}
+
+pub fn supports_extension(instance: &ash::Instance, physical_device: vk::PhysicalDevice, name: &std::ffi::CStr) -> bool {
+    let extensions = unsafe { instance.enumerate_device_extension_properties(physical_device).unwrap_or_default() };
+    extensions.iter().any(|e| unsafe { std::ffi::CStr::from_ptr(e.extension_name.as_ptr()) } == name)
+}
+
+fn score(instance: &ash::Instance, physical_device: vk::PhysicalDevice, properties: &vk::PhysicalDeviceProperties, device_local_memory_mb: u64,
+         type_order: &[vk::PhysicalDeviceType; 3], surface: SurfaceSupport) -> Option<u32> {
+    graphics_queue_family(instance, physical_device, surface)?;
+    if let Some((loader, surface)) = surface {
+        if !supports_extension(instance, physical_device, vk::KhrSwapchainFn::name()) {
+            return None;
+        }
+        let formats = unsafe { loader.get_physical_device_surface_formats(physical_device, surface).unwrap_or_default() };
+        if formats.is_empty() {
+            return None;
+        }
+    }
+
+    // device type dominates, memory and optional features only break ties
+    let type_score = match type_order.iter().position(|t| *t == properties.device_type) {
+        Some(position) => (type_order.len() - position) as u32 * 100_000,
+        None => 0,
+    };
+    let memory_score = (device_local_memory_mb / 64).min(50_000) as u32;
+    let features = unsafe { instance.get_physical_device_features(physical_device) };
+    let feature_score = if features.pipeline_statistics_query == vk::TRUE { 100 } else { 0 };
+
+    Some(type_score + memory_score + feature_score)
+}
+
+/// All physical devices in enumeration order, scored for `preference`
+pub fn list_gpus(instance: &ash::Instance, preference: GpuPreference, surface: SurfaceSupport) -> Vec<GpuInfo> {
+    let physical_devices = unsafe { instance.enumerate_physical_devices().unwrap() };
+    let type_order = preference.device_type_order();
+
+    physical_devices.iter().enumerate().map(|(index, &physical_device)| {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        let memory_properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
+        let device_local_memory_mb = memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize].iter()
+            .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size / (1024 * 1024))
+            .sum();
+        let name = unsafe { std::ffi::CStr::from_ptr(properties.device_name.as_ptr()) }.to_string_lossy().into_owned();
+
+        GpuInfo {
+            index,
+            name,
+            device_type: properties.device_type,
+            device_local_memory_mb,
+            score: score(instance, physical_device, &properties, device_local_memory_mb, &type_order, surface),
+            physical_device,
+        }
+    }).collect()
+}
+
+/// Device matching `selector` if it's usable, otherwise the highest scoring one
+pub fn select_physical_device(instance: &ash::Instance, preference: GpuPreference, selector: Option<&GpuSelector>, surface: SurfaceSupport) -> Option<vk::PhysicalDevice> {
+    let gpus = list_gpus(instance, preference, surface);
+
+    if let Some(selector) = selector {
+        match gpus.iter().find(|gpu| selector.matches(gpu)) {
+            Some(gpu) if gpu.score.is_some() => return Some(gpu.physical_device),
+            Some(gpu) => warn!("Selected GPU {} can't be used, falling back to automatic selection", gpu.name),
+            None => warn!("No GPU matches {:?}, falling back to automatic selection", selector),
+        }
+    }
+
+    gpus.iter()
+        .filter_map(|gpu| gpu.score.map(|score| (score, gpu.physical_device)))
+        .max_by_key(|(score, _)| *score)
+        .map(|(_, physical_device)| physical_device)
+}
@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+use std::mem;
+
+use ash::prelude::VkResult;
+use ash::vk;
+use egui::epaint::textures::{TextureFilter, TexturesDelta};
+use egui::epaint::{ClippedPrimitive, ImageData, Primitive, TextureId, Vertex};
+use log::debug;
+
+use super::descriptor_allocator::DescriptorAllocator;
+use super::resourceManager::{BufferHandle, ImageHandle, ResourceManager};
+use super::shaders;
+use super::vertex::VertexFormat;
+
+/// Initial size of each in-flight frame's vertex and index buffers
+const INITIAL_VERTEX_BUFFER_SIZE: vk::DeviceSize = (4096 * mem::size_of::<Vertex>()) as vk::DeviceSize;
+const INITIAL_INDEX_BUFFER_SIZE: vk::DeviceSize = (8192 * mem::size_of::<u32>()) as vk::DeviceSize;
+
+// egui vertices are position, texture coordinate and sRGB color with premultiplied alpha
+crate::vertex_format!(Vertex {
+    pos: vk::Format::R32G32_SFLOAT,
+    uv: vk::Format::R32G32_SFLOAT,
+    color: vk::Format::R8G8B8A8_UNORM,
+});
+
+/// Tessellated egui output of one frame, passed to `Frame::set_gui`
+#[derive(Default)]
+pub struct GuiOutput {
+    pub primitives: Vec<ClippedPrimitive>,
+    pub textures_delta: TexturesDelta,
+    pub pixels_per_point: f32,
+}
+
+struct CachedTexture {
+    size: [usize; 2],
+    /// sRGB with premultiplied alpha, row by row
+    pixels: Vec<u8>,
+    filter: vk::Filter,
+    /// Bumped on every change, `GuiRenderer` re-uploads textures whose version it hasn't seen
+    version: u64,
+}
+
+/// CPU copies of the egui textures. egui sends every texture only once,
+/// so they outlive the device and are uploaded again after device loss.
+#[derive(Default)]
+pub(super) struct GuiTextures {
+    textures: HashMap<TextureId, CachedTexture>,
+    next_version: u64,
+}
+
+impl GuiTextures {
+    /// New textures and updated regions, applied before the frame is drawn
+    pub fn set(&mut self, delta: &TexturesDelta) {
+        for (id, image_delta) in &delta.set {
+            let (size, pixels): ([usize; 2], Vec<u8>) = match &image_delta.image {
+                ImageData::Color(image) => (image.size, image.pixels.iter().flat_map(|c| [c.r(), c.g(), c.b(), c.a()]).collect()),
+                ImageData::Font(image) => (image.size, image.srgba_pixels(None).flat_map(|c| [c.r(), c.g(), c.b(), c.a()]).collect()),
+            };
+            let filter = match image_delta.options.magnification {
+                TextureFilter::Nearest => vk::Filter::NEAREST,
+                TextureFilter::Linear => vk::Filter::LINEAR,
+            };
+            let version = self.next_version;
+            self.next_version += 1;
+
+            match image_delta.pos {
+                None => {
+                    self.textures.insert(*id, CachedTexture { size, pixels, filter, version });
+                },
+                Some([x, y]) => {
+                    let texture = self.textures.get_mut(id).expect("Partial update of an unknown egui texture");
+                    let row_bytes = size[0] * 4;
+                    for row in 0..size[1] {
+                        let dst = ((y + row) * texture.size[0] + x) * 4;
+                        texture.pixels[dst..dst + row_bytes].copy_from_slice(&pixels[row * row_bytes..(row + 1) * row_bytes]);
+                    }
+                    texture.version = version;
+                },
+            }
+        }
+    }
+
+    /// Textures no longer used, applied after the frame is recorded
+    pub fn free(&mut self, delta: &TexturesDelta) {
+        for id in &delta.free {
+            self.textures.remove(id);
+        }
+    }
+}
+
+struct GpuTexture {
+    image: ImageHandle,
+    view: vk::ImageView,
+    descriptor_set: vk::DescriptorSet,
+    version: u64,
+}
+
+/// Indexed draw of one `ClippedPrimitive`, offsets into this frame's buffers
+pub(super) struct GuiDraw {
+    clip_rect: egui::Rect,
+    texture_id: TextureId,
+    first_index: u32,
+    index_count: u32,
+    vertex_offset: i32,
+}
+
+/// What a `GuiRenderer::cmd_draw` draws into, the pipeline comes from `GuiRenderer::create_pipeline` for the same output image
+#[derive(Debug, Clone, Copy)]
+pub(super) struct GuiTarget {
+    pub pipeline: vk::Pipeline,
+    pub extent: vk::Extent2D,
+    pub pixels_per_point: f32,
+}
+
+/// Device copies of `GuiTextures` and the per-frame vertex and index buffers of the GUI.
+/// The pipeline depends on the output render pass and is created with `create_pipeline`.
+pub(super) struct GuiRenderer {
+    linear_sampler: vk::Sampler,
+    nearest_sampler: vk::Sampler,
+
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+
+    textures: HashMap<TextureId, GpuTexture>,
+
+    /// `Vertex` and `u32` index lists, one copy per in-flight frame, grown on demand
+    vertex_buffers: Vec<BufferHandle>,
+    index_buffers: Vec<BufferHandle>,
+}
+
+impl GuiRenderer {
+    pub fn new(device: &ash::Device, resource_manager: &mut ResourceManager, in_flight_frames: usize) -> Self {
+        let linear_sampler = resource_manager.create_sampler(vk::Filter::LINEAR, vk::SamplerAddressMode::CLAMP_TO_EDGE);
+        resource_manager.set_name(linear_sampler, "gui_linear_sampler");
+        let nearest_sampler = resource_manager.create_sampler(vk::Filter::NEAREST, vk::SamplerAddressMode::CLAMP_TO_EDGE);
+        resource_manager.set_name(nearest_sampler, "gui_nearest_sampler");
+
+        let descriptor_set_layout_bindings = [vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build()];
+        let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&descriptor_set_layout_bindings);
+        let descriptor_set_layout = unsafe { device.create_descriptor_set_layout(&descriptor_set_layout_create_info, None).unwrap() };
+        resource_manager.set_name(descriptor_set_layout, "gui_descriptor_set_layout");
+
+        let push_constant_ranges = [vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .offset(0)
+            .size(mem::size_of::<[f32; 2]>() as u32)
+            .build()];
+        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(std::slice::from_ref(&descriptor_set_layout))
+            .push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout = unsafe { device.create_pipeline_layout(&pipeline_layout_create_info, None).unwrap() };
+        resource_manager.set_name(pipeline_layout, "gui_pipeline_layout");
+
+        let vertex_buffers = (0..in_flight_frames).map(|i| {
+            let vertex_buffer = resource_manager.create_buffer(INITIAL_VERTEX_BUFFER_SIZE, vk::BufferUsageFlags::VERTEX_BUFFER);
+            resource_manager.set_buffer_name(&vertex_buffer, &format!("gui_vertex_buffer[{}]", i));
+            vertex_buffer
+        }).collect();
+        let index_buffers = (0..in_flight_frames).map(|i| {
+            let index_buffer = resource_manager.create_buffer(INITIAL_INDEX_BUFFER_SIZE, vk::BufferUsageFlags::INDEX_BUFFER);
+            resource_manager.set_buffer_name(&index_buffer, &format!("gui_index_buffer[{}]", i));
+            index_buffer
+        }).collect();
+
+        Self {
+            linear_sampler,
+            nearest_sampler,
+
+            descriptor_set_layout,
+            pipeline_layout,
+
+            textures: HashMap::new(),
+
+            vertex_buffers,
+            index_buffers,
+        }
+    }
+
+    /// The descriptor sets of the textures go with the `DescriptorAllocator`
+    pub fn destroy(self, device: &ash::Device, resource_manager: &mut ResourceManager) {
+        unsafe {
+            for texture in self.textures.values() {
+                device.destroy_image_view(texture.view, None);
+            }
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            device.destroy_sampler(self.linear_sampler, None);
+            device.destroy_sampler(self.nearest_sampler, None);
+        }
+        for (_, texture) in self.textures {
+            resource_manager.destroy_image(texture.image);
+        }
+        for buffer in self.vertex_buffers.into_iter().chain(self.index_buffers) {
+            resource_manager.destroy_buffer(buffer);
+        }
+    }
+
+    /// Uploads new and changed textures of `cache` and destroys the freed ones.
+    /// Waits for the device first if a texture in use is replaced, egui only does that when new glyphs are rasterized.
+    pub fn sync(&mut self, device: &ash::Device, resource_manager: &mut ResourceManager, descriptor_allocator: &mut DescriptorAllocator, cache: &GuiTextures) -> VkResult<()> {
+        let stale: Vec<TextureId> = self.textures.iter()
+            .filter(|(id, texture)| cache.textures.get(id).is_none_or(|cached| cached.version != texture.version))
+            .map(|(id, _)| *id)
+            .collect();
+        if !stale.is_empty() {
+            unsafe { device.device_wait_idle()?; }
+        }
+        for id in stale {
+            let texture = self.textures.remove(&id).unwrap();
+            unsafe { device.destroy_image_view(texture.view, None) };
+            descriptor_allocator.free(device, &[texture.descriptor_set]);
+            resource_manager.destroy_image(texture.image);
+        }
+
+        for (id, cached) in &cache.textures {
+            if self.textures.contains_key(id) {
+                continue;
+            }
+            debug!("Uploading egui texture {:?}, {}x{}", id, cached.size[0], cached.size[1]);
+            let image = resource_manager.create_image(cached.size[0] as u32,
+                cached.size[1] as u32,
+                vk::Format::R8G8B8A8_UNORM,
+                vk::ImageTiling::OPTIMAL,
+                vk::ImageUsageFlags::SAMPLED);
+            resource_manager.set_image_name(&image, &format!("gui_texture_{:?}", id));
+            if let Err(e) = resource_manager.fill_image(&image, &cached.pixels) {
+                resource_manager.destroy_image(image);
+                return Err(e);
+            }
+            let view = resource_manager.create_image_view(resource_manager.image(&image), vk::Format::R8G8B8A8_UNORM, vk::ImageAspectFlags::COLOR);
+
+            let descriptor_set = descriptor_allocator.allocate(device, resource_manager, self.descriptor_set_layout);
+            let descriptor_image_infos = [vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(view)
+                .sampler(if cached.filter == vk::Filter::NEAREST { self.nearest_sampler } else { self.linear_sampler })
+                .build()];
+            let descriptor_write_set = [vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&descriptor_image_infos)
+                .build()];
+            unsafe { device.update_descriptor_sets(&descriptor_write_set, &[]) };
+
+            self.textures.insert(*id, GpuTexture {
+                image,
+                view,
+                descriptor_set,
+                version: cached.version,
+            });
+        }
+        Ok(())
+    }
+
+    /// Writes the meshes of `primitives` into the buffers of `in_flight_frame`, growing them if needed.
+    /// Paint callbacks are not supported and skipped.
+    pub fn upload(&mut self, resource_manager: &mut ResourceManager, in_flight_frame: usize, primitives: &[ClippedPrimitive]) -> VkResult<Vec<GuiDraw>> {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut draws = Vec::new();
+        for primitive in primitives {
+            if let Primitive::Mesh(mesh) = &primitive.primitive {
+                draws.push(GuiDraw {
+                    clip_rect: primitive.clip_rect,
+                    texture_id: mesh.texture_id,
+                    first_index: indices.len() as u32,
+                    index_count: mesh.indices.len() as u32,
+                    vertex_offset: vertices.len() as i32,
+                });
+                vertices.extend_from_slice(&mesh.vertices);
+                indices.extend_from_slice(&mesh.indices);
+            }
+        }
+        if draws.is_empty() {
+            return Ok(draws);
+        }
+
+        for (buffers, size, usage, name) in [
+            (&mut self.vertex_buffers, (vertices.len() * mem::size_of::<Vertex>()) as vk::DeviceSize, vk::BufferUsageFlags::VERTEX_BUFFER, "gui_vertex_buffer"),
+            (&mut self.index_buffers, (indices.len() * mem::size_of::<u32>()) as vk::DeviceSize, vk::BufferUsageFlags::INDEX_BUFFER, "gui_index_buffer"),
+        ] {
+            let buffer_size = resource_manager.buffer_size(&buffers[in_flight_frame]);
+            if size > buffer_size {
+                let size = size.max(buffer_size * 2);
+                debug!("Growing {} [{}] to {} bytes", name, in_flight_frame, size);
+                let buffer = resource_manager.create_buffer(size, usage);
+                resource_manager.set_buffer_name(&buffer, &format!("{}[{}]", name, in_flight_frame));
+                let old_buffer = mem::replace(&mut buffers[in_flight_frame], buffer);
+                resource_manager.destroy_buffer(old_buffer);
+            }
+        }
+        resource_manager.fill_buffer(&self.vertex_buffers[in_flight_frame], &vertices)?;
+        resource_manager.fill_buffer(&self.index_buffers[in_flight_frame], &indices)?;
+        Ok(draws)
+    }
+
+    /// Records `draws` from the last upload of `in_flight_frame`, inside a render pass compatible with the one of `target`.
+    /// Draws of textures that are not uploaded are skipped.
+    pub fn cmd_draw(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, resource_manager: &ResourceManager, in_flight_frame: usize,
+                    draws: &[GuiDraw], target: GuiTarget) {
+        if draws.is_empty() {
+            return;
+        }
+        let GuiTarget { pipeline, extent, pixels_per_point } = target;
+        let screen_size = [extent.width as f32 / pixels_per_point, extent.height as f32 / pixels_per_point];
+        let screen_size_bytes: Vec<u8> = screen_size.iter().flat_map(|v| v.to_ne_bytes()).collect();
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[resource_manager.buffer(&self.vertex_buffers[in_flight_frame])], &[0]);
+            device.cmd_bind_index_buffer(command_buffer, resource_manager.buffer(&self.index_buffers[in_flight_frame]), 0, vk::IndexType::UINT32);
+            device.cmd_push_constants(command_buffer, self.pipeline_layout, vk::ShaderStageFlags::VERTEX, 0, &screen_size_bytes);
+        }
+        for draw in draws {
+            let Some(texture) = self.textures.get(&draw.texture_id) else {
+                continue;
+            };
+            // clip rect in points to whole pixels inside the output image
+            let min_x = (draw.clip_rect.min.x * pixels_per_point).round().clamp(0.0, extent.width as f32) as u32;
+            let min_y = (draw.clip_rect.min.y * pixels_per_point).round().clamp(0.0, extent.height as f32) as u32;
+            let max_x = (draw.clip_rect.max.x * pixels_per_point).round().clamp(min_x as f32, extent.width as f32) as u32;
+            let max_y = (draw.clip_rect.max.y * pixels_per_point).round().clamp(min_y as f32, extent.height as f32) as u32;
+            if max_x == min_x || max_y == min_y {
+                continue;
+            }
+            let scissor = vk::Rect2D {
+                offset: vk::Offset2D { x: min_x as i32, y: min_y as i32 },
+                extent: vk::Extent2D { width: max_x - min_x, height: max_y - min_y },
+            };
+            unsafe {
+                device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+                device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline_layout, 0, &[texture.descriptor_set], &[]);
+                device.cmd_draw_indexed(command_buffer, draw.index_count, 1, draw.first_index, draw.vertex_offset, 0);
+            }
+        }
+    }
+
+    /// Triangle list pipeline with premultiplied alpha blending over the output image and a dynamic scissor.
+    /// `transfer_function` is the specialization constant of output.frag, gui.frag encodes its colors the same way.
+    pub fn create_pipeline(&self, device: &ash::Device, render_pass: vk::RenderPass, extent: vk::Extent2D, transfer_function: u32) -> vk::Pipeline {
+        let vertex_shader_code = shaders::load_spirv("gui_vert.spv");
+        let fragment_shader_code = shaders::load_spirv("gui_frag.spv");
+        let shader_module_create_info = vk::ShaderModuleCreateInfo::builder()
+            .code(&vertex_shader_code);
+        let vertex_shader_module = unsafe { device.create_shader_module(&shader_module_create_info, None).unwrap() };
+        let shader_module_create_info = vk::ShaderModuleCreateInfo::builder()
+            .code(&fragment_shader_code);
+        let fragment_shader_module = unsafe { device.create_shader_module(&shader_module_create_info, None).unwrap() };
+
+        let transfer_function = transfer_function.to_ne_bytes();
+        let specialization_map_entries = [vk::SpecializationMapEntry {
+            constant_id: 0,
+            offset: 0,
+            size: mem::size_of::<u32>(),
+        }];
+        let specialization_info = vk::SpecializationInfo::builder()
+            .map_entries(&specialization_map_entries)
+            .data(&transfer_function);
+        let shader_stages = [vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .module(vertex_shader_module)
+            .name(c"main")
+            .build(),
+        vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(fragment_shader_module)
+            .name(c"main")
+            .specialization_info(&specialization_info)
+            .build()];
+
+        let (vertex_binding_description, vertex_attribute_descriptions) = Vertex::input_descriptions(0);
+        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(std::slice::from_ref(&vertex_binding_description))
+            .vertex_attribute_descriptions(&vertex_attribute_descriptions);
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+            .primitive_restart_enable(false);
+
+        let viewports = [vk::Viewport::builder()
+            .x(0.0)
+            .y(0.0)
+            .width(extent.width as f32)
+            .height(extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0)
+            .build()];
+        // set per draw from the clip rect
+        let scissors = [vk::Rect2D::default()];
+        let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+            .viewports(&viewports)
+            .scissors(&scissors);
+        let dynamic_states = [vk::DynamicState::SCISSOR];
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(&dynamic_states);
+
+        // egui doesn't keep a consistent winding order
+        let rasterizer = vk::PipelineRasterizationStateCreateInfo::builder()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .line_width(1.0)
+            .cull_mode(vk::CullModeFlags::NONE);
+        let multisampling = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+
+        // the output image keeps its alpha, read back frames stay opaque
+        let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::RGBA)
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::ONE)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .build()];
+        let color_blending = vk::PipelineColorBlendStateCreateInfo::builder()
+            .attachments(&color_blend_attachments);
+
+        let graphics_pipeline_create_info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&shader_stages)
+            .vertex_input_state(&vertex_input_info)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterizer)
+            .multisample_state(&multisampling)
+            .color_blend_state(&color_blending)
+            .dynamic_state(&dynamic_state)
+            .layout(self.pipeline_layout)
+            .render_pass(render_pass)
+            .subpass(0)
+            .build();
+        let pipelines = unsafe { device.create_graphics_pipelines(vk::PipelineCache::null(), &[graphics_pipeline_create_info], None).unwrap() };
+
+        unsafe {
+            device.destroy_shader_module(vertex_shader_module, None);
+            device.destroy_shader_module(fragment_shader_module, None);
+        }
+        pipelines[0]
+    }
+}
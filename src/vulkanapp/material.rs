@@ -0,0 +1,377 @@
+use std::mem;
+
+use ash::prelude::VkResult;
+use ash::vk;
+use glam::{Vec2, Vec4};
+
+use super::descriptor_allocator::DescriptorAllocator;
+use super::resourceManager::{BufferHandle, ImageHandle, ResourceManager};
+use super::std_layout::bytes_of;
+
+/// Upper bound of the bindless texture array, lowered to the device limits
+const MAX_BINDLESS_TEXTURES: u32 = 4096;
+
+/// Texture added with `VulkanApp::create_texture`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextureId(usize);
+
+impl TextureId {
+    /// img.png, loaded at startup
+    pub const DEFAULT: TextureId = TextureId(0);
+}
+
+/// Material added with `VulkanApp::create_material`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MaterialId(usize);
+
+impl MaterialId {
+    /// `Material::default()`, created at startup
+    pub const DEFAULT: MaterialId = MaterialId(0);
+}
+
+/// Uniform buffer of a material, std140 like `MaterialParams` in shader.frag.
+/// With bindless textures the same fields follow the object id and texture index in the push constants.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialParams {
+    /// Linear RGBA, multiplies the texture
+    pub color: Vec4,
+    /// Texture coordinates are sampled at uv * uv_scale + uv_offset
+    pub uv_scale: Vec2,
+    pub uv_offset: Vec2,
+}
+
+crate::assert_block_size!(MaterialParams, 32);
+
+impl Default for MaterialParams {
+    fn default() -> Self {
+        Self {
+            color: Vec4::ONE,
+            uv_scale: Vec2::ONE,
+            uv_offset: Vec2::ZERO,
+        }
+    }
+}
+
+/// Shader, texture and parameters of meshes and sprites. Materials can't be changed once created.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Material {
+    /// Name for `shaders::load_spirv`, must declare the same descriptor sets and push constants as frag.spv.
+    /// frag.spv is replaced by frag_bindless.spv when the device supports bindless textures, other shaders are used as they are.
+    pub fragment_shader: &'static str,
+    pub texture: TextureId,
+    pub params: MaterialParams,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            fragment_shader: "frag.spv",
+            texture: TextureId::DEFAULT,
+            params: MaterialParams::default(),
+        }
+    }
+}
+
+struct CachedTexture {
+    width: u32,
+    height: u32,
+    /// sRGB RGBA, row by row
+    pixels: Vec<u8>,
+}
+
+/// CPU copies of the textures and materials, uploaded again after device loss. Both only grow.
+#[derive(Default)]
+pub(super) struct MaterialCache {
+    textures: Vec<CachedTexture>,
+    materials: Vec<Material>,
+}
+
+impl MaterialCache {
+    pub fn add_texture(&mut self, width: u32, height: u32, pixels: Vec<u8>) -> TextureId {
+        assert_eq!(pixels.len(), (width * height * 4) as usize, "Texture must be RGBA8");
+        self.textures.push(CachedTexture { width, height, pixels });
+        TextureId(self.textures.len() - 1)
+    }
+
+    pub fn add_material(&mut self, material: Material) -> MaterialId {
+        assert!(material.texture.0 < self.textures.len(), "Unknown texture {:?}", material.texture);
+        self.materials.push(material);
+        MaterialId(self.materials.len() - 1)
+    }
+
+    pub fn material(&self, id: MaterialId) -> &Material {
+        &self.materials[id.0]
+    }
+}
+
+struct GpuTexture {
+    image: ImageHandle,
+    view: vk::ImageView,
+}
+
+struct GpuMaterial {
+    params_buffer: BufferHandle,
+    descriptor_set: vk::DescriptorSet,
+}
+
+/// `PushConstants` of shader.frag compiled with BINDLESS
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct BindlessPushConstants {
+    object_id: u32,
+    texture_index: u32,
+    _padding: [u32; 2],
+    params: MaterialParams,
+}
+
+crate::assert_block_size!(BindlessPushConstants, 48);
+
+/// Textures of all materials in one descriptor set, see `GpuMaterials::new`
+struct BindlessTextures {
+    /// Holds only `descriptor_set`, update after bind sets can't come from the `DescriptorAllocator`
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    capacity: u32,
+}
+
+/// Device features needed for bindless textures, all of them part of `VK_EXT_descriptor_indexing`
+pub(super) fn bindless_supported(features: &vk::PhysicalDeviceDescriptorIndexingFeatures) -> bool {
+    [
+        features.runtime_descriptor_array,
+        features.descriptor_binding_variable_descriptor_count,
+        features.descriptor_binding_partially_bound,
+        features.descriptor_binding_sampled_image_update_after_bind,
+        features.descriptor_binding_update_unused_while_pending,
+        features.shader_sampled_image_array_non_uniform_indexing,
+    ].iter().all(|supported| *supported == vk::TRUE)
+}
+
+/// Largest bindless texture array the device allows
+pub(super) fn bindless_capacity(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> u32 {
+    let mut descriptor_indexing = vk::PhysicalDeviceDescriptorIndexingProperties::default();
+    let mut properties = vk::PhysicalDeviceProperties2::builder().push_next(&mut descriptor_indexing);
+    unsafe { instance.get_physical_device_properties2(physical_device, &mut properties) };
+    // a combined image sampler counts against both the sampler and the sampled image limits
+    MAX_BINDLESS_TEXTURES
+        .min(descriptor_indexing.max_per_stage_descriptor_update_after_bind_samplers)
+        .min(descriptor_indexing.max_per_stage_descriptor_update_after_bind_sampled_images)
+        .min(descriptor_indexing.max_descriptor_set_update_after_bind_samplers)
+        .min(descriptor_indexing.max_descriptor_set_update_after_bind_sampled_images)
+}
+
+/// Device copies of `MaterialCache`, bound as descriptor set 1 of the main pass.
+/// Each material has its own set, or with bindless textures one set holds every texture at its `TextureId`
+/// and the material parameters are push constants, so draws of different materials don't rebind the set.
+pub(super) struct GpuMaterials {
+    sampler: vk::Sampler,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    bindless: Option<BindlessTextures>,
+
+    textures: Vec<GpuTexture>,
+    /// Empty with bindless textures
+    materials: Vec<GpuMaterial>,
+}
+
+impl GpuMaterials {
+    /// `bindings` of set 1 as declared by the main shaders: the texture at binding 0 and `MaterialParams` at binding 1 for frag.spv,
+    /// the texture array at binding 0 for frag_bindless.spv. `bindless_capacity` is the length of that array, None for frag.spv.
+    pub fn new(device: &ash::Device, resource_manager: &ResourceManager, bindings: &[vk::DescriptorSetLayoutBinding], bindless_capacity: Option<u32>) -> Self {
+        let sampler = resource_manager.create_sampler(vk::Filter::LINEAR, vk::SamplerAddressMode::REPEAT);
+        resource_manager.set_name(sampler, "material_sampler");
+
+        let Some(capacity) = bindless_capacity else {
+            let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(bindings);
+            let descriptor_set_layout = unsafe { device.create_descriptor_set_layout(&descriptor_set_layout_create_info, None).unwrap() };
+            resource_manager.set_name(descriptor_set_layout, "material_descriptor_set_layout");
+
+            return Self {
+                sampler,
+                descriptor_set_layout,
+                bindless: None,
+
+                textures: Vec::new(),
+                materials: Vec::new(),
+            };
+        };
+
+        // the runtime sized array is reflected with a count of 0
+        let bindings: Vec<_> = bindings.iter().map(|binding| vk::DescriptorSetLayoutBinding {
+            descriptor_count: if binding.descriptor_count == 0 { capacity } else { binding.descriptor_count },
+            ..*binding
+        }).collect();
+        // textures are added while the set is used by the other in-flight frame, at indices it doesn't read
+        let binding_flags: Vec<_> = bindings.iter().map(|binding| if binding.descriptor_count == capacity {
+            vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT
+                | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+                | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING
+        } else {
+            vk::DescriptorBindingFlags::empty()
+        }).collect();
+        let mut binding_flags_create_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder()
+            .binding_flags(&binding_flags);
+        let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+            .bindings(&bindings)
+            .push_next(&mut binding_flags_create_info);
+        let descriptor_set_layout = unsafe { device.create_descriptor_set_layout(&descriptor_set_layout_create_info, None).unwrap() };
+        resource_manager.set_name(descriptor_set_layout, "bindless_descriptor_set_layout");
+
+        let pool_sizes = [vk::DescriptorPoolSize { ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER, descriptor_count: capacity }];
+        let pool_create_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+            .max_sets(1)
+            .pool_sizes(&pool_sizes);
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_create_info, None).unwrap() };
+        resource_manager.set_name(descriptor_pool, "bindless_descriptor_pool");
+
+        let mut variable_count_allocate_info = vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder()
+            .descriptor_counts(std::slice::from_ref(&capacity));
+        let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(std::slice::from_ref(&descriptor_set_layout))
+            .push_next(&mut variable_count_allocate_info);
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&allocate_info).unwrap()[0] };
+        resource_manager.set_name(descriptor_set, "bindless_descriptor_set");
+
+        Self {
+            sampler,
+            descriptor_set_layout,
+            bindless: Some(BindlessTextures { descriptor_pool, descriptor_set, capacity }),
+
+            textures: Vec::new(),
+            materials: Vec::new(),
+        }
+    }
+
+    pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    /// The same set for every material with bindless textures
+    pub fn descriptor_set(&self, id: MaterialId) -> vk::DescriptorSet {
+        match &self.bindless {
+            Some(bindless) => bindless.descriptor_set,
+            None => self.materials[id.0].descriptor_set,
+        }
+    }
+
+    /// Fragment shader the main pass uses for `material`
+    pub fn fragment_shader(&self, material: &Material) -> &'static str {
+        match material.fragment_shader {
+            "frag.spv" if self.bindless.is_some() => "frag_bindless.spv",
+            fragment_shader => fragment_shader,
+        }
+    }
+
+    /// Fragment push constants of a draw, laid out like `PushConstants` in shader.frag:
+    /// the object id, and with bindless textures the texture index, padding to 16 bytes and `MaterialParams`
+    pub fn push_constants(&self, material: &Material, object_id: u32) -> Vec<u8> {
+        if self.bindless.is_none() {
+            return object_id.to_ne_bytes().to_vec();
+        }
+        let push_constants = BindlessPushConstants {
+            object_id,
+            texture_index: material.texture.0 as u32,
+            _padding: [0; 2],
+            params: material.params,
+        };
+        bytes_of(&push_constants).to_vec()
+    }
+
+    /// Uploads the textures and materials added to `cache` since the last sync
+    pub fn sync(&mut self, device: &ash::Device, resource_manager: &mut ResourceManager, descriptor_allocator: &mut DescriptorAllocator, cache: &MaterialCache) -> VkResult<()> {
+        for (i, texture) in cache.textures.iter().enumerate().skip(self.textures.len()) {
+            let image = resource_manager.create_image(texture.width,
+                texture.height,
+                vk::Format::R8G8B8A8_UNORM,
+                vk::ImageTiling::OPTIMAL,
+                vk::ImageUsageFlags::SAMPLED);
+            resource_manager.set_image_name(&image, &format!("texture_image[{}]", i));
+            if let Err(e) = resource_manager.fill_image(&image, &texture.pixels) {
+                resource_manager.destroy_image(image);
+                return Err(e);
+            }
+            let view = resource_manager.create_image_view(resource_manager.image(&image), vk::Format::R8G8B8A8_UNORM, vk::ImageAspectFlags::COLOR);
+            resource_manager.set_name(view, &format!("texture_image_view[{}]", i));
+
+            if let Some(bindless) = &self.bindless {
+                assert!((i as u32) < bindless.capacity, "More than {} textures", bindless.capacity);
+                let descriptor_image_infos = [vk::DescriptorImageInfo::builder()
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .image_view(view)
+                    .sampler(self.sampler)
+                    .build()];
+                let descriptor_write_set = [vk::WriteDescriptorSet::builder()
+                    .dst_set(bindless.descriptor_set)
+                    .dst_binding(0)
+                    .dst_array_element(i as u32)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&descriptor_image_infos)
+                    .build()];
+                unsafe { device.update_descriptor_sets(&descriptor_write_set, &[]) };
+            }
+            self.textures.push(GpuTexture { image, view });
+        }
+        if self.bindless.is_some() {
+            return Ok(());
+        }
+
+        for (i, material) in cache.materials.iter().enumerate().skip(self.materials.len()) {
+            let params_buffer = resource_manager.create_buffer(mem::size_of::<MaterialParams>() as vk::DeviceSize, vk::BufferUsageFlags::UNIFORM_BUFFER);
+            resource_manager.set_buffer_name(&params_buffer, &format!("material_params_buffer[{}]", i));
+            resource_manager.fill_buffer(&params_buffer, &[material.params])?;
+
+            let descriptor_set = descriptor_allocator.allocate(device, resource_manager, self.descriptor_set_layout);
+            resource_manager.set_name(descriptor_set, &format!("material_descriptor_set[{}]", i));
+            let descriptor_image_infos = [vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(self.textures[material.texture.0].view)
+                .sampler(self.sampler)
+                .build()];
+            let descriptor_buffer_infos = [vk::DescriptorBufferInfo::builder()
+                .buffer(resource_manager.buffer(&params_buffer))
+                .offset(0)
+                .range(vk::WHOLE_SIZE)
+                .build()];
+            let descriptor_write_set = [vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&descriptor_image_infos)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(&descriptor_buffer_infos)
+                .build()];
+            unsafe { device.update_descriptor_sets(&descriptor_write_set, &[]) };
+
+            self.materials.push(GpuMaterial { params_buffer, descriptor_set });
+        }
+        Ok(())
+    }
+
+    /// The descriptor sets of the materials go with the `DescriptorAllocator`
+    pub fn destroy(self, device: &ash::Device, resource_manager: &mut ResourceManager) {
+        unsafe {
+            if let Some(bindless) = &self.bindless {
+                device.destroy_descriptor_pool(bindless.descriptor_pool, None);
+            }
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            device.destroy_sampler(self.sampler, None);
+            for texture in &self.textures {
+                device.destroy_image_view(texture.view, None);
+            }
+        }
+        for texture in self.textures {
+            resource_manager.destroy_image(texture.image);
+        }
+        for material in self.materials {
+            resource_manager.destroy_buffer(material.params_buffer);
+        }
+    }
+}
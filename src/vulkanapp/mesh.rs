@@ -0,0 +1,86 @@
+use ash::prelude::VkResult;
+use ash::vk;
+use glam::Vec2;
+
+use super::resourceManager::{BufferHandle, ResourceManager};
+use super::vertex::Vertex;
+
+/// Indexed triangle list in model units, uploaded once with `VulkanApp::upload_mesh` and drawn with `Frame::draw`
+#[derive(Debug, Clone, Default)]
+pub struct MeshData {
+    pub vertices: Vec<Vertex>,
+    /// Three indices into `vertices` per triangle
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>) -> Self {
+        assert!(indices.len().is_multiple_of(3), "Mesh must be a list of whole triangles");
+        assert!(indices.iter().all(|i| (*i as usize) < vertices.len()), "Mesh index out of range");
+        Self { vertices, indices }
+    }
+}
+
+/// Mesh added with `VulkanApp::upload_mesh`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MeshHandle(usize);
+
+/// CPU copies of the uploaded meshes, uploaded again after device loss. Only grows.
+#[derive(Default)]
+pub(super) struct MeshCache {
+    meshes: Vec<MeshData>,
+}
+
+impl MeshCache {
+    pub fn add(&mut self, mesh: MeshData) -> MeshHandle {
+        assert!(!mesh.indices.is_empty(), "Mesh has no triangles");
+        self.meshes.push(mesh);
+        MeshHandle(self.meshes.len() - 1)
+    }
+}
+
+pub(super) struct GpuMesh {
+    pub vertex_buffer: BufferHandle,
+    pub index_buffer: BufferHandle,
+    pub index_count: u32,
+    /// Model space bounds of the vertices, for GPU culling
+    pub bounds_min: Vec2,
+    pub bounds_max: Vec2,
+}
+
+/// Device copies of `MeshCache`
+#[derive(Default)]
+pub(super) struct GpuMeshes {
+    meshes: Vec<GpuMesh>,
+}
+
+impl GpuMeshes {
+    pub fn get(&self, handle: MeshHandle) -> &GpuMesh {
+        &self.meshes[handle.0]
+    }
+
+    /// Uploads the meshes added to `cache` since the last sync, `vertex_buffer_usage` as for the frame's vertex buffers
+    pub fn sync(&mut self, resource_manager: &mut ResourceManager, cache: &MeshCache, vertex_buffer_usage: vk::BufferUsageFlags) -> VkResult<()> {
+        for (i, mesh) in cache.meshes.iter().enumerate().skip(self.meshes.len()) {
+            let vertex_buffer = resource_manager.create_buffer(std::mem::size_of_val(mesh.vertices.as_slice()) as vk::DeviceSize, vertex_buffer_usage);
+            resource_manager.set_buffer_name(&vertex_buffer, &format!("mesh_vertex_buffer[{}]", i));
+            resource_manager.fill_buffer(&vertex_buffer, &mesh.vertices)?;
+            let index_buffer = resource_manager.create_buffer(std::mem::size_of_val(mesh.indices.as_slice()) as vk::DeviceSize, vk::BufferUsageFlags::INDEX_BUFFER);
+            resource_manager.set_buffer_name(&index_buffer, &format!("mesh_index_buffer[{}]", i));
+            resource_manager.fill_buffer(&index_buffer, &mesh.indices)?;
+
+            let positions = mesh.vertices.iter().map(|vertex| vertex.position.truncate());
+            let bounds_min = positions.clone().fold(Vec2::splat(f32::MAX), Vec2::min);
+            let bounds_max = positions.fold(Vec2::splat(f32::MIN), Vec2::max);
+            self.meshes.push(GpuMesh { vertex_buffer, index_buffer, index_count: mesh.indices.len() as u32, bounds_min, bounds_max });
+        }
+        Ok(())
+    }
+
+    pub fn destroy(self, resource_manager: &mut ResourceManager) {
+        for mesh in self.meshes {
+            resource_manager.destroy_buffer(mesh.vertex_buffer);
+            resource_manager.destroy_buffer(mesh.index_buffer);
+        }
+    }
+}
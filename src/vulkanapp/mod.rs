@@ -1,86 +1,264 @@
 mod resourceManager;
 mod vertex;
+mod gpu_selection;
+mod depth_readback;
+mod profiler;
+mod picking;
+mod frame_timer;
+mod config;
+mod surface_format;
+mod uniforms;
+mod shaders;
+mod calibration;
+mod debug_config;
+mod debug_view;
+mod debug_draw;
+mod color_filter;
+mod draw_list;
+mod render_graph;
+mod device_profile;
+mod post_process;
+mod text;
+mod pipeline_manager;
+mod reflection;
+mod descriptor_allocator;
+mod material;
+mod mesh;
+mod scene;
+mod batching;
+mod gpu_culling;
+mod std_layout;
+#[cfg(feature = "egui")]
+mod gui_renderer;
 
-use ash::vk::QueryPoolCreateFlags;
-use ash::vk::QueryPoolCreateInfo;
-use ash::vk::QueryPoolCreateInfoBuilder;
-use ash::vk::QueryType;
 use resourceManager::ResourceManager;
-use vertex::Vertex;
+pub use gpu_selection::{GpuPreference, GpuSelector, GpuInfo, on_battery_power};
+pub use depth_readback::DepthReadback;
+use depth_readback::DepthReadbackSlot;
+pub use profiler::{ScopeReport, PipelineStatistics};
+pub use picking::{EntityId, PickResult};
+use picking::PickSlot;
+pub use frame_timer::{FrameTimingReport, CpuStageReport};
+use frame_timer::FrameTimer;
+pub use config::RendererConfig;
+pub use surface_format::DisplayColorSpace;
+pub use uniforms::FrameUniforms;
+pub use calibration::DisplayCalibration;
+pub use color_filter::ColorFilter;
+pub use device_profile::DeviceProfile;
+pub use post_process::{Tonemapper, PostProcessStage, AntiAliasing};
+pub use draw_list::{Frame, Mesh, Sprite, Transform2D, Camera2D};
+pub use vertex::Vertex;
+pub use mesh::{MeshData, MeshHandle};
+pub use scene::{Scene, NodeId, Drawable};
+pub use batching::BatchStats;
+pub use resourceManager::HeapStats;
+pub use debug_config::{DebugConfig, DebugMessage, DebugCallback};
+pub use debug_view::DebugView;
+pub use debug_draw::DebugDraw;
+pub use text::TextDraw;
+pub use material::{Material, MaterialParams, MaterialId, TextureId};
+#[cfg(feature = "egui")]
+pub use gui_renderer::GuiOutput;
+use profiler::GpuProfiler;
+use render_graph::{RenderGraph, ImageState};
+use post_process::{PostProcessChain, PostProcessOutput};
+use text::TextRenderer;
+use pipeline_manager::{PipelineManager, PipelineKey};
+use material::{MaterialCache, GpuMaterials};
+use mesh::{MeshCache, GpuMeshes};
+use gpu_culling::{GpuCulling, CullDraw};
+use draw_list::{Geometry, DrawCommand, DrawData};
+use descriptor_allocator::DescriptorAllocator;
+#[cfg(feature = "egui")]
+use gui_renderer::{GuiRenderer, GuiTarget, GuiTextures};
 
 use std::ffi::c_void;
 use std::mem;
 use std::ptr;
-use crate::offset_of;
+use std::time::Instant;
 
-use ash::{vk::{self, Handle, SurfaceKHR}, Entry, extensions};
+use ash::{vk::{self, Handle, SurfaceKHR}, Entry, extensions, prelude::VkResult};
+use log::{debug, error, info, warn, trace};
 
 
 
-use self::resourceManager::BufferResource;
+use self::resourceManager::{BufferHandle, ImageHandle};
 
 struct SyncObjects {
     image_available_semaphores: Vec<vk::Semaphore>,
     render_finished_semaphores: Vec<vk::Semaphore>,
     in_flight_fences: Vec<vk::Fence>,
 }
+/// Swapchain or, in headless mode, the offscreen render target, and everything sized after it
 struct SwapchainDependentResources {
-    swapchain_loader: ash::extensions::khr::Swapchain,
+    /// None in headless mode, `swapchain` is null then
+    swapchain_loader: Option<ash::extensions::khr::Swapchain>,
     swapchain: vk::SwapchainKHR,
+    /// The only color image in headless mode
+    offscreen_image: Option<ImageHandle>,
     swapchain_images: Vec<vk::Image>,
     swapchain_format: vk::Format,
+    color_space: DisplayColorSpace,
     swapchain_extent: vk::Extent2D,
     swapchain_imageviews: Vec<vk::ImageView>,
-    swapchain_framebuffers: Vec<vk::Framebuffer>,
+    /// Main pass framebuffer, renders into the scene target of `post_process`
+    framebuffer: vk::Framebuffer,
 
+    depth_image: ImageHandle,
+    depth_image_view: vk::ImageView,
 
-    render_pass: vk::RenderPass,
-    pipeline_layout: vk::PipelineLayout,
-    graphics_pipeline: vk::Pipeline,
+    object_id_image: ImageHandle,
+    object_id_image_view: vk::ImageView,
+
+    /// None without MSAA, the main pass then renders into the scene target and `object_id_image` directly
+    msaa_targets: Option<MsaaTargets>,
+
+    /// `TextDraw` glyphs, drawn in the output render pass of `post_process`
+    text_pipeline: vk::Pipeline,
+    /// `GuiOutput` meshes, drawn after the text
+    #[cfg(feature = "egui")]
+    gui_pipeline: vk::Pipeline,
 
-    descriptor_set: vk::DescriptorSet,
+    /// Tonemapping, user stages and output encoding, writes `swapchain_images`
+    post_process: PostProcessChain,
 }
 
-pub struct VulkanApp {
-    // vulkan stuff
-    entry: ash::Entry,
-    instance: ash::Instance,
-    surface: vk::SurfaceKHR,
-    debug_utils_loader: Option<ash::extensions::ext::DebugUtils>,
-    debug_messenger: Option<vk::DebugUtilsMessengerEXT>,
+/// Multisampled attachments of the main pass, resolved into the scene target and `object_id_image`
+struct MsaaTargets {
+    color_image: ImageHandle,
+    color_image_view: vk::ImageView,
+    object_id_image: ImageHandle,
+    object_id_image_view: vk::ImageView,
+}
 
+/// Everything created from the logical device. Recreated from scratch when the device is lost.
+struct DeviceDependentResources {
     physical_device: vk::PhysicalDevice,
     device: ash::Device,
+    /// Detected from `physical_device` unless overridden by the config
+    device_profile: DeviceProfile,
 
     queue: vk::Queue,
 
-    swapchain_dependent_resources: Option<SwapchainDependentResources>,
-
     command_pool: vk::CommandPool,
     command_buffers: Vec<vk::CommandBuffer>,
 
     resource_manager: ResourceManager,
     resource_command_buffer: vk::CommandBuffer,
 
-    vertex_buffer: BufferResource,
+    /// One copy per in-flight frame, so the CPU never writes a buffer the GPU may still read
+    vertex_buffers: Vec<BufferHandle>,
+    /// `DebugVertex` line lists, one copy per in-flight frame, grown on demand
+    debug_vertex_buffers: Vec<BufferHandle>,
+    /// `FrameUniforms`, one copy per in-flight frame
+    uniform_buffers: Vec<BufferHandle>,
+    /// `DrawData` of the scene draws, one copy per in-flight frame, grown on demand
+    draw_buffers: Vec<BufferHandle>,
+    /// Vertex and index buffers of `ResourceCache::meshes`
+    meshes: GpuMeshes,
+
+    /// Every descriptor set, kept across swapchain recreation
+    descriptor_allocator: DescriptorAllocator,
+    /// Textures and materials of `ResourceCache::materials`, descriptor set 1 of the main pass
+    materials: GpuMaterials,
+
+    /// Main pass, renders into the scene target of `SwapchainDependentResources::post_process`
+    render_pass: vk::RenderPass,
+    /// Samples per pixel of the main pass, TYPE_1 without MSAA
+    msaa_samples: vk::SampleCountFlags,
+    /// `FrameUniforms`, descriptor set 0 of the main pass
+    frame_descriptor_set_layout: vk::DescriptorSetLayout,
+    /// Set 0 of the main pass, one per in-flight frame, each points at that frame's uniform and draw buffers
+    frame_descriptor_sets: Vec<vk::DescriptorSet>,
+    /// Frame and material sets and the fragment push constants, shared by all pipelines of the main pass
+    pipeline_layout: vk::PipelineLayout,
+    /// Source image and `FrameUniforms` of the post-processing stages
+    post_process_descriptor_set_layout: vk::DescriptorSetLayout,
+    /// Pipelines of the main pass
+    pipeline_manager: PipelineManager,
 
-    image_view: vk::ImageView,
-    sampler: vk::Sampler,
+    /// Font atlas and vertex buffers of the HUD text
+    text_renderer: TextRenderer,
+    #[cfg(feature = "egui")]
+    gui_renderer: GuiRenderer,
 
     sync_objects: SyncObjects,
 
+    profiler: GpuProfiler,
+
+    depth_readback_slots: Vec<DepthReadbackSlot>,
+    pick_slots: Vec<PickSlot>,
+
+    /// `fillModeNonSolid` is enabled, needed for `DebugView::Wireframe`
+    wireframe_supported: bool,
+    /// `bufferDeviceAddress` is enabled, the scene is drawn with vert_pulling.spv reading the vertex address of each `DrawData`
+    vertex_pulling: bool,
+    /// Mesh draws are culled on the GPU and drawn indirectly, None without VK_KHR_draw_indirect_count or `drawIndirectFirstInstance`
+    culling: Option<GpuCulling>,
+}
+
+/// CPU-side copies of uploaded data, used to re-upload resources after device loss
+struct ResourceCache {
+    vertex_buffer_size: vk::DeviceSize,
+
+    materials: MaterialCache,
+    meshes: MeshCache,
+}
+
+pub struct VulkanApp {
+    // vulkan stuff
+    entry: ash::Entry,
+    instance: ash::Instance,
+    /// None in headless mode
+    surface: Option<vk::SurfaceKHR>,
+    /// Size of the offscreen render target, set only in headless mode
+    headless_extent: Option<vk::Extent2D>,
+    debug_utils_loader: Option<ash::extensions::ext::DebugUtils>,
+    debug_messenger: Option<vk::DebugUtilsMessengerEXT>,
+    /// User data of the debug messenger callback, must outlive the messenger
+    _debug_config: Box<DebugConfig>,
+
+    device_dependent_resources: Option<DeviceDependentResources>,
+    swapchain_dependent_resources: Option<SwapchainDependentResources>,
+
+    resource_cache: ResourceCache,
+    #[cfg(feature = "egui")]
+    gui_textures: GuiTextures,
+    gpu_preference: GpuPreference,
+    config: RendererConfig,
+
+    depth_readback_request: Option<(u32, u32)>,
+    depth_readback_result: Option<DepthReadback>,
+
+    pick_request: Option<(u32, u32)>,
+    pick_result: Option<PickResult>,
+
+    frame_timer: FrameTimer,
+    batch_stats: BatchStats,
+    frame_uniforms: FrameUniforms,
+    calibration_pattern: bool,
+
     cur_frame: usize,
     in_flight_frame: usize,
-
-    query_pool: vk::QueryPool,
 }
 
 const IN_FLIGHT_FRAMES: usize = 2;
 
 impl VulkanApp {
-    pub fn new(glfw: &glfw::Glfw, window: &glfw::Window, vertex_data: &Vec<f32>) -> VulkanApp {
+    /// `vertex_data` only sizes the initial vertex buffers, they grow when a `Frame` needs more
+    pub fn new(glfw: &glfw::Glfw, window: &glfw::Window, vertex_data: &[Vertex], config: RendererConfig) -> VulkanApp {
+        let required_extensions = glfw.get_required_instance_extensions().unwrap();
+        VulkanApp::create(required_extensions, Some(window), None, vertex_data, config)
+    }
+
+    /// Renders into an offscreen color image instead of a window, read it with `read_back_frame`
+    pub fn new_headless(width: u32, height: u32, vertex_data: &[Vertex], config: RendererConfig) -> VulkanApp {
+        VulkanApp::create(Vec::new(), None, Some(vk::Extent2D { width, height }), vertex_data, config)
+    }
 
-        let required_extensions = glfw.get_required_instance_extensions().unwrap().iter()
+    fn create(required_extensions: Vec<String>, window: Option<&glfw::Window>, headless_extent: Option<vk::Extent2D>, vertex_data: &[Vertex], config: RendererConfig) -> VulkanApp {
+        let required_extensions = required_extensions.iter()
             .map(|s| s.clone()+"\0")
             .collect::<Vec<String>>();
 
@@ -90,10 +268,10 @@ impl VulkanApp {
             instance_extensions.push(i.as_ptr() as *const i8);
         }
 
-        let mut validation_layers = Vec::new();
-        if cfg!(debug_assertions) {
+        let debug_config = Box::new(config.debug.clone());
+        let validation_layers = validation_layer_names(debug_config.validation);
+        if debug_config.validation {
             instance_extensions.push(vk::ExtDebugUtilsFn::name().as_ptr());
-            validation_layers.push("VK_LAYER_KHRONOS_validation\0".as_ptr() as *const i8);
         }
 
 
@@ -101,6 +279,35 @@ impl VulkanApp {
         //check if extensions are supported
         let mut supported = true;
         let available_extensions = entry.enumerate_instance_extension_properties(None).unwrap();
+
+        //optional: without it the surface reports SDR formats only
+        if window.is_some() && config.color_space.needs_colorspace_extension() {
+            let colorspace_ext_name = vk::ExtSwapchainColorspaceFn::name();
+            let colorspace_ext_available = available_extensions.iter().any(|e| {
+                let available_ext_name = unsafe { std::ffi::CStr::from_ptr(e.extension_name.as_ptr()) };
+                available_ext_name == colorspace_ext_name
+            });
+            if colorspace_ext_available {
+                instance_extensions.push(colorspace_ext_name.as_ptr());
+            } else {
+                warn!("Extension {} is not supported, HDR output is unavailable", colorspace_ext_name.to_str().unwrap());
+            }
+        }
+        //optional: provided by the validation layer itself
+        let mut validation_features_enabled = false;
+        if debug_config.uses_validation_features() {
+            let validation_features_name = vk::ExtValidationFeaturesFn::name();
+            let layer_extensions = entry.enumerate_instance_extension_properties(Some(VALIDATION_LAYER_NAME)).unwrap_or_default();
+            let validation_features_available = layer_extensions.iter().any(|e| {
+                let available_ext_name = unsafe { std::ffi::CStr::from_ptr(e.extension_name.as_ptr()) };
+                available_ext_name == validation_features_name
+            });
+            if validation_features_available {
+                validation_features_enabled = true;
+            } else {
+                warn!("Extension {} is not supported, GPU-assisted and best practices validation are unavailable", validation_features_name.to_str().unwrap());
+            }
+        }
         for i in &instance_extensions {
             let requested_ext_name = unsafe { std::ffi::CStr::from_ptr(*i) };
             let mut found = false;
@@ -112,7 +319,7 @@ impl VulkanApp {
                 }
             }
             if !found {
-                println!("Extension {} is not supported", requested_ext_name.to_str().unwrap());
+                warn!("Extension {} is not supported", requested_ext_name.to_str().unwrap());
                 supported = false;
             }
         }
@@ -133,7 +340,7 @@ impl VulkanApp {
                 }
             }
             if !found {
-                println!("Layer {} is not supported", requested_layer_name.to_str().unwrap());
+                warn!("Layer {} is not supported", requested_layer_name.to_str().unwrap());
                 supported = false;
             }
         }
@@ -158,13 +365,22 @@ impl VulkanApp {
             enabled_layer_count: validation_layers.len().try_into().unwrap(),
             ..Default::default()
         };
-        let debug_messanger_create_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
-            .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
-            .message_type(vk::DebugUtilsMessageTypeFlagsEXT::GENERAL | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE)
+        let mut debug_messanger_create_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
+            .message_severity(debug_config.severities)
+            .message_type(debug_config.message_types)
             .pfn_user_callback(Some(vulkan_debug_callback))
+            .user_data(&*debug_config as *const DebugConfig as *mut c_void)
+            .build();
+        let enabled_validation_features = debug_config.enabled_validation_features();
+        let validation_features = vk::ValidationFeaturesEXT::builder()
+            .enabled_validation_features(&enabled_validation_features)
             .build();
-        if cfg!(debug_assertions) {
-            println!("Validation layers enabled");
+        if debug_config.validation {
+            info!("Validation layers enabled");
+            if validation_features_enabled {
+                info!("Validation features: {:?}", enabled_validation_features);
+                debug_messanger_create_info.p_next = &validation_features as *const _ as *const c_void;
+            }
             create_info.p_next = &debug_messanger_create_info as *const _ as *const c_void;
         }
         let instance_res = unsafe { entry.create_instance(&create_info, None) };
@@ -173,18 +389,20 @@ impl VulkanApp {
         match instance_res {
             Ok(i) => {
                 instance = i;
-                println!("Instance created");
+                info!("Instance created");
 
             },
             Err(e) => {
-                println!("Instance creation failed: {:?}", e);
+                error!("Instance creation failed: {:?}", e);
                 panic!("Instance creation failed");
             }
         }
         // Instance is created
         let debug_utils_loader: Option<ash::extensions::ext::DebugUtils>;
         let debug_messenger: Option<vk::DebugUtilsMessengerEXT>;
-        if cfg!(debug_assertions) {
+        if debug_config.validation {
+            // the p_next chain only applies to instance creation
+            debug_messanger_create_info.p_next = ptr::null();
             let debug_utils_loader_ins = extensions::ext::DebugUtils::new(&entry, &instance);
             debug_messenger = Some(unsafe {debug_utils_loader_ins.create_debug_utils_messenger(&debug_messanger_create_info, None).unwrap()});
             debug_utils_loader = Some(debug_utils_loader_ins);
@@ -194,61 +412,197 @@ impl VulkanApp {
             debug_messenger = None;
         }
         
-        let physical_devices = unsafe { instance.enumerate_physical_devices().unwrap() };
-
-        let physical_device = *physical_devices.iter().find(|&d| {
-            let properties = unsafe { instance.get_physical_device_properties(*d) };
-            properties.device_type == vk::PhysicalDeviceType::DISCRETE_GPU
-        }).or_else(|| {
-            physical_devices.iter().find(|&d| {
-                let properties = unsafe { instance.get_physical_device_properties(*d) };
-                properties.device_type == vk::PhysicalDeviceType::INTEGRATED_GPU
-            })
-        }).or_else(|| {
-            physical_devices.iter().find(|&d| {
-                let properties = unsafe { instance.get_physical_device_properties(*d) };
-                properties.device_type == vk::PhysicalDeviceType::CPU
-            })
-        }).unwrap_or_else(|| {
+        let surface = window.map(|window| VulkanApp::create_surface(&instance, window));
+
+        let image_path = "img.png";
+        let image_object = image::open(image_path).unwrap(); 
+
+        let (image_width, image_height) = (image_object.width(), image_object.height());
+        let image_size =
+            (std::mem::size_of::<u8>() as u32 * image_width * image_height * 4) as vk::DeviceSize;
+
+        let image_data = match &image_object {
+            image::DynamicImage::ImageLuma8(_)
+            | image::DynamicImage::ImageRgb8(_) => image_object.to_rgba8().into_raw(),
+            image::DynamicImage::ImageLumaA8(_)
+            | image::DynamicImage::ImageRgba8(_) => image_object.into_bytes(),
+            _ => panic!("Unsupported image format"),
+        };
+
+        if image_size == 0 {
+            panic!("Failed to load texture image!")
+        }
+
+        let mut materials = MaterialCache::default();
+        materials.add_texture(image_width, image_height, image_data);
+        materials.add_material(Material::default());
+
+        let resource_cache = ResourceCache {
+            vertex_buffer_size: mem::size_of_val(vertex_data) as u64,
+
+            materials,
+            meshes: MeshCache::default(),
+        };
+
+        let gpu_preference = GpuPreference::Auto;
+        let mut device_dependent_stuff = VulkanApp::create_device_dependent_resources(&entry, &instance, surface, debug_utils_loader.as_ref(), &resource_cache, gpu_preference, &config);
+
+        let swapchain_dependent_stuff = match (window, surface) {
+            (Some(window), Some(surface)) => VulkanApp::create_swapchain_dependent_resources(window, &entry, &instance, &mut device_dependent_stuff, surface, &config, None), // swapchain and all dependent resources are created
+            _ => VulkanApp::create_offscreen_resources(&mut device_dependent_stuff, headless_extent.unwrap(), &config),
+        };
+
+        // headless rendering is never paced
+        let mut frame_timer = FrameTimer::new(IN_FLIGHT_FRAMES);
+        if window.is_some() {
+            frame_timer.set_frame_rate_cap(device_dependent_stuff.device_profile.frame_rate_cap());
+        }
+
+        VulkanApp {
+            entry,
+            instance,
+            debug_utils_loader,
+            debug_messenger,
+            _debug_config: debug_config,
+            surface,
+            headless_extent,
+            device_dependent_resources: Some(device_dependent_stuff),
+            swapchain_dependent_resources: Some(swapchain_dependent_stuff),
+
+            resource_cache,
+            #[cfg(feature = "egui")]
+            gui_textures: GuiTextures::default(),
+            gpu_preference,
+            config,
+
+            depth_readback_request: None,
+            depth_readback_result: None,
+
+            pick_request: None,
+            pick_result: None,
+
+            frame_timer,
+            batch_stats: BatchStats::default(),
+            frame_uniforms: FrameUniforms::default(),
+            calibration_pattern: false,
+
+            cur_frame: 0,
+            in_flight_frame: 0,
+        }
+    }
+
+    fn create_surface(instance: &ash::Instance, window: &glfw::Window) -> vk::SurfaceKHR {
+        let mut surface : u64 = 0;
+        let res = window.create_window_surface(instance.handle().as_raw() as usize, std::ptr::null(), &mut surface);
+        if res != vk::Result::SUCCESS.as_raw() as u32 {
+            panic!("Failed to create window surface: {:?}", vk::Result::from_raw(res as i32));
+        }
+        vk::SurfaceKHR::from_raw(surface)
+    }
+
+    /// `surface` is None in headless mode, the device is created without the swapchain extension then
+    /// `debug_utils` is used to name the created objects, None when validation is disabled.
+    /// `config` picks the GPU and overrides its device profile.
+    fn create_device_dependent_resources(entry: &ash::Entry, instance: &ash::Instance, surface: Option<vk::SurfaceKHR>, debug_utils: Option<&extensions::ext::DebugUtils>, resource_cache: &ResourceCache, gpu_preference: GpuPreference, config: &RendererConfig) -> DeviceDependentResources {
+        let surface_loader = extensions::khr::Surface::new(entry, instance);
+        let surface_support = surface.map(|surface| (&surface_loader, surface));
+        let physical_device = gpu_selection::select_physical_device(instance, gpu_preference, config.gpu.as_ref(), surface_support).unwrap_or_else(|| {
             panic!("No avaliable physical device found");
         });
         
         //select chosen physical device
         let dev_name_array = unsafe { instance.get_physical_device_properties(physical_device).device_name };
         let dev_name = unsafe {std::ffi::CStr::from_ptr(dev_name_array.as_ptr())};
-        println!("Chosen device: {}", dev_name.to_str().unwrap());
+        info!("Chosen device: {}", dev_name.to_str().unwrap());
+        let device_profile = config.device_profile.unwrap_or_else(|| DeviceProfile::detect(instance, physical_device));
+        info!("Device profile: {:?}", device_profile);
 
 
-        let queue_family_properties = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
-        let queue_family_index = queue_family_properties.iter().enumerate().find(|(_, p)| {
-            p.queue_flags.contains(vk::QueueFlags::GRAPHICS) 
-        }).map(|(i, _)| i as u32).unwrap();
+        // the selected device always has a family that can present
+        let queue_family_index = gpu_selection::graphics_queue_family(instance, physical_device, surface_support).unwrap();
 
-        let mut surface : u64 = 0;
-        window.create_window_surface(instance.handle().as_raw() as usize, std::ptr::null(), &mut surface);
-        let surface = vk::SurfaceKHR::from_raw(surface);
-
-        let presentation_support = glfw.get_physical_device_presentation_support_raw(instance.handle().as_raw() as usize, physical_device.as_raw() as usize, queue_family_index);
-        if !presentation_support {
-            panic!("Presentation not supported");
-        }
+        let validation_layers = validation_layer_names(debug_utils.is_some());
 
         let mut device_extensions = vec![];
-        device_extensions.push(vk::KhrSwapchainFn::name().as_ptr());
+        if surface.is_some() {
+            device_extensions.push(vk::KhrSwapchainFn::name().as_ptr());
+        }
 
         let queue_create_infos = [vk::DeviceQueueCreateInfo::builder()
             .queue_family_index(queue_family_index)
             .queue_priorities(&[1.0])
             .build()];
-        let device_create_info = vk::DeviceCreateInfo::builder()
+
+        let supported_features = unsafe { instance.get_physical_device_features(physical_device) };
+        let enabled_features = vk::PhysicalDeviceFeatures::builder()
+            .pipeline_statistics_query(supported_features.pipeline_statistics_query == vk::TRUE)
+            // wireframe debug view
+            .fill_mode_non_solid(supported_features.fill_mode_non_solid == vk::TRUE)
+            // indirect draws of GPU culling start at the draw's `DrawData` through firstInstance
+            .draw_indirect_first_instance(supported_features.draw_indirect_first_instance == vk::TRUE)
+            .build();
+
+        // bindless textures and vertex pulling, both core since Vulkan 1.2.
+        // Descriptor indexing is an extension of 1.1 before, the buffer device address extension is not used.
+        let device_api_version = unsafe { instance.get_physical_device_properties(physical_device).api_version };
+        let descriptor_indexing_extension = device_api_version < vk::API_VERSION_1_2;
+        let mut supported_descriptor_indexing = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+        let mut supported_buffer_device_address = vk::PhysicalDeviceBufferDeviceAddressFeatures::default();
+        if device_api_version >= vk::API_VERSION_1_2
+            || (device_api_version >= vk::API_VERSION_1_1 && gpu_selection::supports_extension(instance, physical_device, vk::ExtDescriptorIndexingFn::name())) {
+            let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut supported_descriptor_indexing);
+            if device_api_version >= vk::API_VERSION_1_2 {
+                features = features.push_next(&mut supported_buffer_device_address);
+            }
+            unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+        }
+        let bindless_supported = material::bindless_supported(&supported_descriptor_indexing);
+        info!("Bindless textures: {}", if bindless_supported { "supported" } else { "not supported" });
+        let mut enabled_descriptor_indexing = vk::PhysicalDeviceDescriptorIndexingFeatures::builder()
+            .runtime_descriptor_array(true)
+            .descriptor_binding_variable_descriptor_count(true)
+            .descriptor_binding_partially_bound(true)
+            .descriptor_binding_sampled_image_update_after_bind(true)
+            .descriptor_binding_update_unused_while_pending(true)
+            .shader_sampled_image_array_non_uniform_indexing(true);
+        if bindless_supported && descriptor_indexing_extension {
+            device_extensions.push(vk::ExtDescriptorIndexingFn::name().as_ptr());
+        }
+        let vertex_pulling = supported_buffer_device_address.buffer_device_address == vk::TRUE;
+        info!("Vertex pulling: {}", if vertex_pulling { "supported" } else { "not supported" });
+        let mut enabled_buffer_device_address = vk::PhysicalDeviceBufferDeviceAddressFeatures::builder()
+            .buffer_device_address(true);
+        // the extension instead of the 1.2 feature, which can't be enabled next to the feature structs above
+        let gpu_culling = enabled_features.draw_indirect_first_instance == vk::TRUE
+            && gpu_selection::supports_extension(instance, physical_device, vk::KhrDrawIndirectCountFn::name());
+        info!("GPU culling: {}", if gpu_culling { "supported" } else { "not supported" });
+        if gpu_culling {
+            device_extensions.push(vk::KhrDrawIndirectCountFn::name().as_ptr());
+        }
+        // queried with vkGetPhysicalDeviceMemoryProperties2, core in 1.1
+        let memory_budget = device_api_version >= vk::API_VERSION_1_1
+            && gpu_selection::supports_extension(instance, physical_device, vk::ExtMemoryBudgetFn::name());
+        info!("Memory budget: {}", if memory_budget { "supported" } else { "not supported" });
+        if memory_budget {
+            device_extensions.push(vk::ExtMemoryBudgetFn::name().as_ptr());
+        }
+
+        let mut device_create_info = vk::DeviceCreateInfo::builder()
             .queue_create_infos(&queue_create_infos)
             .enabled_extension_names(&device_extensions)
-            .enabled_layer_names(&validation_layers);
+            .enabled_layer_names(&validation_layers)
+            .enabled_features(&enabled_features);
+        if bindless_supported {
+            device_create_info = device_create_info.push_next(&mut enabled_descriptor_indexing);
+        }
+        if vertex_pulling {
+            device_create_info = device_create_info.push_next(&mut enabled_buffer_device_address);
+        }
 
         let device = unsafe { instance.create_device(physical_device, &device_create_info, None).unwrap() };
         
 
-        // Device and Surface are created
+        // Device is created
 
         
         let queue = unsafe { device.get_device_queue(queue_family_index, 0) };
@@ -286,115 +640,422 @@ impl VulkanApp {
             .command_buffer_count(1)
             .build()).unwrap() }[0];
 
-        let mut resource_manager = ResourceManager::new(&instance, physical_device, device.clone(), queue, resource_command_buffer);
-        
-
-        let vertex_buffer = resource_manager.create_buffer(vertex_data.len() as u64 * 4 , vk::BufferUsageFlags::VERTEX_BUFFER);
-        
-        let image_path = "img.png";
-        let image_object = image::open(image_path).unwrap(); 
+        let mut resource_manager = ResourceManager::new(instance, physical_device, device.clone(), queue, resource_command_buffer, debug_utils.cloned(), memory_budget);
 
-        let (image_width, image_height) = (image_object.width(), image_object.height());
-        let image_size =
-            (std::mem::size_of::<u8>() as u32 * image_width * image_height * 4) as vk::DeviceSize;
-
-        let image_data = match &image_object {
-            image::DynamicImage::ImageLuma8(_)
-            | image::DynamicImage::ImageRgb8(_) => image_object.to_rgba8().into_raw(),
-            image::DynamicImage::ImageLumaA8(_)
-            | image::DynamicImage::ImageRgba8(_) => image_object.into_bytes(),
-            _ => panic!("Unsupported image format"),
-        };
-
-        if image_size == 0 {
-            panic!("Failed to load texture image!")
+        resource_manager.set_name(device.handle(), "device");
+        resource_manager.set_name(queue, "graphics_queue");
+        resource_manager.set_name(command_pool, "command_pool");
+        for (i, command_buffer) in command_buffers.iter().enumerate() {
+            resource_manager.set_name(*command_buffer, &format!("frame_command_buffer[{}]", i));
+        }
+        resource_manager.set_name(resource_command_buffer, "resource_command_buffer");
+        for (i, semaphore) in image_available_semaphores.iter().enumerate() {
+            resource_manager.set_name(*semaphore, &format!("image_available_semaphore[{}]", i));
+        }
+        for (i, semaphore) in render_finished_semaphores.iter().enumerate() {
+            resource_manager.set_name(*semaphore, &format!("render_finished_semaphore[{}]", i));
+        }
+        for (i, fence) in in_flight_fences.iter().enumerate() {
+            resource_manager.set_name(*fence, &format!("in_flight_fence[{}]", i));
         }
 
-        let vk_image = resource_manager.create_image(image_width, 
-            image_height, 
-            vk::Format::R8G8B8A8_UNORM, 
-            vk::ImageTiling::OPTIMAL,
-            vk::ImageUsageFlags::SAMPLED);
-
-        resource_manager.fill_image(vk_image, image_data.as_slice());
+        let vertex_buffers = (0..IN_FLIGHT_FRAMES).map(|i| {
+            let vertex_buffer = resource_manager.create_buffer(resource_cache.vertex_buffer_size, scene_vertex_buffer_usage(vertex_pulling));
+            resource_manager.set_buffer_name(&vertex_buffer, &format!("vertex_buffer[{}]", i));
+            vertex_buffer
+        }).collect();
+
+        let debug_vertex_buffers = (0..IN_FLIGHT_FRAMES).map(|i| {
+            let debug_vertex_buffer = resource_manager.create_buffer(debug_draw::INITIAL_BUFFER_SIZE, vk::BufferUsageFlags::VERTEX_BUFFER);
+            resource_manager.set_buffer_name(&debug_vertex_buffer, &format!("debug_vertex_buffer[{}]", i));
+            debug_vertex_buffer
+        }).collect();
+
+        let uniform_buffers: Vec<_> = (0..IN_FLIGHT_FRAMES).map(|i| {
+            let uniform_buffer = resource_manager.create_buffer(mem::size_of::<FrameUniforms>() as vk::DeviceSize, vk::BufferUsageFlags::UNIFORM_BUFFER);
+            resource_manager.set_buffer_name(&uniform_buffer, &format!("frame_uniform_buffer[{}]", i));
+            uniform_buffer
+        }).collect();
+
+        let draw_buffers: Vec<_> = (0..IN_FLIGHT_FRAMES).map(|i| {
+            let draw_buffer = resource_manager.create_buffer(draw_list::INITIAL_DRAW_BUFFER_SIZE, vk::BufferUsageFlags::STORAGE_BUFFER);
+            resource_manager.set_buffer_name(&draw_buffer, &format!("draw_buffer[{}]", i));
+            draw_buffer
+        }).collect();
+
+        let mut meshes = GpuMeshes::default();
+        meshes.sync(&mut resource_manager, &resource_cache.meshes, scene_vertex_buffer_usage(vertex_pulling)).unwrap();
+
+        let msaa_samples = msaa_sample_count(instance, physical_device, config.msaa);
+        info!("MSAA: {} samples", msaa_samples.as_raw());
+        let render_pass = VulkanApp::create_main_render_pass(&device, msaa_samples);
+
+        //descriptor sets and push constants as declared by the main shaders: frame uniforms, material and object id
+        let main_fragment_shader = if bindless_supported { "frag_bindless.spv" } else { "frag.spv" };
+        let main_shaders = [reflection::reflect(&shaders::load_spirv("vert.spv")), reflection::reflect(&shaders::load_spirv(main_fragment_shader))];
+        let main_shaders = [&main_shaders[0], &main_shaders[1]];
+        let frame_descriptor_set_layout_bindings = reflection::descriptor_set_layout_bindings(&main_shaders, 0);
+        let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&frame_descriptor_set_layout_bindings);
+        let frame_descriptor_set_layout = unsafe { device.create_descriptor_set_layout(&descriptor_set_layout_create_info, None).unwrap() };
+        resource_manager.set_name(frame_descriptor_set_layout, "frame_descriptor_set_layout");
+
+        let mut descriptor_allocator = DescriptorAllocator::new();
+        let frame_descriptor_sets: Vec<_> = uniform_buffers.iter().zip(&draw_buffers).enumerate().map(|(i, (uniform_buffer, draw_buffer))| {
+            let descriptor_set = descriptor_allocator.allocate(&device, &resource_manager, frame_descriptor_set_layout);
+            resource_manager.set_name(descriptor_set, &format!("frame_descriptor_set[{}]", i));
+            write_frame_descriptor_set(&device, &resource_manager, descriptor_set, uniform_buffer, draw_buffer);
+            descriptor_set
+        }).collect();
 
-        let image_view = resource_manager.create_image_view(vk_image.image, vk::Format::R8G8B8A8_UNORM, vk::ImageAspectFlags::COLOR);
+        let bindless_capacity = bindless_supported.then(|| material::bindless_capacity(instance, physical_device));
+        let mut materials = GpuMaterials::new(&device, &resource_manager, &reflection::descriptor_set_layout_bindings(&main_shaders, 1), bindless_capacity);
+        materials.sync(&device, &mut resource_manager, &mut descriptor_allocator, &resource_cache.materials).unwrap();
 
-        let sampler = resource_manager.create_sampler();
+        let push_constant_ranges = reflection::push_constant_ranges(&main_shaders);
+        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&[frame_descriptor_set_layout, materials.descriptor_set_layout()])
+            .push_constant_ranges(&push_constant_ranges)
+            .build();
 
-        let swapchain_dependent_stuff =  VulkanApp::create_swapchain_dependent_resources(window, &entry, &instance, &physical_device, surface, &device, image_view, sampler, None); // swapchain and all dependent resources are created
+        let pipeline_layout = unsafe { device.create_pipeline_layout(&pipeline_layout_create_info, None).unwrap() };
+        let pipeline_manager = PipelineManager::new(render_pass, pipeline_layout, msaa_samples);
+        resource_manager.set_name(render_pass, "main_render_pass");
+        resource_manager.set_name(pipeline_layout, "main_pipeline_layout");
 
+        //the source image and frame uniforms of the post-processing stages
+        let post_process_shaders = ["post_vert.spv", "tonemap.spv", "output.spv"].map(|name| reflection::reflect(&shaders::load_spirv(name)));
+        let post_process_descriptor_set_layout_bindings = reflection::descriptor_set_layout_bindings(&post_process_shaders.iter().collect::<Vec<_>>(), 0);
+        let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&post_process_descriptor_set_layout_bindings);
+        let post_process_descriptor_set_layout = unsafe { device.create_descriptor_set_layout(&descriptor_set_layout_create_info, None).unwrap() };
+        resource_manager.set_name(post_process_descriptor_set_layout, "post_process_descriptor_set_layout");
 
-        // Perform some queries
+        let text_renderer = TextRenderer::new(&device, &mut resource_manager, &mut descriptor_allocator, IN_FLIGHT_FRAMES);
+        let culling = gpu_culling.then(|| GpuCulling::new(instance, &device, &mut resource_manager, &mut descriptor_allocator, IN_FLIGHT_FRAMES));
+        #[cfg(feature = "egui")]
+        let gui_renderer = GuiRenderer::new(&device, &mut resource_manager, IN_FLIGHT_FRAMES);
 
-        let query_pool_info = QueryPoolCreateInfo::builder()
-            .query_type(QueryType::TIMESTAMP)
-            .query_count(2)
-            .build();
+        let profiler = GpuProfiler::new(instance, physical_device, &device, IN_FLIGHT_FRAMES, enabled_features.pipeline_statistics_query == vk::TRUE);
+        profiler.set_debug_names(&resource_manager);
 
-        let query_pool = unsafe { device.create_query_pool(&query_pool_info, None).unwrap() };
+        let depth_readback_slots = (0..IN_FLIGHT_FRAMES).map(|i| DepthReadbackSlot::new(&mut resource_manager, i)).collect();
+        let pick_slots = (0..IN_FLIGHT_FRAMES).map(|i| PickSlot::new(&mut resource_manager, i)).collect();
 
-        VulkanApp {
-            entry,
-            instance,
-            debug_utils_loader,
-            debug_messenger,
+        DeviceDependentResources {
             physical_device,
             device,
-            surface,
+            device_profile,
             queue,
-            swapchain_dependent_resources: Some(swapchain_dependent_stuff),
             command_pool,
             command_buffers,
 
             resource_manager,
             resource_command_buffer,
 
-            vertex_buffer,
+            vertex_buffers,
+            debug_vertex_buffers,
+            uniform_buffers,
+            draw_buffers,
+            meshes,
+
+            descriptor_allocator,
+            materials,
+
+            render_pass,
+            msaa_samples,
+            frame_descriptor_set_layout,
+            frame_descriptor_sets,
+            pipeline_layout,
+            post_process_descriptor_set_layout,
+            pipeline_manager,
 
-            image_view,
-            sampler,
+            text_renderer,
+            #[cfg(feature = "egui")]
+            gui_renderer,
 
             sync_objects: SyncObjects {
                 image_available_semaphores,
                 render_finished_semaphores,
                 in_flight_fences,
             },
-            cur_frame: 0,
-            in_flight_frame: 0,
 
-            query_pool,
+            profiler,
+
+            depth_readback_slots,
+            pick_slots,
+
+            wireframe_supported: enabled_features.fill_mode_non_solid == vk::TRUE,
+            vertex_pulling,
+            culling,
+        }
+    }
+
+    fn destroy_device_dependent_resources(device_dependent_resources: DeviceDependentResources) {
+        let DeviceDependentResources {
+            device,
+            command_pool,
+            mut resource_manager,
+            descriptor_allocator,
+            materials,
+            meshes,
+            render_pass,
+            frame_descriptor_set_layout,
+            pipeline_layout,
+            post_process_descriptor_set_layout,
+            pipeline_manager,
+            text_renderer,
+            #[cfg(feature = "egui")]
+            gui_renderer,
+            sync_objects,
+            profiler,
+            culling,
+            ..
+        } = device_dependent_resources;
+
+        unsafe {
+            // ignore the result: a lost device still has to be torn down
+            let _ = device.device_wait_idle();
+
+            profiler.destroy(&device);
+            pipeline_manager.destroy(&device);
+            device.destroy_pipeline_layout(pipeline_layout, None);
+            device.destroy_descriptor_set_layout(post_process_descriptor_set_layout, None);
+            device.destroy_descriptor_set_layout(frame_descriptor_set_layout, None);
+            device.destroy_render_pass(render_pass, None);
+            materials.destroy(&device, &mut resource_manager);
+            meshes.destroy(&mut resource_manager);
+            descriptor_allocator.destroy(&device);
+            text_renderer.destroy(&device, &mut resource_manager);
+            if let Some(culling) = culling {
+                culling.destroy(&mut resource_manager);
+            }
+            #[cfg(feature = "egui")]
+            gui_renderer.destroy(&device, &mut resource_manager);
+
+            for semaphore in sync_objects.image_available_semaphores.iter().chain(sync_objects.render_finished_semaphores.iter()) {
+                device.destroy_semaphore(*semaphore, None);
+            }
+            for fence in sync_objects.in_flight_fences.iter() {
+                device.destroy_fence(*fence, None);
+            }
+
+            // buffers, images and the transfer fence are freed by the resource manager
+            drop(resource_manager);
+
+            device.destroy_command_pool(command_pool, None);
+            device.destroy_device(None);
         }
     }
 
-    pub fn draw_frame(&mut self, vertex_data: &[f32]) -> bool {
+    /// Returns false if the frame was dropped because the swapchain, surface or device had to be recreated
+    pub fn draw_frame(&mut self, window: &glfw::Window, frame: &Frame) -> bool {
+        self.frame_timer.begin_frame();
+        let res = match self.try_draw_frame(frame) {
+            Ok(()) => true,
+            Err(e) => {
+                self.recover_from_error(window, e);
+                false
+            }
+        };
+        self.frame_timer.end_frame();
+        res
+    }
+
+    /// Headless counterpart of `draw_frame`. Returns false if the frame was dropped because the device had to be recreated.
+    pub fn draw_offscreen_frame(&mut self, frame: &Frame) -> bool {
+        self.frame_timer.begin_frame();
+        let res = match self.try_draw_frame(frame) {
+            Ok(()) => true,
+            Err(vk::Result::ERROR_DEVICE_LOST) => {
+                warn!("Device lost, recreating device and re-uploading resources");
+                self.recreate_device(None);
+                false
+            },
+            Err(e) => panic!("Failed to draw frame: {}", e),
+        };
+        self.frame_timer.end_frame();
+        res
+    }
+
+    /// RGBA8 pixels of the last frame drawn with `draw_offscreen_frame`, row-major without padding.
+    /// Blocks until the GPU is done with that frame. Only available in headless mode.
+    pub fn read_back_frame(&mut self) -> Vec<u8> {
+        let swapchain = self.swapchain_dependent_resources.as_ref().unwrap();
+        let offscreen_image = swapchain.offscreen_image.as_ref().expect("read_back_frame is only available in headless mode");
+        let device_resources = self.device_dependent_resources.as_mut().unwrap();
+
+        // the render pass leaves the offscreen image in TRANSFER_SRC_OPTIMAL
+        unsafe { device_resources.device.device_wait_idle().expect("Failed to wait for device idle!"); }
+        device_resources.resource_manager.read_image(offscreen_image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL).expect("Failed to read back frame")
+    }
+
+    fn try_draw_frame(&mut self, draw_list: &Frame) -> VkResult<()> {
+        let mut stage_start = Instant::now();
         let frame = self.cur_frame;
         let in_flight_frame = self.in_flight_frame;
 
         let swapchain = self.swapchain_dependent_resources.as_ref().unwrap();
-        let device = &self.device;
+        let device_resources = self.device_dependent_resources.as_mut().unwrap();
+        let device = &device_resources.device;
+        let sync_objects = &device_resources.sync_objects;
+        let command_buffers = &device_resources.command_buffers;
         // 1) wait for image available
+        unsafe { device.wait_for_fences(&[sync_objects.in_flight_fences[in_flight_frame]], true, u64::MAX)?; }
+        self.frame_timer.fence_signaled(in_flight_frame);
+
+        // the frame that used this slot is complete, its readback can be collected
+        if let Some(readback) = device_resources.depth_readback_slots[in_flight_frame].take_result(&device_resources.resource_manager)? {
+            self.depth_readback_result = Some(readback);
+        }
+        if let Some(pick) = device_resources.pick_slots[in_flight_frame].take_result(&device_resources.resource_manager)? {
+            self.pick_result = Some(pick);
+        }
+
         let (image_index, _is_sub_optimal) = unsafe {
-            device.wait_for_fences(&[self.sync_objects.in_flight_fences[in_flight_frame]], true, std::u64::MAX).expect("Failed to wait for Fence!");
-
-            device.reset_fences(&[self.sync_objects.in_flight_fences[in_flight_frame]]).expect("Failed to reset Fence!");
-
-            swapchain.swapchain_loader
-                .acquire_next_image(
-                    swapchain.swapchain,
-                    std::u64::MAX,
-                    self.sync_objects.image_available_semaphores[frame],
-                    vk::Fence::null(),
-                )
-                .expect("Failed to acquire next image.")
+            // headless mode always renders into the single offscreen image
+            let res = match &swapchain.swapchain_loader {
+                Some(swapchain_loader) => swapchain_loader
+                    .acquire_next_image(
+                        swapchain.swapchain,
+                        u64::MAX,
+                        sync_objects.image_available_semaphores[frame],
+                        vk::Fence::null(),
+                    )?,
+                None => (0, false),
+            };
+
+            // reset only after a successful acquire, so the fence stays signaled if the frame is dropped
+            device.reset_fences(&[sync_objects.in_flight_fences[in_flight_frame]]).expect("Failed to reset Fence!");
+            res
         };
         if _is_sub_optimal {
-            println!("acquire_next_image: Suboptimal swapchain image");
+            warn!("acquire_next_image: Suboptimal swapchain image");
         }
+        self.frame_timer.lap("wait", &mut stage_start);
 
         // 2.0) update vertex buffer
-
-        self.resource_manager.fill_buffer(self.vertex_buffer, vertex_data);
+        // the fence wait above guarantees the GPU is done with this frame's copy
+
+        device_resources.materials.sync(&device_resources.device, &mut device_resources.resource_manager, &mut device_resources.descriptor_allocator, &self.resource_cache.materials)?;
+        device_resources.meshes.sync(&mut device_resources.resource_manager, &self.resource_cache.meshes, scene_vertex_buffer_usage(device_resources.vertex_pulling))?;
+
+        let vertex_data = draw_list.vertex_data();
+        let vertex_data_size = mem::size_of_val(vertex_data) as vk::DeviceSize;
+        if vertex_data_size > device_resources.resource_manager.buffer_size(&device_resources.vertex_buffers[in_flight_frame]) {
+            // grow geometrically, the old buffer is destroyed with a device wait
+            let size = vertex_data_size.max(self.resource_cache.vertex_buffer_size * 2);
+            debug!("Growing vertex buffer [{}] to {} bytes", in_flight_frame, size);
+            let resource_manager = &mut device_resources.resource_manager;
+            let vertex_buffer = resource_manager.create_buffer(size, scene_vertex_buffer_usage(device_resources.vertex_pulling));
+            resource_manager.set_buffer_name(&vertex_buffer, &format!("vertex_buffer[{}]", in_flight_frame));
+            let old_buffer = mem::replace(&mut device_resources.vertex_buffers[in_flight_frame], vertex_buffer);
+            resource_manager.destroy_buffer(old_buffer);
+            self.resource_cache.vertex_buffer_size = self.resource_cache.vertex_buffer_size.max(size);
+        }
+        // immediate geometry is already in world units and has no origin to measure
+        let in_render_distance = |draw: &&DrawCommand| match (draw.geometry, self.config.render_distance) {
+            (Geometry::Mesh(_), Some(distance)) => draw.model.translation.distance(draw_list.camera().position) <= distance,
+            _ => true,
+        };
+        let mut draws: Vec<_> = draw_list.draws().iter().filter(in_render_distance).collect();
+        if self.config.sort_draws {
+            let (cache, materials) = (&self.resource_cache.materials, &device_resources.materials);
+            draws.sort_by_key(|draw| batching::sort_key(draw, materials.fragment_shader(cache.material(draw.material))));
+        }
+        let batches = batching::batches(&draws);
+        // vertex addresses are resolved here, the frame only knows the transforms
+        let draw_data: Vec<_> = draws.iter().map(|draw| {
+            let vertex_buffer = match draw.geometry {
+                Geometry::Immediate { .. } => &device_resources.vertex_buffers[in_flight_frame],
+                Geometry::Mesh(mesh) => &device_resources.meshes.get(mesh).vertex_buffer,
+            };
+            DrawData {
+                vertices: if device_resources.vertex_pulling { device_resources.resource_manager.buffer_device_address(vertex_buffer) } else { 0 },
+                ..draw_list.draw_data(draw)
+            }
+        }).collect();
+        let draw_data_size = mem::size_of_val(draw_data.as_slice()) as vk::DeviceSize;
+        let draw_buffer_size = device_resources.resource_manager.buffer_size(&device_resources.draw_buffers[in_flight_frame]);
+        if draw_data_size > draw_buffer_size {
+            let size = draw_data_size.max(draw_buffer_size * 2);
+            debug!("Growing draw buffer [{}] to {} bytes", in_flight_frame, size);
+            let resource_manager = &mut device_resources.resource_manager;
+            let draw_buffer = resource_manager.create_buffer(size, vk::BufferUsageFlags::STORAGE_BUFFER);
+            resource_manager.set_buffer_name(&draw_buffer, &format!("draw_buffer[{}]", in_flight_frame));
+            // the set is only used by this frame, which the fence wait above finished
+            write_frame_descriptor_set(&device_resources.device, resource_manager, device_resources.frame_descriptor_sets[in_flight_frame],
+                &device_resources.uniform_buffers[in_flight_frame], &draw_buffer);
+            let old_buffer = mem::replace(&mut device_resources.draw_buffers[in_flight_frame], draw_buffer);
+            resource_manager.destroy_buffer(old_buffer);
+        }
+        let debug_lines = draw_list.debug_lines();
+        let debug_vertex_data = debug_lines.vertex_data(draw_list.camera());
+        let debug_vertex_data_size = (debug_vertex_data.len() * mem::size_of::<f32>()) as vk::DeviceSize;
+        let debug_buffer_size = device_resources.resource_manager.buffer_size(&device_resources.debug_vertex_buffers[in_flight_frame]);
+        if debug_vertex_data_size > debug_buffer_size {
+            let size = debug_vertex_data_size.max(debug_buffer_size * 2);
+            debug!("Growing debug vertex buffer [{}] to {} bytes", in_flight_frame, size);
+            let resource_manager = &mut device_resources.resource_manager;
+            let debug_vertex_buffer = resource_manager.create_buffer(size, vk::BufferUsageFlags::VERTEX_BUFFER);
+            resource_manager.set_buffer_name(&debug_vertex_buffer, &format!("debug_vertex_buffer[{}]", in_flight_frame));
+            let old_buffer = mem::replace(&mut device_resources.debug_vertex_buffers[in_flight_frame], debug_vertex_buffer);
+            resource_manager.destroy_buffer(old_buffer);
+        }
+        let hud = draw_list.hud_text();
+        device_resources.text_renderer.upload(&mut device_resources.resource_manager, in_flight_frame, &hud.vertex_data(swapchain.swapchain_extent))?;
+        #[cfg(feature = "egui")]
+        let gui_draws = match draw_list.gui() {
+            Some(gui) => {
+                self.gui_textures.set(&gui.textures_delta);
+                device_resources.gui_renderer.sync(&device_resources.device, &mut device_resources.resource_manager, &mut device_resources.descriptor_allocator, &self.gui_textures)?;
+                device_resources.gui_renderer.upload(&mut device_resources.resource_manager, in_flight_frame, &gui.primitives)?
+            },
+            None => Vec::new(),
+        };
+        let device = &device_resources.device;
+        let vertex_buffer = &device_resources.vertex_buffers[in_flight_frame];
+        if !vertex_data.is_empty() {
+            device_resources.resource_manager.fill_buffer(vertex_buffer, vertex_data)?;
+        }
+        if !draw_data.is_empty() {
+            device_resources.resource_manager.fill_buffer(&device_resources.draw_buffers[in_flight_frame], &draw_data)?;
+        }
+        // every instance of a mesh batch is tested on its own, the visible ones take the first command slots of the batch
+        let cull_draws: Vec<_> = batches.iter().enumerate().flat_map(|(batch_index, batch)| {
+            let mesh = match batch.geometry {
+                Geometry::Mesh(mesh) => Some(device_resources.meshes.get(mesh)),
+                Geometry::Immediate { .. } => None,
+            };
+            mesh.into_iter().flat_map(move |mesh| (0..batch.instance_count).map(move |instance| CullDraw {
+                bounds_min: mesh.bounds_min,
+                bounds_max: mesh.bounds_max,
+                draw: batch.first_draw + instance,
+                batch: batch_index as u32,
+                first_command: batch.first_draw,
+                index_count: mesh.index_count,
+            }))
+        }).collect();
+        if let Some(culling) = &mut device_resources.culling {
+            culling.upload(&mut device_resources.resource_manager, in_flight_frame, &cull_draws,
+                draws.len(), batches.len(), &device_resources.draw_buffers[in_flight_frame])?;
+        }
+        let debug_vertex_buffer = &device_resources.debug_vertex_buffers[in_flight_frame];
+        if !debug_vertex_data.is_empty() {
+            device_resources.resource_manager.fill_buffer(debug_vertex_buffer, &debug_vertex_data)?;
+        }
+        let frame_uniforms = FrameUniforms {
+            exposure: self.config.calibration.exposure,
+            gamma: self.config.calibration.gamma,
+            calibration_pattern: self.calibration_pattern as u32,
+            color_filter: self.config.color_filter.shader_value(),
+            // the test pattern and the debug views are shown with exposure only
+            tonemapper: if self.calibration_pattern || self.config.debug_view.shader_value() != 0 { Tonemapper::None } else { self.config.tonemapper }.shader_value(),
+            debug_view: self.config.debug_view.shader_value(),
+            ..self.frame_uniforms
+        };
+        device_resources.resource_manager.fill_buffer(&device_resources.uniform_buffers[in_flight_frame], &[frame_uniforms])?;
+        self.frame_timer.lap("upload", &mut stage_start);
 
         // println!("frame: {}, image_index: {}", frame, image_index);
         // 2.1) record command buffer
@@ -404,7 +1065,7 @@ impl VulkanApp {
 
         unsafe {
             let reset_res = device
-                .reset_command_buffer(self.command_buffers[frame], vk::CommandBufferResetFlags::empty());
+                .reset_command_buffer(command_buffers[frame], vk::CommandBufferResetFlags::empty());
             match reset_res {
                 Ok(_) => {},
                 Err(e) => {
@@ -414,45 +1075,200 @@ impl VulkanApp {
 
 
             let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
-                .render_pass(swapchain.render_pass)
-                .framebuffer(swapchain.swapchain_framebuffers[image_index as usize])
+                .render_pass(device_resources.render_pass)
+                .framebuffer(swapchain.framebuffer)
                 .render_area(vk::Rect2D {
                     offset: vk::Offset2D { x: 0, y: 0 },
                     extent: swapchain.swapchain_extent,
                 })
                 .clear_values(&[vk::ClearValue {
                     color: vk::ClearColorValue {
-                        float32: [0.8, 0.4, 0.7, 1.0],
+                        float32: draw_list.clear_color(),
+                    },
+                }, vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        depth: 1.0,
+                        stencil: 0,
+                    },
+                }, vk::ClearValue {
+                    // 0 means no entity
+                    color: vk::ClearColorValue {
+                        uint32: [0, 0, 0, 0],
                     },
                 }])
                 .build();
 
 
             device
-                .begin_command_buffer(self.command_buffers[frame], &command_buffer_begin_info)
+                .begin_command_buffer(command_buffers[frame], &command_buffer_begin_info)
                 .expect("Failed to begin recording command buffer!");
 
-            device.cmd_reset_query_pool(self.command_buffers[frame], self.query_pool, 0, 2);
-            device.cmd_write_timestamp(self.command_buffers[frame], vk::PipelineStageFlags::TOP_OF_PIPE, self.query_pool, 0);
-            device
-                .cmd_begin_render_pass(self.command_buffers[frame], &render_pass_begin_info, vk::SubpassContents::INLINE);
-            
-            device.cmd_bind_vertex_buffers(self.command_buffers[frame], 0, &[self.vertex_buffer.buffer], &[0]);
-           
-            device.cmd_bind_descriptor_sets(self.command_buffers[frame], vk::PipelineBindPoint::GRAPHICS, swapchain.pipeline_layout, 0, &[swapchain.descriptor_set], &[]);
-            device
-                .cmd_bind_pipeline(self.command_buffers[frame], vk::PipelineBindPoint::GRAPHICS, swapchain.graphics_pipeline);
-            
-            device
-                .cmd_draw(self.command_buffers[frame], 6, 1, 0, 0);
+            device_resources.profiler.begin_frame(device, command_buffers[frame], in_flight_frame)?;
+            let mut frame_scope = device_resources.profiler.scope(device, command_buffers[frame], "frame");
+
+            let resource_manager = &device_resources.resource_manager;
+            let depth_image = resource_manager.image(&swapchain.depth_image);
+            let object_id_image = resource_manager.image(&swapchain.object_id_image);
+            let scene_target = resource_manager.image(swapchain.post_process.scene_target());
+            let polygon_mode = match self.config.debug_view {
+                DebugView::Wireframe if device_resources.wireframe_supported => vk::PolygonMode::LINE,
+                _ => vk::PolygonMode::FILL,
+            };
+            // resolved before recording, the pipeline manager builds pipelines of new fragment shaders on first use
+            let materials = &device_resources.materials;
+            let meshes = &device_resources.meshes;
+            let scene_draws: Vec<_> = batches.iter().map(|batch| {
+                let material = self.resource_cache.materials.material(batch.material);
+                let pipeline = device_resources.pipeline_manager.get(device, resource_manager, &PipelineKey::scene(materials.fragment_shader(material), polygon_mode, device_resources.vertex_pulling));
+                // 0 is the cleared background, not pickable
+                let object_id = batch.entity.map_or(0, |entity| entity.0);
+                (batch, pipeline, materials.descriptor_set(batch.material), materials.push_constants(material, object_id))
+            }).collect();
+            let changes = |values: Vec<u64>| values.windows(2).filter(|pair| pair[0] != pair[1]).count() as u32 + !values.is_empty() as u32;
+            self.batch_stats = BatchStats {
+                draws: draws.len() as u32,
+                batches: batches.len() as u32,
+                pipeline_binds: changes(scene_draws.iter().map(|(_, pipeline, _, _)| pipeline.as_raw()).collect()),
+                material_binds: changes(scene_draws.iter().map(|(_, _, set, _)| set.as_raw()).collect()),
+            };
+            let debug_line_pipeline = device_resources.pipeline_manager.get(device, resource_manager, &PipelineKey::DEBUG_LINE);
+            let pipeline_layout = device_resources.pipeline_layout;
+            let viewports = [vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: swapchain.swapchain_extent.width as f32,
+                height: swapchain.swapchain_extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }];
+            let scissors = [vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: swapchain.swapchain_extent,
+            }];
+            let mut graph = RenderGraph::new();
+
+            let culling = device_resources.culling.as_ref();
+            if let Some(culling) = culling.filter(|_| !cull_draws.is_empty()) {
+                graph.add_pass(RenderGraph::pass("cull", move |frame_scope: &mut profiler::GpuScope, command_buffer| {
+                    let _cull_scope = frame_scope.scope("cull");
+                    culling.cmd_cull(command_buffer, resource_manager, in_flight_frame, cull_draws.len() as u32);
+                }));
+            }
 
-            device
-                .cmd_end_render_pass(self.command_buffers[frame]);
-            self.resource_manager.cmd_barrier_after_vertex_buffer_use(device, self.command_buffers[frame], &self.vertex_buffer);
-            device.cmd_write_timestamp(self.command_buffers[frame], vk::PipelineStageFlags::BOTTOM_OF_PIPE, self.query_pool, 1);
+            // the render pass transitions its attachments itself, the graph only tracks the final layouts
+            graph.add_pass(RenderGraph::pass("main render pass", |frame_scope: &mut profiler::GpuScope, command_buffer| {
+                let mut render_pass_scope = frame_scope.scope("render_pass");
+                let _statistics_scope = render_pass_scope.pipeline_statistics();
+                device
+                    .cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
+                
+                device.cmd_set_viewport(command_buffer, 0, &viewports);
+                device.cmd_set_scissor(command_buffer, 0, &scissors);
+               
+                device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline_layout, 0, &[device_resources.frame_descriptor_sets[in_flight_frame]], &[]);
+
+                let mut bound_pipeline = vk::Pipeline::null();
+                let mut bound_material_set = vk::DescriptorSet::null();
+                let mut bound_vertex_buffer = vk::Buffer::null();
+                let mut bound_index_buffer = vk::Buffer::null();
+                // the instance index selects the draw's `DrawData`, vert_pulling.spv ignores the bound vertex buffers
+                for (batch_index, (batch, pipeline, material_set, push_constants)) in scene_draws.iter().enumerate() {
+                    if *pipeline != bound_pipeline {
+                        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, *pipeline);
+                        bound_pipeline = *pipeline;
+                    }
+                    // bound once with bindless textures
+                    if *material_set != bound_material_set {
+                        device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline_layout, 1, &[*material_set], &[]);
+                        bound_material_set = *material_set;
+                    }
+                    device.cmd_push_constants(command_buffer, pipeline_layout, vk::ShaderStageFlags::FRAGMENT, 0, push_constants);
+                    match batch.geometry {
+                        Geometry::Immediate { first_vertex, vertex_count } => {
+                            let buffer = resource_manager.buffer(vertex_buffer);
+                            if buffer != bound_vertex_buffer {
+                                device.cmd_bind_vertex_buffers(command_buffer, 0, &[buffer], &[0]);
+                                bound_vertex_buffer = buffer;
+                            }
+                            device.cmd_draw(command_buffer, vertex_count, batch.instance_count, first_vertex, batch.first_draw);
+                        },
+                        Geometry::Mesh(mesh) => {
+                            let mesh = meshes.get(mesh);
+                            let buffer = resource_manager.buffer(&mesh.vertex_buffer);
+                            if buffer != bound_vertex_buffer {
+                                device.cmd_bind_vertex_buffers(command_buffer, 0, &[buffer], &[0]);
+                                bound_vertex_buffer = buffer;
+                            }
+                            let index_buffer = resource_manager.buffer(&mesh.index_buffer);
+                            if index_buffer != bound_index_buffer {
+                                device.cmd_bind_index_buffer(command_buffer, index_buffer, 0, vk::IndexType::UINT32);
+                                bound_index_buffer = index_buffer;
+                            }
+                            match culling {
+                                Some(culling) => culling.cmd_draw(command_buffer, resource_manager, in_flight_frame, batch_index as u32, batch.first_draw, batch.instance_count),
+                                None => device.cmd_draw_indexed(command_buffer, mesh.index_count, batch.instance_count, 0, 0, batch.first_draw),
+                            }
+                        },
+                    }
+                }
+
+                // over the scene, same layout, the debug line shaders use no descriptor sets
+                if debug_lines.vertex_count() > 0 {
+                    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, debug_line_pipeline);
+                    device.cmd_bind_vertex_buffers(command_buffer, 0, &[resource_manager.buffer(debug_vertex_buffer)], &[0]);
+                    device.cmd_draw(command_buffer, debug_lines.vertex_count(), 1, 0, 0);
+                }
+
+                device
+                    .cmd_end_render_pass(command_buffer);
+            })
+                .leaves(depth_image, ImageState::new(vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::PipelineStageFlags::LATE_FRAGMENT_TESTS, vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE))
+                .leaves(object_id_image, ImageState::new(vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags::COLOR_ATTACHMENT_WRITE))
+                .leaves(scene_target, post_process::TARGET_WRITTEN));
+
+            let text_renderer = &device_resources.text_renderer;
+            #[cfg(feature = "egui")]
+            let gui_renderer = &device_resources.gui_renderer;
+            swapchain.post_process.add_passes(&mut graph, device, resource_manager, in_flight_frame, image_index as usize, move |command_buffer| {
+                text_renderer.cmd_draw(device, command_buffer, resource_manager, swapchain.text_pipeline, in_flight_frame, hud.vertex_count());
+                #[cfg(feature = "egui")]
+                if let Some(gui) = draw_list.gui() {
+                    gui_renderer.cmd_draw(device, command_buffer, resource_manager, in_flight_frame, &gui_draws,
+                        GuiTarget { pipeline: swapchain.gui_pipeline, extent: swapchain.swapchain_extent, pixels_per_point: gui.pixels_per_point });
+                }
+            });
+
+            let transfer_read = ImageState::new(vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_READ);
+
+            if let Some((x, y)) = self.depth_readback_request.take() {
+                let slot = &mut device_resources.depth_readback_slots[in_flight_frame];
+                graph.add_pass(RenderGraph::pass("depth readback", move |frame_scope: &mut profiler::GpuScope, command_buffer| {
+                    let _readback_scope = frame_scope.scope("depth_readback");
+                    slot.cmd_copy(device, command_buffer, resource_manager, depth_image, swapchain.swapchain_extent, (x, y));
+                })
+                    .uses(depth_image, vk::ImageAspectFlags::DEPTH, transfer_read));
+            }
+
+            if let Some((x, y)) = self.pick_request.take() {
+                let slot = &mut device_resources.pick_slots[in_flight_frame];
+                graph.add_pass(RenderGraph::pass("pick", move |frame_scope: &mut profiler::GpuScope, command_buffer| {
+                    let _pick_scope = frame_scope.scope("pick");
+                    slot.cmd_copy(device, command_buffer, resource_manager, object_id_image, swapchain.swapchain_extent, (x, y));
+                })
+                    .uses(object_id_image, vk::ImageAspectFlags::COLOR, transfer_read));
+            }
+
+            graph.execute(device, command_buffers[frame], resource_manager, &mut frame_scope);
+            drop(frame_scope);
+
+            // the device copies are destroyed on the next sync, once nothing uses them
+            #[cfg(feature = "egui")]
+            if let Some(gui) = draw_list.gui() {
+                self.gui_textures.free(&gui.textures_delta);
+            }
             
             let end_cb_res = device
-                .end_command_buffer(self.command_buffers[frame]);
+                .end_command_buffer(command_buffers[frame]);
             match end_cb_res {
                 Ok(_) => {},
                 Err(e) => {
@@ -460,75 +1276,92 @@ impl VulkanApp {
                 }
             }
         }
+        self.frame_timer.lap("record", &mut stage_start);
 
         // 2.2) queue submit
+        // there is nothing to acquire or present in headless mode
+        let semaphore_count = if swapchain.swapchain_loader.is_some() { 1 } else { 0 };
         let submit_infos = [vk::SubmitInfo {
             s_type: vk::StructureType::SUBMIT_INFO,
             p_next: ptr::null(),
-            wait_semaphore_count: 1,
-            p_wait_semaphores: &self.sync_objects.image_available_semaphores[frame],
+            wait_semaphore_count: semaphore_count,
+            p_wait_semaphores: &sync_objects.image_available_semaphores[frame],
             p_wait_dst_stage_mask: &vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
             command_buffer_count: 1,
-            p_command_buffers: &self.command_buffers[frame],
-            signal_semaphore_count: 1,
-            p_signal_semaphores: &self.sync_objects.render_finished_semaphores[frame],
+            p_command_buffers: &command_buffers[frame],
+            signal_semaphore_count: semaphore_count,
+            p_signal_semaphores: &sync_objects.render_finished_semaphores[frame],
         }];
 
         unsafe {
             device
                 .queue_submit(
-                    self.queue,
+                    device_resources.queue,
                     &submit_infos,
-                    self.sync_objects.in_flight_fences[in_flight_frame],
-                )
-                .expect("Failed to execute queue submit.");
+                    sync_objects.in_flight_fences[in_flight_frame],
+                )?;
         }
+        self.frame_timer.lap("submit", &mut stage_start);
+
+        self.cur_frame = (self.cur_frame + 1) % command_buffers.len();
+        self.in_flight_frame = (self.in_flight_frame + 1) % IN_FLIGHT_FRAMES;
 
         // 3) present
+        let swapchain_loader = match &swapchain.swapchain_loader {
+            Some(swapchain_loader) => swapchain_loader,
+            None => return Ok(()),
+        };
         let swapchains = [swapchain.swapchain];
 
         let present_info = vk::PresentInfoKHR {
             s_type: vk::StructureType::PRESENT_INFO_KHR,
             p_next: ptr::null(),
             wait_semaphore_count: 1,
-            p_wait_semaphores: &self.sync_objects.render_finished_semaphores[frame],
+            p_wait_semaphores: &sync_objects.render_finished_semaphores[frame],
             swapchain_count: 1,
             p_swapchains: swapchains.as_ptr(),
             p_image_indices: &image_index,
             p_results: ptr::null_mut(),
         };
 
-        // get timestamps
-        let mut timestamps = [0u64; 2];
-        unsafe {
-            device.get_query_pool_results(
-                self.query_pool,
-                0,
-                2,
-                &mut timestamps,
-                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
-            ).expect("Failed to get query pool results!");
+        device_resources.resource_manager.queue_begin_label("present");
+        let present_res = unsafe { swapchain_loader.queue_present(device_resources.queue, &present_info) };
+        device_resources.resource_manager.queue_end_label();
+        match present_res {
+            Ok(is_suboptimal) if is_suboptimal  => {
+                warn!("queue_present: Suboptimal swapchain image");
+            },
+            Err(e) => {
+                return Err(e);
+            }
+            Ok(_) => {}
         }
-        println!("Timestamps difference: {}ns", timestamps[1] - timestamps[0]);
-
-        self.cur_frame = (self.cur_frame + 1) % self.command_buffers.len();
-        self.in_flight_frame = (self.in_flight_frame + 1) % IN_FLIGHT_FRAMES;
+        self.frame_timer.lap("present", &mut stage_start);
+        self.frame_timer.presented(in_flight_frame);
+        Ok(())
+    }
 
-        unsafe {
-            match swapchain.swapchain_loader.queue_present(self.queue, &present_info) {
-                Ok(is_suboptimal) if is_suboptimal  => {
-                    println!("queue_present: Suboptimal swapchain image");
-                },
-                Err(e) => {
-                    println!("queue_present: {}", e);
-                }
-                Ok(_) => {}
-            }
+    fn recover_from_error(&mut self, window: &glfw::Window, error: vk::Result) {
+        match error {
+            vk::Result::ERROR_OUT_OF_DATE_KHR => {
+                warn!("Swapchain is out of date, recreating");
+                self.recreate_swapchain(window);
+            },
+            vk::Result::ERROR_SURFACE_LOST_KHR => {
+                warn!("Surface lost, recreating surface and swapchain");
+                self.recreate_surface(window);
+            },
+            vk::Result::ERROR_DEVICE_LOST => {
+                warn!("Device lost, recreating device and re-uploading resources");
+                self.recreate_device(Some(window));
+            },
+            e => panic!("Failed to draw frame: {}", e),
         }
-        true
     }
     
-    fn create_swapchain_dependent_resources(window: &glfw::Window, entry: &ash::Entry, instance: &ash::Instance, physical_device: &vk::PhysicalDevice, surface: SurfaceKHR, device: &ash::Device, image_view: vk::ImageView, sampler: vk::Sampler, old_swapchain: Option<vk::SwapchainKHR>) -> SwapchainDependentResources {
+    fn create_swapchain_dependent_resources(window: &glfw::Window, entry: &ash::Entry, instance: &ash::Instance, device_dependent_resources: &mut DeviceDependentResources, surface: SurfaceKHR, config: &RendererConfig, old_swapchain: Option<vk::SwapchainKHR>) -> SwapchainDependentResources {
+        let physical_device = &device_dependent_resources.physical_device;
+        let device = &device_dependent_resources.device;
 
         //query swapchain support
         let surface_loader = extensions::khr::Surface::new(entry, instance);
@@ -536,14 +1369,12 @@ impl VulkanApp {
         let surface_formats = unsafe { surface_loader.get_physical_device_surface_formats(*physical_device, surface).unwrap() };
         let surface_present_modes = unsafe { surface_loader.get_physical_device_surface_present_modes(*physical_device, surface).unwrap() };
 
-        //prefer VK_FORMAT_B8G8R8A8_UNORM and VK_COLOR_SPACE_SRGB_NONLINEAR_KHR
-        let surface_format = surface_formats.iter().find(|f| {
-            f.format == vk::Format::B8G8R8A8_UNORM && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-        }).unwrap_or_else(|| {
-            surface_formats.first().unwrap()
-        });
-        //prefer MAILBOX then IMMEDIATE or default FIFO
-        let present_mode = surface_present_modes.iter().find(|m| {
+        //prefer the configured color space, then VK_FORMAT_B8G8R8A8_UNORM and VK_COLOR_SPACE_SRGB_NONLINEAR_KHR
+        let (surface_format, color_space) = surface_format::select_surface_format(&surface_formats, config.color_space);
+        info!("Surface format: {:?}, {:?}", surface_format.format, surface_format.color_space);
+        //prefer MAILBOX then IMMEDIATE or default FIFO, FIFO is always supported
+        let vsync = config.vsync.unwrap_or(device_dependent_resources.device_profile.vsync());
+        let present_mode = if vsync { &vk::PresentModeKHR::FIFO } else { surface_present_modes.iter().find(|m| {
             **m == vk::PresentModeKHR::MAILBOX
         }).unwrap_or_else(|| {
             surface_present_modes.iter().find(|m| {
@@ -551,8 +1382,8 @@ impl VulkanApp {
             }).unwrap_or_else(|| {
                 surface_present_modes.first().unwrap()
             })
-        });
-        println!("Present mode: {:?}", present_mode);
+        }) };
+        info!("Present mode: {:?}", present_mode);
 
         let extent = window.get_framebuffer_size();
 
@@ -568,7 +1399,12 @@ impl VulkanApp {
             actual_extent
         };
 
-        let image_count = surface_capabilities.min_image_count + 1;
+        let mut image_count = config.swapchain_image_count.unwrap_or(surface_capabilities.min_image_count + 1)
+            .max(surface_capabilities.min_image_count);
+        // max_image_count of 0 means there is no limit
+        if surface_capabilities.max_image_count != 0 {
+            image_count = image_count.min(surface_capabilities.max_image_count);
+        }
 
         let swapchain_loader = extensions::khr::Swapchain::new(instance, device);
         let mut swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
@@ -591,13 +1427,121 @@ impl VulkanApp {
         let swapchain_create_info = swapchain_create_info.build();
         
         let swapchain = unsafe { swapchain_loader.create_swapchain(&swapchain_create_info, None).unwrap() };
+        device_dependent_resources.resource_manager.set_name(swapchain, "swapchain");
         let swapchain_images = unsafe { swapchain_loader.get_swapchain_images(swapchain).unwrap() };
+        info!("Swapchain images: {} (requested {})", swapchain_images.len(), image_count);
 
+        let render_target_resources = VulkanApp::create_render_target_resources(device_dependent_resources, swapchain_images, surface_format.format, color_space, swapchain_extent, vk::ImageLayout::PRESENT_SRC_KHR, config);
+        SwapchainDependentResources {
+            swapchain_loader: Some(swapchain_loader),
+            swapchain,
+            ..render_target_resources
+        }
+    }
+
+    /// Offscreen color image used instead of a swapchain in headless mode
+    fn create_offscreen_resources(device_dependent_resources: &mut DeviceDependentResources, extent: vk::Extent2D, config: &RendererConfig) -> SwapchainDependentResources {
+        let format = vk::Format::R8G8B8A8_UNORM;
+        // TRANSFER_SRC for read_back_frame
+        let offscreen_image = device_dependent_resources.resource_manager.create_image(extent.width,
+            extent.height,
+            format,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC);
+        let images = vec![device_dependent_resources.resource_manager.image(&offscreen_image)];
+
+        let render_target_resources = VulkanApp::create_render_target_resources(device_dependent_resources, images, format, DisplayColorSpace::Sdr, extent, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, config);
+        device_dependent_resources.resource_manager.set_image_name(&offscreen_image, "offscreen_color_image");
+        SwapchainDependentResources {
+            offscreen_image: Some(offscreen_image),
+            ..render_target_resources
+        }
+    }
+
+    /// Main pass with the HDR scene color, depth and object id attachments. Their formats are fixed, so it lives as long as the device.
+    /// With MSAA the color and object id attachments are multisampled and resolved into attachments 3 and 4, the object id takes sample 0.
+    fn create_main_render_pass(device: &ash::Device, samples: vk::SampleCountFlags) -> vk::RenderPass {
+        let multisampled = samples != vk::SampleCountFlags::TYPE_1;
+        let attachment = |format, samples, load_op, store_op, final_layout| vk::AttachmentDescription::builder()
+            .format(format)
+            .samples(samples)
+            .load_op(load_op)
+            .store_op(store_op)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(final_layout)
+            .build();
+        // multisampled color is only read by the resolve
+        let resolved = |final_layout| match multisampled {
+            true => (vk::AttachmentStoreOp::DONT_CARE, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            false => (vk::AttachmentStoreOp::STORE, final_layout),
+        };
+        let (color_store_op, color_final_layout) = resolved(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        let (object_id_store_op, object_id_final_layout) = resolved(vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        let mut attachments = vec![
+            attachment(post_process::HDR_FORMAT, samples, vk::AttachmentLoadOp::CLEAR, color_store_op, color_final_layout),
+            attachment(depth_readback::DEPTH_FORMAT, samples, vk::AttachmentLoadOp::CLEAR, vk::AttachmentStoreOp::STORE, vk::ImageLayout::TRANSFER_SRC_OPTIMAL),
+            attachment(picking::OBJECT_ID_FORMAT, samples, vk::AttachmentLoadOp::CLEAR, object_id_store_op, object_id_final_layout),
+        ];
+        if multisampled {
+            attachments.push(attachment(post_process::HDR_FORMAT, vk::SampleCountFlags::TYPE_1, vk::AttachmentLoadOp::DONT_CARE, vk::AttachmentStoreOp::STORE, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL));
+            attachments.push(attachment(picking::OBJECT_ID_FORMAT, vk::SampleCountFlags::TYPE_1, vk::AttachmentLoadOp::DONT_CARE, vk::AttachmentStoreOp::STORE, vk::ImageLayout::TRANSFER_SRC_OPTIMAL));
+        }
+        let color_attachment_refs = [vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build(),
+        vk::AttachmentReference::builder()
+            .attachment(2)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build()];
+        let resolve_attachment_refs = [vk::AttachmentReference::builder()
+            .attachment(3)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build(),
+        vk::AttachmentReference::builder()
+            .attachment(4)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build()];
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build();
+        let mut subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs)
+            .depth_stencil_attachment(&depth_attachment_ref);
+        if multisampled {
+            subpass = subpass.resolve_attachments(&resolve_attachment_refs);
+        }
+        let subpasses = [subpass.build()];
+        let dependencies = [vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            // depth, object id and scene images are shared between in-flight frames, wait for the previous readback and post-processing
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS | vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .build()];
+        let render_pass_create_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies)
+            .build();
+        unsafe { device.create_render_pass(&render_pass_create_info, None).unwrap() }
+    }
+
+    /// Everything rendered into `images`, shared by the swapchain and the offscreen target. The last post-processing stage leaves them in `final_layout`.
+    /// `config` adds its post-processing stages and anti-aliasing to the chain.
+    fn create_render_target_resources(device_dependent_resources: &mut DeviceDependentResources, swapchain_images: Vec<vk::Image>, format: vk::Format, color_space: DisplayColorSpace, swapchain_extent: vk::Extent2D, final_layout: vk::ImageLayout, config: &RendererConfig) -> SwapchainDependentResources {
+        let device = &device_dependent_resources.device;
         let swapchain_imageviews = swapchain_images.iter().map(|image| {
             let image_view_create_info = vk::ImageViewCreateInfo::builder()
                 .image(*image)
                 .view_type(vk::ImageViewType::TYPE_2D)
-                .format(surface_format.format)
+                .format(format)
                 .components(vk::ComponentMapping::builder()
                     .r(vk::ComponentSwizzle::IDENTITY)
                     .g(vk::ComponentSwizzle::IDENTITY)
@@ -617,332 +1561,579 @@ impl VulkanApp {
 
         // swapchain and image views are created
 
-        let render_pass = {
-            let color_attachments = [vk::AttachmentDescription::builder()
-                .format(surface_format.format)
-                .samples(vk::SampleCountFlags::TYPE_1)
-                .load_op(vk::AttachmentLoadOp::CLEAR)
-                .store_op(vk::AttachmentStoreOp::STORE)
-                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-                .initial_layout(vk::ImageLayout::UNDEFINED)
-                .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-                .build()];
-            let color_attachment_refs = [vk::AttachmentReference::builder()
-                .attachment(0)
-                .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                .build()];
-            let subpasses = [vk::SubpassDescription::builder()
-                .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-                .color_attachments(&color_attachment_refs)
-                .build()];
-            let dependencies = [vk::SubpassDependency::builder()
-                .src_subpass(vk::SUBPASS_EXTERNAL)
-                .dst_subpass(0)
-                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-                .src_access_mask(vk::AccessFlags::empty())
-                .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-                .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
-                .build()];
-            let render_pass_create_info = vk::RenderPassCreateInfo::builder()
-                .attachments(&color_attachments)
-                .subpasses(&subpasses)
-                .dependencies(&dependencies)
-                .build();
-            unsafe { device.create_render_pass(&render_pass_create_info, None).unwrap() }
-        };
+        let samples = device_dependent_resources.msaa_samples;
+        // TRANSFER_SRC for depth readback
+        let depth_image = device_dependent_resources.resource_manager.create_multisampled_image(swapchain_extent.width,
+            swapchain_extent.height,
+            depth_readback::DEPTH_FORMAT,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            samples);
+        let depth_image_view = device_dependent_resources.resource_manager.create_image_view(device_dependent_resources.resource_manager.image(&depth_image), depth_readback::DEPTH_FORMAT, vk::ImageAspectFlags::DEPTH);
+
+        // TRANSFER_SRC for picking
+        let object_id_image = device_dependent_resources.resource_manager.create_image(swapchain_extent.width,
+            swapchain_extent.height,
+            picking::OBJECT_ID_FORMAT,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC);
+        let object_id_image_view = device_dependent_resources.resource_manager.create_image_view(device_dependent_resources.resource_manager.image(&object_id_image), picking::OBJECT_ID_FORMAT, vk::ImageAspectFlags::COLOR);
+
+        let msaa_targets = (samples != vk::SampleCountFlags::TYPE_1).then(|| {
+            let resource_manager = &mut device_dependent_resources.resource_manager;
+            let mut create_target = |format| {
+                let image = resource_manager.create_multisampled_image(swapchain_extent.width, swapchain_extent.height, format, vk::ImageTiling::OPTIMAL, vk::ImageUsageFlags::COLOR_ATTACHMENT, samples);
+                let view = resource_manager.create_image_view(resource_manager.image(&image), format, vk::ImageAspectFlags::COLOR);
+                (image, view)
+            };
+            let (color_image, color_image_view) = create_target(post_process::HDR_FORMAT);
+            let (object_id_image, object_id_image_view) = create_target(picking::OBJECT_ID_FORMAT);
+            MsaaTargets { color_image, color_image_view, object_id_image, object_id_image_view }
+        });
 
-        let framebuffers = swapchain_imageviews.iter().map(|image_view| {
+        //create post-processing chain, its first target is the main pass color attachment
+        let stages: Vec<_> = std::iter::once(PostProcessStage::TONEMAP)
+            .chain(config.post_process.iter().copied())
+            .chain(config.anti_aliasing.stage())
+            .chain(std::iter::once(PostProcessStage::OUTPUT))
+            .collect();
+        let post_process = PostProcessChain::new(device, &mut device_dependent_resources.resource_manager, &mut device_dependent_resources.descriptor_allocator, &stages, device_dependent_resources.post_process_descriptor_set_layout, &device_dependent_resources.uniform_buffers,
+            PostProcessOutput { views: &swapchain_imageviews, format, final_layout, extent: swapchain_extent, transfer_function: color_space.transfer_function() });
+        let text_pipeline = device_dependent_resources.text_renderer.create_pipeline(device, post_process.output_render_pass(), swapchain_extent, color_space.transfer_function());
+        #[cfg(feature = "egui")]
+        let gui_pipeline = device_dependent_resources.gui_renderer.create_pipeline(device, post_process.output_render_pass(), swapchain_extent, color_space.transfer_function());
+
+        let framebuffer = {
+            let attachments = match &msaa_targets {
+                Some(msaa) => vec![msaa.color_image_view, depth_image_view, msaa.object_id_image_view, post_process.scene_target_view(), object_id_image_view],
+                None => vec![post_process.scene_target_view(), depth_image_view, object_id_image_view],
+            };
             let framebuffer_create_info = vk::FramebufferCreateInfo::builder()
-                .render_pass(render_pass)
-                .attachments(&[*image_view])
+                .render_pass(device_dependent_resources.render_pass)
+                .attachments(&attachments)
                 .width(swapchain_extent.width)
                 .height(swapchain_extent.height)
                 .layers(1)
                 .build();
             unsafe { device.create_framebuffer(&framebuffer_create_info, None).unwrap() }
-        }).collect::<Vec<_>>();
+        };
 
-        //render pass and framebuffers are created
+        let resource_manager = &device_dependent_resources.resource_manager;
+        for (i, (image, imageview)) in swapchain_images.iter().zip(swapchain_imageviews.iter()).enumerate() {
+            resource_manager.set_name(*image, &format!("swapchain_image_{}", i));
+            resource_manager.set_name(*imageview, &format!("swapchain_imageview_{}", i));
+        }
+        resource_manager.set_name(framebuffer, "main_framebuffer");
+        resource_manager.set_image_name(&depth_image, "depth_image");
+        resource_manager.set_name(depth_image_view, "depth_image_view");
+        resource_manager.set_image_name(&object_id_image, "object_id_image");
+        resource_manager.set_name(object_id_image_view, "object_id_image_view");
+        if let Some(msaa) = &msaa_targets {
+            resource_manager.set_image_name(&msaa.color_image, "msaa_color_image");
+            resource_manager.set_name(msaa.color_image_view, "msaa_color_image_view");
+            resource_manager.set_image_name(&msaa.object_id_image, "msaa_object_id_image");
+            resource_manager.set_name(msaa.object_id_image_view, "msaa_object_id_image_view");
+        }
+        resource_manager.set_name(text_pipeline, "text_pipeline");
+        #[cfg(feature = "egui")]
+        resource_manager.set_name(gui_pipeline, "gui_pipeline");
 
-        //create descriptor layout for combined image sampler
-        let descriptor_set_layout_bindings = [vk::DescriptorSetLayoutBinding::builder()
-            .binding(0)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .descriptor_count(1)
-            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
-            .build()];
+        
+        SwapchainDependentResources { 
+            text_pipeline,
+            #[cfg(feature = "egui")]
+            gui_pipeline,
 
-        let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
-            .bindings(&descriptor_set_layout_bindings);
-        let descriptor_set_layout = unsafe { device.create_descriptor_set_layout(&descriptor_set_layout_create_info, None).unwrap() };
+            swapchain_loader: None,
+            swapchain: vk::SwapchainKHR::null(),
+            offscreen_image: None,
+            swapchain_images,
+            swapchain_imageviews,
+            swapchain_format: format,
+            color_space,
+            swapchain_extent,
+            framebuffer,
 
-        //create descriptor pool
-        let descriptor_pool_sizes = [vk::DescriptorPoolSize::builder()
-            .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .descriptor_count(1)
-            .build()];
+            depth_image,
+            depth_image_view,
 
-        let descriptor_pool_create_info = vk::DescriptorPoolCreateInfo::builder()
-            .max_sets(1)
-            .pool_sizes(&descriptor_pool_sizes);
-        let descriptor_pool = unsafe { device.create_descriptor_pool(&descriptor_pool_create_info, None).unwrap() };
+            object_id_image,
+            object_id_image_view,
 
-        //allocate descriptor set
-        let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::builder()
-            .descriptor_pool(descriptor_pool)
-            .set_layouts(&[descriptor_set_layout]).build();
+            msaa_targets,
 
-        let descriptor_set = unsafe { device.allocate_descriptor_sets(&descriptor_set_allocate_info).unwrap() }[0];
+            post_process,
+        }     
+    }
+    /// Destroys everything except the swapchain itself, which is returned so it can be passed as `old_swapchain`
+    fn destroy_swapchain_dependent_resources(device_dependent_resources: &mut DeviceDependentResources, swapchain_dependent_resources: SwapchainDependentResources) -> vk::SwapchainKHR {
+        let device = &device_dependent_resources.device;
+        unsafe {
+            device.destroy_framebuffer(swapchain_dependent_resources.framebuffer, None);
 
-        //create descriptor image info
-        let descriptor_image_info = vk::DescriptorImageInfo::builder()
-            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(image_view)
-            .sampler(sampler)
-            .build();
+            device.destroy_pipeline(swapchain_dependent_resources.text_pipeline, None);
+            #[cfg(feature = "egui")]
+            device.destroy_pipeline(swapchain_dependent_resources.gui_pipeline, None);
 
-        //update descriptor set
-        let descriptor_write_set = [vk::WriteDescriptorSet::builder()
-            .dst_set(descriptor_set)
-            .dst_binding(0)
-            .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .image_info(&[descriptor_image_info])
-            .build()];
+            for imageview in swapchain_dependent_resources.swapchain_imageviews.iter() {
+                device.destroy_image_view(*imageview, None);
+            }
 
-        unsafe { device.update_descriptor_sets(&descriptor_write_set, &[]) };
-        
-        //load shaders from file
-        let vertex_shader_code = std::fs::read("shaders/vert.spv").unwrap();
-        let fragment_shader_code = std::fs::read("shaders/frag.spv").unwrap();
-        
-        let mut shader_module_create_info = vk::ShaderModuleCreateInfo {
-            s_type: vk::StructureType::SHADER_MODULE_CREATE_INFO,
-            p_next: std::ptr::null(),
-            flags: vk::ShaderModuleCreateFlags::empty(),
-            code_size: vertex_shader_code.len(),
-            p_code: vertex_shader_code.as_ptr() as *const u32,
-        };
-        let vertex_shader_module = unsafe { device.create_shader_module(&shader_module_create_info, None).unwrap() };
+            device.destroy_image_view(swapchain_dependent_resources.depth_image_view, None);
+            device.destroy_image_view(swapchain_dependent_resources.object_id_image_view, None);
+            if let Some(msaa) = &swapchain_dependent_resources.msaa_targets {
+                device.destroy_image_view(msaa.color_image_view, None);
+                device.destroy_image_view(msaa.object_id_image_view, None);
+            }
+        }
+        swapchain_dependent_resources.post_process.destroy(device, &mut device_dependent_resources.resource_manager, &mut device_dependent_resources.descriptor_allocator);
+        device_dependent_resources.resource_manager.destroy_image(swapchain_dependent_resources.depth_image);
+        device_dependent_resources.resource_manager.destroy_image(swapchain_dependent_resources.object_id_image);
+        if let Some(msaa) = swapchain_dependent_resources.msaa_targets {
+            device_dependent_resources.resource_manager.destroy_image(msaa.color_image);
+            device_dependent_resources.resource_manager.destroy_image(msaa.object_id_image);
+        }
+        if let Some(offscreen_image) = swapchain_dependent_resources.offscreen_image {
+            device_dependent_resources.resource_manager.destroy_image(offscreen_image);
+        }
+        swapchain_dependent_resources.swapchain
+    }
 
-        shader_module_create_info.code_size = fragment_shader_code.len();
-        shader_module_create_info.p_code = fragment_shader_code.as_ptr() as *const u32;
-        let fragment_shader_module = unsafe { device.create_shader_module(&shader_module_create_info, None).unwrap() };
+    fn recreate_swapchain(&mut self, window: &glfw::Window) {
+        let surface = match self.surface {
+            Some(surface) => surface,
+            None => {
+                warn!("Headless mode has no swapchain to recreate");
+                return;
+            }
+        };
 
-        let vertex_shader_stage_create_info = vk::PipelineShaderStageCreateInfo::builder()
-            .stage(vk::ShaderStageFlags::VERTEX)
-            .module(vertex_shader_module)
-            .name(std::ffi::CStr::from_bytes_with_nul(b"main\0").unwrap())
-            .build();
-        let fragment_shader_stage_create_info = vk::PipelineShaderStageCreateInfo::builder()
-            .stage(vk::ShaderStageFlags::FRAGMENT)
-            .module(fragment_shader_module)
-            .name(std::ffi::CStr::from_bytes_with_nul(b"main\0").unwrap())
-            .build();
+        let (mut w, mut h) = window.get_framebuffer_size();
+        while w == 0 || h == 0 {
+            (w, h) = window.get_framebuffer_size();
+        }
 
-        let shader_stages = [vertex_shader_stage_create_info, fragment_shader_stage_create_info];
+        let device_dependent_resources = self.device_dependent_resources.as_mut().unwrap();
+        unsafe { device_dependent_resources.device.device_wait_idle().expect("Failed to wait for device idle!"); }
 
-        let vertex_binding_descriptions = [vk::VertexInputBindingDescription::builder()
-            .binding(0)
-            .stride(std::mem::size_of::<Vertex>() as u32)
-            .input_rate(vk::VertexInputRate::VERTEX)
-            .build()];
+        //free resources
+        match self.swapchain_dependent_resources.take() {
+            Some(swapchain_dependent_resources) => {
+                let swapchain_loader = swapchain_dependent_resources.swapchain_loader.clone().unwrap();
+                let old_swapchain = VulkanApp::destroy_swapchain_dependent_resources(device_dependent_resources, swapchain_dependent_resources);
 
+                self.swapchain_dependent_resources = Some(VulkanApp::create_swapchain_dependent_resources(
+                    window,
+                    &self.entry,
+                    &self.instance,
+                    device_dependent_resources,
+                    surface,
+                    &self.config,
+                    Some(old_swapchain),
+                ));
 
-        let vertex_attribute_descriptions = [
-            vk::VertexInputAttributeDescription::builder()
-                .binding(0)
-                .location(0)
-                .format(vk::Format::R32G32B32_SFLOAT)
-                .offset(offset_of!(Vertex, position) as u32)
-                .build(),
-            vk::VertexInputAttributeDescription::builder()
-                .binding(0)
-                .location(1)
-                .format(vk::Format::R32G32_SFLOAT)
-                .offset(offset_of!(Vertex, texCoord) as u32)
-                .build(),
-        ];
-        
-        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
-            .vertex_binding_descriptions(&vertex_binding_descriptions)
-            .vertex_attribute_descriptions(&vertex_attribute_descriptions)
-            .build();
+                unsafe { swapchain_loader.destroy_swapchain(old_swapchain, None); }
+            },
+            None => {
+                warn!("No swapchain dependent resources to free");
+            }
+        }
 
-        let dynamic_state_create_info = vk::PipelineDynamicStateCreateInfo::builder()
-            .dynamic_states(&[])
-            .build();
+    }
 
-        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
-            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
-            .primitive_restart_enable(false)
-            .build();
+    /// Destroys the swapchain and the surface, then creates both again from the window
+    fn recreate_surface(&mut self, window: &glfw::Window) {
+        let device_dependent_resources = self.device_dependent_resources.as_mut().unwrap();
+        unsafe { device_dependent_resources.device.device_wait_idle().expect("Failed to wait for device idle!"); }
 
-        let viewports = [vk::Viewport::builder()
-            .x(0.0)
-            .y(0.0)
-            .width(swapchain_extent.width as f32)
-            .height(swapchain_extent.height as f32)
-            .min_depth(0.0)
-            .max_depth(1.0)
-            .build()];
+        if let Some(swapchain_dependent_resources) = self.swapchain_dependent_resources.take() {
+            let swapchain_loader = swapchain_dependent_resources.swapchain_loader.clone().unwrap();
+            let swapchain = VulkanApp::destroy_swapchain_dependent_resources(device_dependent_resources, swapchain_dependent_resources);
+            unsafe { swapchain_loader.destroy_swapchain(swapchain, None); }
+        }
 
-        let scissors = [vk::Rect2D::builder()
-            .offset(vk::Offset2D::builder().x(0).y(0).build())
-            .extent(swapchain_extent)
-            .build()];
-        
-        let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
-            .viewports(&viewports)
-            .scissors(&scissors)
-            .build();
+        let surface_loader = extensions::khr::Surface::new(&self.entry, &self.instance);
+        unsafe { surface_loader.destroy_surface(self.surface.unwrap(), None); }
+        let surface = VulkanApp::create_surface(&self.instance, window);
+        self.surface = Some(surface);
 
-        let rasterizer = vk::PipelineRasterizationStateCreateInfo::builder()
-            .depth_clamp_enable(false)
-            .rasterizer_discard_enable(false)
-            .polygon_mode(vk::PolygonMode::FILL)
-            .line_width(1.0)
-            .cull_mode(vk::CullModeFlags::NONE)
-            .front_face(vk::FrontFace::CLOCKWISE)
-            .depth_bias_enable(false)
-            .build();
+        self.swapchain_dependent_resources = Some(VulkanApp::create_swapchain_dependent_resources(
+            window,
+            &self.entry,
+            &self.instance,
+            device_dependent_resources,
+            surface,
+            &self.config,
+            None,
+        ));
+    }
 
-        let multisampling = vk::PipelineMultisampleStateCreateInfo::builder()
-            .sample_shading_enable(false)
-            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
-            .build();
+    /// Tears down the device and everything created from it, then creates a new device and re-uploads resources from `resource_cache`.
+    /// `window` is None in headless mode.
+    fn recreate_device(&mut self, window: Option<&glfw::Window>) {
+        let mut device_dependent_resources = self.device_dependent_resources.take().unwrap();
 
-        let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
-            .color_write_mask(vk::ColorComponentFlags::RGBA)
-            .blend_enable(true)
-            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
-            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-            .color_blend_op(vk::BlendOp::ADD)
-            .src_alpha_blend_factor(vk::BlendFactor::ONE)
-            .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
-            .alpha_blend_op(vk::BlendOp::ADD)
-            .build()];
+        if let Some(swapchain_dependent_resources) = self.swapchain_dependent_resources.take() {
+            let swapchain_loader = swapchain_dependent_resources.swapchain_loader.clone();
+            let swapchain = VulkanApp::destroy_swapchain_dependent_resources(&mut device_dependent_resources, swapchain_dependent_resources);
+            if let Some(swapchain_loader) = swapchain_loader {
+                unsafe { swapchain_loader.destroy_swapchain(swapchain, None); }
+            }
+        }
+        VulkanApp::destroy_device_dependent_resources(device_dependent_resources);
+
+        let mut device_dependent_resources = VulkanApp::create_device_dependent_resources(&self.entry, &self.instance, self.surface, self.debug_utils_loader.as_ref(), &self.resource_cache, self.gpu_preference, &self.config);
+        self.swapchain_dependent_resources = Some(match (window, self.surface) {
+            (Some(window), Some(surface)) => VulkanApp::create_swapchain_dependent_resources(
+                window,
+                &self.entry,
+                &self.instance,
+                &mut device_dependent_resources,
+                surface,
+                &self.config,
+                None,
+            ),
+            _ => VulkanApp::create_offscreen_resources(&mut device_dependent_resources, self.headless_extent.unwrap(), &self.config),
+        });
+        self.device_dependent_resources = Some(device_dependent_resources);
 
-        let color_blending = vk::PipelineColorBlendStateCreateInfo::builder()
-            .logic_op_enable(false)
-            .logic_op(vk::LogicOp::COPY)
-            .attachments(&color_blend_attachments)
-            .build();
+        self.cur_frame = 0;
+        self.in_flight_frame = 0;
+        self.frame_timer.reset_in_flight();
+    }
 
-        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::builder()
-            .set_layouts(&[descriptor_set_layout])
-            .push_constant_ranges(&[])
-            .build();
+    /// Copies a small depth region around framebuffer pixel (x, y) at the end of the next frame.
+    /// The result is available from `take_depth_readback` once that frame completes on the GPU.
+    /// Ignored with MSAA, a multisampled depth image can't be copied to a buffer.
+    pub fn request_depth_readback(&mut self, x: u32, y: u32) {
+        if self.device_dependent_resources.as_ref().is_some_and(|resources| resources.msaa_samples != vk::SampleCountFlags::TYPE_1) {
+            warn!("Depth readback is not available with MSAA");
+            return;
+        }
+        self.depth_readback_request = Some((x, y));
+    }
 
-        let pipeline_layout = unsafe { device.create_pipeline_layout(&pipeline_layout_create_info, None).unwrap() };
+    pub fn take_depth_readback(&mut self) -> Option<DepthReadback> {
+        self.depth_readback_result.take()
+    }
 
-        let graphics_pipeline_create_info = vk::GraphicsPipelineCreateInfo::builder()
-            .stages(&shader_stages)
-            .vertex_input_state(&vertex_input_info)
-            .input_assembly_state(&input_assembly)
-            .viewport_state(&viewport_state)
-            .rasterization_state(&rasterizer)
-            .multisample_state(&multisampling)
-            .color_blend_state(&color_blending)
-            .dynamic_state(&dynamic_state_create_info)
-            .layout(pipeline_layout)
-            .render_pass(render_pass)
-            .subpass(0)
-            .build();
+    /// Reads the object id at framebuffer pixel (x, y) at the end of the next frame.
+    /// The result is available from `take_pick_result` once that frame completes on the GPU.
+    pub fn request_pick(&mut self, x: u32, y: u32) {
+        self.pick_request = Some((x, y));
+    }
 
-        let graphics_pipelines = unsafe { device.create_graphics_pipelines(vk::PipelineCache::null(), &[graphics_pipeline_create_info], None).unwrap() };
+    pub fn take_pick_result(&mut self) -> Option<PickResult> {
+        self.pick_result.take()
+    }
 
-        unsafe {
-            device.destroy_shader_module(vertex_shader_module, None);
-            device.destroy_shader_module(fragment_shader_module, None);
+    /// Non-blocking picking for continuous use (e.g. hover): schedules a readback of (x, y) and returns
+    /// the entity from the latest completed readback of the same pixel. None until that readback lands.
+    pub fn pick(&mut self, x: u32, y: u32) -> Option<EntityId> {
+        self.request_pick(x, y);
+        match self.pick_result {
+            Some(result) if result.x == x && result.y == y => result.entity,
+            _ => None,
         }
+    }
 
-        
-        SwapchainDependentResources { 
-            render_pass,
-            graphics_pipeline: graphics_pipelines[0],
-            pipeline_layout,
+    /// Values uploaded to the `FrameUniforms` block of the next frames.
+    /// The calibration fields are ignored, they come from `display_calibration` and `calibration_pattern`.
+    pub fn set_frame_uniforms(&mut self, frame_uniforms: FrameUniforms) {
+        self.frame_uniforms = frame_uniforms;
+    }
 
-            swapchain,
-            swapchain_images,
-            swapchain_imageviews,
-            swapchain_format: surface_format.format,
-            swapchain_extent,
-            swapchain_framebuffers: framebuffers,
-            swapchain_loader,
+    /// sRGB RGBA8 pixels row by row, uploaded before the next frame
+    pub fn create_texture(&mut self, width: u32, height: u32, pixels: Vec<u8>) -> TextureId {
+        self.resource_cache.materials.add_texture(width, height, pixels)
+    }
 
-            descriptor_set
-        }     
+    /// Usable in `Frame` draws right away, uploaded before the next frame
+    pub fn create_material(&mut self, material: Material) -> MaterialId {
+        self.resource_cache.materials.add_material(material)
     }
-    fn recreate_swapchain(&mut self, window: &glfw::Window) {
-        let (mut w, mut h) = window.get_framebuffer_size();
-        while w == 0 || h == 0 {
-            (w, h) = window.get_framebuffer_size();
+
+    /// Uploaded before the next frame is drawn, the mesh stays on the GPU until the app is dropped
+    pub fn upload_mesh(&mut self, mesh: &MeshData) -> MeshHandle {
+        self.resource_cache.meshes.add(mesh.clone())
+    }
+
+    pub fn display_calibration(&self) -> DisplayCalibration {
+        self.config.calibration
+    }
+
+    /// Takes effect from the next frame, values are clamped to the supported ranges
+    pub fn set_display_calibration(&mut self, calibration: DisplayCalibration) {
+        self.config.calibration = calibration.clamped();
+    }
+
+    pub fn color_filter(&self) -> ColorFilter {
+        self.config.color_filter
+    }
+
+    /// Takes effect from the next frame
+    pub fn set_color_filter(&mut self, color_filter: ColorFilter) {
+        self.config.color_filter = color_filter;
+    }
+
+    pub fn debug_view(&self) -> DebugView {
+        self.config.debug_view
+    }
+
+    /// Takes effect from the next frame. The wireframe falls back to shaded rendering without `fillModeNonSolid`.
+    pub fn set_debug_view(&mut self, debug_view: DebugView) {
+        if debug_view == DebugView::Wireframe && !self.device_dependent_resources.as_ref().unwrap().wireframe_supported {
+            warn!("fillModeNonSolid is not supported, the wireframe view is drawn shaded");
         }
+        self.config.debug_view = debug_view;
+    }
 
-        unsafe { self.device.device_wait_idle().expect("Failed to wait for device idle!"); }
+    /// Rebuilds the main pass pipelines from the shader files in `shaders/`, falling back to the embedded copies.
+    /// Waits for the device to be idle. Post-processing, text and GUI pipelines pick up changes on the next swapchain recreation.
+    pub fn reload_shaders(&mut self) {
+        let device_resources = self.device_dependent_resources.as_mut().unwrap();
+        // ignore the result: a lost device is recreated on the next frame anyway
+        let _ = unsafe { device_resources.device.device_wait_idle() };
+        device_resources.pipeline_manager.reload(&device_resources.device, &device_resources.resource_manager);
+    }
 
-        //free resources
-        match self.swapchain_dependent_resources {
-            Some(ref mut swapchain_dependent_resources) => {
-                //free resources
+    pub fn tonemapper(&self) -> Tonemapper {
+        self.config.tonemapper
+    }
 
-                for framebuffer in swapchain_dependent_resources.swapchain_framebuffers.iter() {
-                    unsafe { self.device.destroy_framebuffer(*framebuffer, None); }
-                }
+    /// Takes effect from the next frame
+    pub fn set_tonemapper(&mut self, tonemapper: Tonemapper) {
+        self.config.tonemapper = tonemapper;
+    }
 
-                unsafe { self.device.destroy_pipeline(swapchain_dependent_resources.graphics_pipeline, None); }
-                unsafe { self.device.destroy_pipeline_layout(swapchain_dependent_resources.pipeline_layout, None); }
-                unsafe { self.device.destroy_render_pass(swapchain_dependent_resources.render_pass, None); }
+    pub fn calibration_pattern(&self) -> bool {
+        self.calibration_pattern
+    }
 
-                for imageview in swapchain_dependent_resources.swapchain_imageviews.iter() {
-                    unsafe { self.device.destroy_image_view(*imageview, None); }
-                }
+    /// Replaces the texture with a dark/mid/bright test pattern, used to adjust the calibration.
+    /// The pattern fills whatever geometry is drawn, so draw a fullscreen quad while it's shown.
+    pub fn set_calibration_pattern(&mut self, enabled: bool) {
+        self.calibration_pattern = enabled;
+    }
+
+    /// Pipeline statistics of the main render pass from the latest completed frame
+    pub fn pipeline_statistics(&self) -> Option<PipelineStatistics> {
+        self.device_dependent_resources.as_ref().unwrap().profiler.pipeline_statistics()
+    }
 
-                let old_swapchain = swapchain_dependent_resources.swapchain;
+    /// Rolling GPU timings of the profiler scopes recorded in `draw_frame`
+    pub fn gpu_profile_report(&self) -> Vec<ScopeReport> {
+        self.device_dependent_resources.as_ref().unwrap().profiler.report()
+    }
 
-                self.swapchain_dependent_resources = Some(VulkanApp::create_swapchain_dependent_resources(
-                    window,
-                    &self.entry,
-                    &self.instance,
-                    &self.physical_device,
-                    self.surface,
-                    &self.device,
-                    self.image_view,
-                    self.sampler,
-                    Some(old_swapchain),
-                ));
+    /// Draw counts of the last recorded frame, see `RendererConfig::sort_draws`
+    pub fn batch_stats(&self) -> BatchStats {
+        self.batch_stats
+    }
 
-                unsafe { self.swapchain_dependent_resources.as_ref().unwrap().swapchain_loader.destroy_swapchain(old_swapchain, None); }
+    /// Usage and budget of each memory heap, see `ResourceManager::memory_stats`
+    pub fn memory_stats(&self) -> Vec<HeapStats> {
+        self.device_dependent_resources.as_ref().unwrap().resource_manager.memory_stats()
+    }
 
+    /// Rolling CPU frame timings, GPU time comes from the profiler's "frame" scope
+    pub fn frame_timing(&self) -> FrameTimingReport {
+        let gpu_ms = self.gpu_profile_report().iter().find(|s| s.name == "frame").map(|s| s.avg_ms);
+        self.frame_timer.report(gpu_ms)
+    }
 
+    /// Rolling per-frame CPU time of the renderer's stages and those added with `add_cpu_stage`
+    pub fn cpu_stage_report(&self) -> Vec<CpuStageReport> {
+        self.frame_timer.stage_report()
+    }
 
-            },
+    /// Adds app-side CPU work (simulation, input, ...) to the stage report of the current frame
+    pub fn add_cpu_stage(&mut self, name: &'static str, duration: std::time::Duration) {
+        self.frame_timer.add_stage(name, duration);
+    }
+
+    /// Profile of the current device, the frame rate cap it suggests is applied only at creation
+    pub fn device_profile(&self) -> DeviceProfile {
+        self.device_dependent_resources.as_ref().unwrap().device_profile
+    }
+
+    pub fn frame_rate_cap(&self) -> Option<f32> {
+        self.frame_timer.frame_rate_cap()
+    }
+
+    /// Limits how often `draw_frame` starts a new frame, None removes the cap
+    pub fn set_frame_rate_cap(&mut self, cap: Option<f32>) {
+        self.frame_timer.set_frame_rate_cap(cap);
+    }
+
+    /// Number of images the driver actually created, may be more than requested
+    pub fn swapchain_image_count(&self) -> u32 {
+        self.swapchain_dependent_resources.as_ref().unwrap().swapchain_images.len() as u32
+    }
+
+    /// Color space the swapchain was actually created with, the fragment shader encodes its output for it
+    pub fn display_color_space(&self) -> DisplayColorSpace {
+        self.swapchain_dependent_resources.as_ref().unwrap().color_space
+    }
+
+    /// Physical devices scored for the current preference and surface
+    pub fn list_gpus(&self) -> Vec<GpuInfo> {
+        let surface_loader = extensions::khr::Surface::new(&self.entry, &self.instance);
+        gpu_selection::list_gpus(&self.instance, self.gpu_preference, self.surface.map(|surface| (&surface_loader, surface)))
+    }
+
+    pub fn gpu_preference(&self) -> GpuPreference {
+        self.gpu_preference
+    }
+
+    pub fn set_gpu_preference(&mut self, window: &glfw::Window, gpu_preference: GpuPreference) {
+        self.gpu_preference = gpu_preference;
+        self.check_gpu_switch(window);
+    }
+
+    /// Migrates to another GPU if the current one disappeared or the preference (e.g. power source) now picks a different one.
+    /// Meant to be polled periodically, it enumerates physical devices on every call.
+    pub fn check_gpu_switch(&mut self, window: &glfw::Window) {
+        let current = self.device_dependent_resources.as_ref().unwrap().physical_device;
+        let surface_loader = extensions::khr::Surface::new(&self.entry, &self.instance);
+        let surface_support = self.surface.map(|surface| (&surface_loader, surface));
+        let preferred = match gpu_selection::select_physical_device(&self.instance, self.gpu_preference, self.config.gpu.as_ref(), surface_support) {
+            Some(d) => d,
             None => {
-                println!("No swapchain dependent resources to free");
+                warn!("No avaliable physical device found, keeping the current one");
+                return;
             }
-        }
+        };
 
+        if preferred != current {
+            info!("Preferred GPU changed, migrating to the new device");
+            self.recreate_device(Some(window));
+        }
     }
+
     pub fn framebuffer_resize(&mut self, width: u32, height: u32, window: &glfw::Window) {
-        println!("Framebuffer resized to {}x{}", width, height);
+        debug!("Framebuffer resized to {}x{}", width, height);
         self.recreate_swapchain(window);
     }
 }
 
+impl Drop for VulkanApp {
+    fn drop(&mut self) {
+        // the messenger's user data is freed together with VulkanApp
+        if let (Some(debug_utils_loader), Some(debug_messenger)) = (&self.debug_utils_loader, self.debug_messenger) {
+            unsafe { debug_utils_loader.destroy_debug_utils_messenger(debug_messenger, None) };
+        }
+    }
+}
+
+const VALIDATION_LAYER_NAME: &std::ffi::CStr = c"VK_LAYER_KHRONOS_validation";
+
+fn validation_layer_names(validation: bool) -> Vec<*const i8> {
+    let mut validation_layers = Vec::new();
+    if validation {
+        validation_layers.push(VALIDATION_LAYER_NAME.as_ptr());
+    }
+    validation_layers
+}
+
+/// Points set 0 of the main pass at one frame's `FrameUniforms` and `DrawData` buffers
+fn write_frame_descriptor_set(device: &ash::Device, resource_manager: &ResourceManager, descriptor_set: vk::DescriptorSet, uniform_buffer: &BufferHandle, draw_buffer: &BufferHandle) {
+    let uniform_buffer_infos = [vk::DescriptorBufferInfo::builder()
+        .buffer(resource_manager.buffer(uniform_buffer))
+        .offset(0)
+        .range(vk::WHOLE_SIZE)
+        .build()];
+    let draw_buffer_infos = [vk::DescriptorBufferInfo::builder()
+        .buffer(resource_manager.buffer(draw_buffer))
+        .offset(0)
+        .range(vk::WHOLE_SIZE)
+        .build()];
+    let descriptor_write_set = [vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(1)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+        .buffer_info(&uniform_buffer_infos)
+        .build(),
+    vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(2)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(&draw_buffer_infos)
+        .build()];
+    unsafe { device.update_descriptor_sets(&descriptor_write_set, &[]) };
+}
+
+/// Highest sample count up to `requested` that the attachments of the main pass support
+fn msaa_sample_count(instance: &ash::Instance, physical_device: vk::PhysicalDevice, requested: u32) -> vk::SampleCountFlags {
+    let limits = unsafe { instance.get_physical_device_properties(physical_device) }.limits;
+    let attachments = [
+        (post_process::HDR_FORMAT, vk::ImageUsageFlags::COLOR_ATTACHMENT),
+        (depth_readback::DEPTH_FORMAT, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC),
+        (picking::OBJECT_ID_FORMAT, vk::ImageUsageFlags::COLOR_ATTACHMENT),
+    ];
+    let supported = attachments.iter().fold(limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts, |supported, (format, usage)| {
+        // create_image adds TRANSFER_DST
+        let properties = unsafe { instance.get_physical_device_image_format_properties(physical_device, *format, vk::ImageType::TYPE_2D, vk::ImageTiling::OPTIMAL, *usage | vk::ImageUsageFlags::TRANSFER_DST, vk::ImageCreateFlags::empty()) };
+        properties.map_or(vk::SampleCountFlags::TYPE_1, |properties| supported & properties.sample_counts)
+    });
+    // the flag bits are the sample counts
+    let samples = (0..7).rev().map(|i| vk::SampleCountFlags::from_raw(1 << i))
+        .find(|samples| samples.as_raw() <= requested.max(1) && supported.contains(*samples))
+        .unwrap_or(vk::SampleCountFlags::TYPE_1);
+    if samples.as_raw() != requested.max(1) {
+        warn!("{}x MSAA is not supported, using {}x", requested, samples.as_raw());
+    }
+    samples
+}
+
+/// Usage of the per-frame scene vertex buffers, also read through a device address with vertex pulling
+fn scene_vertex_buffer_usage(vertex_pulling: bool) -> vk::BufferUsageFlags {
+    if vertex_pulling {
+        vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+    } else {
+        vk::BufferUsageFlags::VERTEX_BUFFER
+    }
+}
 
 unsafe extern "system" fn vulkan_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     message_type: vk::DebugUtilsMessageTypeFlagsEXT,
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _user_data: *mut std::ffi::c_void,
+    user_data: *mut std::ffi::c_void,
 ) -> vk::Bool32 {
+    let debug_config = unsafe { &*(user_data as *const DebugConfig) };
     let callback_data = unsafe { &*p_callback_data };
-    let msg = unsafe { std::ffi::CStr::from_ptr(callback_data.p_message) };
-    println!(
-        "validation layer: {:?} {:?}: {}",
-        message_severity, message_type, msg.to_str().unwrap()
-    );
+    if debug_config.muted_message_ids.contains(&callback_data.message_id_number) {
+        return vk::FALSE;
+    }
+
+    let msg = unsafe { std::ffi::CStr::from_ptr(callback_data.p_message) }.to_string_lossy();
+    let id_name = if callback_data.p_message_id_name.is_null() {
+        std::borrow::Cow::Borrowed("")
+    } else {
+        unsafe { std::ffi::CStr::from_ptr(callback_data.p_message_id_name) }.to_string_lossy()
+    };
+
+    if let Some(callback) = &debug_config.callback {
+        let message = DebugMessage {
+            severity: message_severity,
+            message_type,
+            id_number: callback_data.message_id_number,
+            id_name: &id_name,
+            message: &msg,
+        };
+        if callback(&message) {
+            return vk::FALSE;
+        }
+    }
+
+    match message_severity {
+        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => error!(target: "validation", "{:?} {}: {}", message_type, id_name, msg),
+        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => warn!(target: "validation", "{:?} {}: {}", message_type, id_name, msg),
+        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => info!(target: "validation", "{:?} {}: {}", message_type, id_name, msg),
+        _ => trace!(target: "validation", "{:?} {}: {}", message_type, id_name, msg),
+    }
     vk::FALSE
 }
@@ -0,0 +1,84 @@
+use ash::prelude::VkResult;
+use ash::vk;
+
+use super::resourceManager::{BufferHandle, ResourceManager};
+
+pub const OBJECT_ID_FORMAT: vk::Format = vk::Format::R32_UINT;
+
+/// Id written to the object id attachment. 0 is reserved for the cleared background.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EntityId(pub u32);
+
+#[derive(Debug, Clone, Copy)]
+pub struct PickResult {
+    pub x: u32,
+    pub y: u32,
+    pub entity: Option<EntityId>,
+}
+
+/// Readback buffer of a single in-flight frame and the pixel that was copied into it
+pub struct PickSlot {
+    buffer: BufferHandle,
+    pending: Option<(u32, u32)>,
+}
+
+impl PickSlot {
+    /// `index` is the in-flight frame, used only in the debug name
+    pub fn new(resource_manager: &mut ResourceManager, index: usize) -> Self {
+        let buffer = resource_manager.create_readback_buffer(4);
+        resource_manager.set_buffer_name(&buffer, &format!("pick_readback_buffer[{}]", index));
+        Self {
+            buffer,
+            pending: None,
+        }
+    }
+
+    /// Records a copy of `pixel`, clamped to `extent`. `id_image` must be in TRANSFER_SRC_OPTIMAL layout.
+    pub fn cmd_copy(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, resource_manager: &ResourceManager, id_image: vk::Image, extent: vk::Extent2D, pixel: (u32, u32)) {
+        let (x, y) = pixel;
+        let x = x.min(extent.width.saturating_sub(1));
+        let y = y.min(extent.height.saturating_sub(1));
+
+        let copy_region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .image_subresource(vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(0)
+                .base_array_layer(0)
+                .layer_count(1)
+                .build())
+            .image_offset(vk::Offset3D { x: x as i32, y: y as i32, z: 0 })
+            .image_extent(vk::Extent3D { width: 1, height: 1, depth: 1 });
+
+        unsafe {
+            device.cmd_copy_image_to_buffer(command_buffer, id_image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, resource_manager.buffer(&self.buffer), &[copy_region.build()]);
+
+            //make transfer write visible to host read
+            let buffer_memory_barrier = vk::BufferMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::HOST_READ)
+                .buffer(resource_manager.buffer(&self.buffer))
+                .offset(0)
+                .size(vk::WHOLE_SIZE);
+            device.cmd_pipeline_barrier(command_buffer, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::HOST, vk::DependencyFlags::empty(), &[], &[buffer_memory_barrier.build()], &[]);
+        }
+
+        self.pending = Some((x, y));
+    }
+
+    /// Must be called only after the fence of the frame that recorded the copy is signaled
+    pub fn take_result(&mut self, resource_manager: &ResourceManager) -> VkResult<Option<PickResult>> {
+        let (x, y) = match self.pending.take() {
+            Some(pixel) => pixel,
+            None => return Ok(None),
+        };
+
+        let id = resource_manager.read_buffer::<u32>(&self.buffer, 1)?[0];
+
+        Ok(Some(PickResult {
+            x,
+            y,
+            entity: if id == 0 { None } else { Some(EntityId(id)) },
+        }))
+    }
+}
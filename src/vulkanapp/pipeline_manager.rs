@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::mem;
+
+use ash::vk;
+use log::info;
+
+use super::debug_draw::DebugVertex;
+use super::reflection;
+use super::resourceManager::ResourceManager;
+use super::shaders;
+use super::vertex::{Vertex, VertexFormat};
+
+/// Vertex buffer formats drawn in the main render pass. The attributes come from the struct's `VertexFormat`
+/// and must match the inputs of the vertex shader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum VertexLayout {
+    /// `Vertex`, position and texture coordinate
+    Scene,
+    /// `DebugVertex`, position and color
+    DebugLine,
+    /// No vertex buffer binding, the shader reads its vertices through a device address
+    Pulled,
+}
+
+impl VertexLayout {
+    /// Binding 0 and its attributes, None for no vertex buffer
+    fn input_descriptions(self) -> Option<(vk::VertexInputBindingDescription, Vec<vk::VertexInputAttributeDescription>)> {
+        match self {
+            VertexLayout::Scene => Some(Vertex::input_descriptions(0)),
+            VertexLayout::DebugLine => Some(DebugVertex::input_descriptions(0)),
+            VertexLayout::Pulled => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum DepthMode {
+    /// LESS_OR_EQUAL keeps draw order for geometry at the same depth
+    TestAndWrite,
+    /// Always on top, leaves depth untouched
+    Disabled,
+}
+
+/// Everything that tells pipelines of the main render pass apart, equal keys share one pipeline.
+/// Color is always alpha blended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) struct PipelineKey {
+    /// Names for `shaders::load_spirv`
+    pub vertex_shader: &'static str,
+    pub fragment_shader: &'static str,
+    pub vertex_layout: VertexLayout,
+    pub topology: vk::PrimitiveTopology,
+    pub polygon_mode: vk::PolygonMode,
+    pub depth: DepthMode,
+    /// Writes the object id attachment, otherwise the object underneath stays pickable
+    pub writes_object_id: bool,
+}
+
+impl PipelineKey {
+    /// Lines of `DebugDraw` over the scene
+    pub const DEBUG_LINE: PipelineKey = PipelineKey {
+        vertex_shader: "debug_line_vert.spv",
+        fragment_shader: "debug_line_frag.spv",
+        vertex_layout: VertexLayout::DebugLine,
+        topology: vk::PrimitiveTopology::LINE_LIST,
+        polygon_mode: vk::PolygonMode::FILL,
+        depth: DepthMode::Disabled,
+        writes_object_id: false,
+    };
+
+    /// Meshes and sprites of a `Frame` with the fragment shader of their `Material`,
+    /// `PolygonMode::LINE` needs `fillModeNonSolid` and `vertex_pulling` needs `bufferDeviceAddress`
+    pub fn scene(fragment_shader: &'static str, polygon_mode: vk::PolygonMode, vertex_pulling: bool) -> Self {
+        PipelineKey {
+            vertex_shader: if vertex_pulling { "vert_pulling.spv" } else { "vert.spv" },
+            fragment_shader,
+            vertex_layout: if vertex_pulling { VertexLayout::Pulled } else { VertexLayout::Scene },
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            polygon_mode,
+            depth: DepthMode::TestAndWrite,
+            writes_object_id: true,
+        }
+    }
+
+    fn debug_name(&self) -> String {
+        format!("pipeline({}, {}, {:?})", self.vertex_shader, self.fragment_shader, self.polygon_mode)
+    }
+}
+
+/// Builds pipelines of the main render pass on first use and caches them by `PipelineKey`.
+/// Viewport and scissor are dynamic and the render pass and layout live as long as the device,
+/// so the pipelines survive swapchain recreation.
+pub(super) struct PipelineManager {
+    render_pass: vk::RenderPass,
+    layout: vk::PipelineLayout,
+    /// Samples per pixel of the render pass attachments
+    samples: vk::SampleCountFlags,
+    pipelines: HashMap<PipelineKey, vk::Pipeline>,
+}
+
+impl PipelineManager {
+    pub fn new(render_pass: vk::RenderPass, layout: vk::PipelineLayout, samples: vk::SampleCountFlags) -> Self {
+        Self {
+            render_pass,
+            layout,
+            samples,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, device: &ash::Device, resource_manager: &ResourceManager, key: &PipelineKey) -> vk::Pipeline {
+        if let Some(pipeline) = self.pipelines.get(key) {
+            return *pipeline;
+        }
+        let pipeline = create_pipeline(device, self.render_pass, self.layout, self.samples, key);
+        resource_manager.set_name(pipeline, &key.debug_name());
+        self.pipelines.insert(*key, pipeline);
+        pipeline
+    }
+
+    /// Rebuilds every cached pipeline from the current shader files, see `shaders::load_spirv`.
+    /// The device must be idle.
+    pub fn reload(&mut self, device: &ash::Device, resource_manager: &ResourceManager) {
+        for (key, pipeline) in self.pipelines.iter_mut() {
+            let new_pipeline = create_pipeline(device, self.render_pass, self.layout, self.samples, key);
+            resource_manager.set_name(new_pipeline, &key.debug_name());
+            unsafe { device.destroy_pipeline(mem::replace(pipeline, new_pipeline), None) };
+        }
+        info!("Reloaded {} pipelines", self.pipelines.len());
+    }
+
+    pub fn destroy(self, device: &ash::Device) {
+        for pipeline in self.pipelines.into_values() {
+            unsafe { device.destroy_pipeline(pipeline, None) };
+        }
+    }
+}
+
+fn create_pipeline(device: &ash::Device, render_pass: vk::RenderPass, layout: vk::PipelineLayout, samples: vk::SampleCountFlags, key: &PipelineKey) -> vk::Pipeline {
+    let vertex_shader_code = shaders::load_spirv(key.vertex_shader);
+    let fragment_shader_code = shaders::load_spirv(key.fragment_shader);
+    let shader_module_create_info = vk::ShaderModuleCreateInfo::builder()
+        .code(&vertex_shader_code);
+    let vertex_shader_module = unsafe { device.create_shader_module(&shader_module_create_info, None).unwrap() };
+    let shader_module_create_info = vk::ShaderModuleCreateInfo::builder()
+        .code(&fragment_shader_code);
+    let fragment_shader_module = unsafe { device.create_shader_module(&shader_module_create_info, None).unwrap() };
+
+    let shader_stages = [vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vertex_shader_module)
+        .name(c"main")
+        .build(),
+    vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(fragment_shader_module)
+        .name(c"main")
+        .build()];
+
+    let (vertex_binding_descriptions, vertex_attribute_descriptions) = match key.vertex_layout.input_descriptions() {
+        Some((binding, attributes)) => (vec![binding], attributes),
+        None => (Vec::new(), Vec::new()),
+    };
+    let attribute_formats: Vec<_> = vertex_attribute_descriptions.iter().map(|a| (a.location, a.format)).collect();
+    assert_eq!(reflection::reflect(&vertex_shader_code).inputs, attribute_formats, "Inputs of {} don't match {:?}", key.vertex_shader, key.vertex_layout);
+    let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(&vertex_binding_descriptions)
+        .vertex_attribute_descriptions(&vertex_attribute_descriptions);
+    let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(key.topology)
+        .primitive_restart_enable(false);
+
+    // set when recording, the pipeline doesn't depend on the swapchain extent
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewport_count(1)
+        .scissor_count(1);
+    let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+        .dynamic_states(&dynamic_states);
+
+    let rasterizer = vk::PipelineRasterizationStateCreateInfo::builder()
+        .polygon_mode(key.polygon_mode)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::CLOCKWISE);
+    let multisampling = vk::PipelineMultisampleStateCreateInfo::builder()
+        .rasterization_samples(samples);
+
+    let depth_enabled = key.depth == DepthMode::TestAndWrite;
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(depth_enabled)
+        .depth_write_enable(depth_enabled)
+        .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL);
+
+    let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::RGBA)
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE)
+        .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .alpha_blend_op(vk::BlendOp::ADD)
+        .build(),
+    // integer attachment, blending is not supported
+    vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(if key.writes_object_id { vk::ColorComponentFlags::R } else { vk::ColorComponentFlags::empty() })
+        .blend_enable(false)
+        .build()];
+    let color_blending = vk::PipelineColorBlendStateCreateInfo::builder()
+        .attachments(&color_blend_attachments);
+
+    let graphics_pipeline_create_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&shader_stages)
+        .vertex_input_state(&vertex_input_info)
+        .input_assembly_state(&input_assembly)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterizer)
+        .multisample_state(&multisampling)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blending)
+        .dynamic_state(&dynamic_state)
+        .layout(layout)
+        .render_pass(render_pass)
+        .subpass(0)
+        .build();
+    let pipelines = unsafe { device.create_graphics_pipelines(vk::PipelineCache::null(), &[graphics_pipeline_create_info], None).unwrap() };
+
+    unsafe {
+        device.destroy_shader_module(vertex_shader_module, None);
+        device.destroy_shader_module(fragment_shader_module, None);
+    }
+    pipelines[0]
+}
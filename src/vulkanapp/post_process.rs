@@ -0,0 +1,465 @@
+use ash::vk;
+
+use super::descriptor_allocator::DescriptorAllocator;
+use super::profiler::GpuScope;
+use super::render_graph::{RenderGraph, ImageState};
+use super::resourceManager::{BufferHandle, ImageHandle, ResourceManager};
+use super::shaders;
+
+/// Format of the scene color target and of the targets between stages
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// Layout and access the main pass and every intermediate stage leave their color target in
+pub const TARGET_WRITTEN: ImageState = ImageState::new(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+const TARGET_SAMPLED: ImageState = ImageState::new(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ);
+
+/// Operator of the built-in tonemap stage, applied after the calibration exposure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tonemapper {
+    /// Exposure only, SDR outputs clip everything above 1
+    #[default]
+    None,
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve
+    Aces,
+}
+
+impl Tonemapper {
+    pub const ALL: [Tonemapper; 3] = [Tonemapper::None, Tonemapper::Reinhard, Tonemapper::Aces];
+
+    /// Value of `tonemapper` in the `FrameUniforms` block of tonemap.frag
+    pub fn shader_value(self) -> u32 {
+        match self {
+            Tonemapper::None => 0,
+            Tonemapper::Reinhard => 1,
+            Tonemapper::Aces => 2,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|t| *t == self).unwrap();
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
+
+/// Post-process anti-aliasing, an alternative to MSAA for weaker hardware
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AntiAliasing {
+    #[default]
+    None,
+    /// Fast approximate anti-aliasing on the tonemapped image, softens edges and thin details
+    Fxaa,
+}
+
+impl AntiAliasing {
+    /// Stage run after the user stages, right before the output stage
+    pub fn stage(self) -> Option<PostProcessStage> {
+        match self {
+            AntiAliasing::None => None,
+            AntiAliasing::Fxaa => Some(PostProcessStage::FXAA),
+        }
+    }
+}
+
+/// Full-screen pass of the post-processing chain.
+/// The fragment shader gets the previous stage's output as `sampler2D` at binding 0, `FrameUniforms` at binding 1,
+/// the texture coordinate at location 0 and the `transferFunction` specialization constant (id 0) of output.frag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PostProcessStage {
+    /// Debug label and profiler scope of the pass
+    pub name: &'static str,
+    /// SPIR-V of the fragment shader, a file with this name in `shaders/` is used before the built-in ones
+    pub shader: &'static str,
+}
+
+impl PostProcessStage {
+    /// Calibration exposure and `Tonemapper`, linear HDR in and out
+    pub const TONEMAP: PostProcessStage = PostProcessStage { name: "tonemap", shader: "tonemap.spv" };
+    /// FXAA 3.11, see `AntiAliasing::Fxaa`
+    pub const FXAA: PostProcessStage = PostProcessStage { name: "fxaa", shader: "fxaa.spv" };
+    /// Calibration gamma, color filter and the encoding of the swapchain color space
+    pub const OUTPUT: PostProcessStage = PostProcessStage { name: "output", shader: "output.spv" };
+}
+
+struct StagePass {
+    stage: PostProcessStage,
+    pipeline: vk::Pipeline,
+    /// One per in-flight frame, each points at that frame's uniform buffer
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    /// Index into `targets` of the sampled image
+    input: usize,
+    /// Index into `targets` of the written image, None for the last stage, which writes the output image
+    output: Option<usize>,
+}
+
+/// Images the last stage writes, the swapchain images or the offscreen image
+pub struct PostProcessOutput<'a> {
+    pub views: &'a [vk::ImageView],
+    pub format: vk::Format,
+    /// Layout the last stage leaves the images in
+    pub final_layout: vk::ImageLayout,
+    pub extent: vk::Extent2D,
+    /// `transferFunction` specialization constant of the stages
+    pub transfer_function: u32,
+}
+
+/// Full-screen passes from the scene color target to the swapchain (or offscreen) image.
+/// The main pass renders into the first target, stages ping-pong between the two targets and the last one writes the output.
+pub struct PostProcessChain {
+    targets: [ImageHandle; 2],
+    target_views: [vk::ImageView; 2],
+    /// Framebuffer of every target, for stages that write it
+    target_framebuffers: [vk::Framebuffer; 2],
+    /// One per output image
+    output_framebuffers: Vec<vk::Framebuffer>,
+    extent: vk::Extent2D,
+    sampler: vk::Sampler,
+
+    intermediate_render_pass: vk::RenderPass,
+    output_render_pass: vk::RenderPass,
+    pipeline_layout: vk::PipelineLayout,
+
+    passes: Vec<StagePass>,
+}
+
+impl PostProcessChain {
+    /// `descriptor_set_layout` must match the one of tonemap.frag, `uniform_buffers` holds one buffer per in-flight frame.
+    pub fn new(device: &ash::Device, resource_manager: &mut ResourceManager, descriptor_allocator: &mut DescriptorAllocator, stages: &[PostProcessStage], descriptor_set_layout: vk::DescriptorSetLayout, uniform_buffers: &[BufferHandle],
+               output: PostProcessOutput) -> Self {
+        let PostProcessOutput { views: output_views, format: output_format, final_layout: output_final_layout, extent, transfer_function } = output;
+        assert!(!stages.is_empty(), "The post-processing chain needs at least one stage to write the output");
+
+        let targets = [0, 1].map(|i| {
+            let target = resource_manager.create_image(extent.width,
+                extent.height,
+                HDR_FORMAT,
+                vk::ImageTiling::OPTIMAL,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED);
+            resource_manager.set_image_name(&target, &format!("post_process_target[{}]", i));
+            target
+        });
+        let target_views = [0, 1].map(|i| {
+            let view = resource_manager.create_image_view(resource_manager.image(&targets[i]), HDR_FORMAT, vk::ImageAspectFlags::COLOR);
+            resource_manager.set_name(view, &format!("post_process_target_view[{}]", i));
+            view
+        });
+
+        let sampler = resource_manager.create_sampler(vk::Filter::LINEAR, vk::SamplerAddressMode::CLAMP_TO_EDGE);
+        resource_manager.set_name(sampler, "post_process_sampler");
+
+        // every stage overwrites its whole target, the previous contents are never loaded
+        let intermediate_render_pass = create_render_pass(device, HDR_FORMAT, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        let output_render_pass = create_render_pass(device, output_format, output_final_layout);
+        resource_manager.set_name(intermediate_render_pass, "post_process_render_pass");
+        resource_manager.set_name(output_render_pass, "post_process_output_render_pass");
+
+        let create_framebuffer = |render_pass: vk::RenderPass, view: vk::ImageView| {
+            let attachments = [view];
+            let framebuffer_create_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(render_pass)
+                .attachments(&attachments)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1);
+            unsafe { device.create_framebuffer(&framebuffer_create_info, None).unwrap() }
+        };
+        let target_framebuffers = target_views.map(|view| create_framebuffer(intermediate_render_pass, view));
+        let output_framebuffers: Vec<_> = output_views.iter().map(|view| create_framebuffer(output_render_pass, *view)).collect();
+        for (i, framebuffer) in target_framebuffers.iter().enumerate() {
+            resource_manager.set_name(*framebuffer, &format!("post_process_framebuffer[{}]", i));
+        }
+        for (i, framebuffer) in output_framebuffers.iter().enumerate() {
+            resource_manager.set_name(*framebuffer, &format!("post_process_output_framebuffer[{}]", i));
+        }
+
+        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(std::slice::from_ref(&descriptor_set_layout));
+        let pipeline_layout = unsafe { device.create_pipeline_layout(&pipeline_layout_create_info, None).unwrap() };
+        resource_manager.set_name(pipeline_layout, "post_process_pipeline_layout");
+
+        let vertex_shader_code = shaders::load_spirv("post_vert.spv");
+        let shader_module_create_info = vk::ShaderModuleCreateInfo::builder()
+            .code(&vertex_shader_code);
+        let vertex_shader_module = unsafe { device.create_shader_module(&shader_module_create_info, None).unwrap() };
+
+        let passes = stages.iter().enumerate().map(|(i, stage)| {
+            let input = i % 2;
+            let output = if i + 1 == stages.len() { None } else { Some((i + 1) % 2) };
+            let render_pass = if output.is_some() { intermediate_render_pass } else { output_render_pass };
+            let pipeline = create_pipeline(device, vertex_shader_module, stage.shader, pipeline_layout, render_pass, extent, transfer_function);
+            resource_manager.set_name(pipeline, &format!("post_process_pipeline_{}", stage.name));
+
+            let descriptor_sets: Vec<_> = uniform_buffers.iter()
+                .map(|_| descriptor_allocator.allocate(device, resource_manager, descriptor_set_layout))
+                .collect();
+
+            for (frame, (descriptor_set, uniform_buffer)) in descriptor_sets.iter().zip(uniform_buffers).enumerate() {
+                resource_manager.set_name(*descriptor_set, &format!("post_process_descriptor_set_{}[{}]", stage.name, frame));
+                let descriptor_image_infos = [vk::DescriptorImageInfo::builder()
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .image_view(target_views[input])
+                    .sampler(sampler)
+                    .build()];
+                let descriptor_buffer_infos = [vk::DescriptorBufferInfo::builder()
+                    .buffer(resource_manager.buffer(uniform_buffer))
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)
+                    .build()];
+                let descriptor_write_set = [vk::WriteDescriptorSet::builder()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(0)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&descriptor_image_infos)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(1)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(&descriptor_buffer_infos)
+                    .build()];
+                unsafe { device.update_descriptor_sets(&descriptor_write_set, &[]) };
+            }
+
+            StagePass {
+                stage: *stage,
+                pipeline,
+                descriptor_sets,
+                input,
+                output,
+            }
+        }).collect();
+
+        unsafe { device.destroy_shader_module(vertex_shader_module, None); }
+
+        Self {
+            targets,
+            target_views,
+            target_framebuffers,
+            output_framebuffers,
+            extent,
+            sampler,
+
+            intermediate_render_pass,
+            output_render_pass,
+            pipeline_layout,
+
+            passes,
+        }
+    }
+
+    pub fn destroy(self, device: &ash::Device, resource_manager: &mut ResourceManager, descriptor_allocator: &mut DescriptorAllocator) {
+        for pass in &self.passes {
+            descriptor_allocator.free(device, &pass.descriptor_sets);
+        }
+        unsafe {
+            for pass in &self.passes {
+                device.destroy_pipeline(pass.pipeline, None);
+            }
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+
+            for framebuffer in self.target_framebuffers.iter().chain(self.output_framebuffers.iter()) {
+                device.destroy_framebuffer(*framebuffer, None);
+            }
+            device.destroy_render_pass(self.intermediate_render_pass, None);
+            device.destroy_render_pass(self.output_render_pass, None);
+
+            device.destroy_sampler(self.sampler, None);
+            for view in self.target_views {
+                device.destroy_image_view(view, None);
+            }
+        }
+        for target in self.targets {
+            resource_manager.destroy_image(target);
+        }
+    }
+
+    /// Color target of the main pass, which must leave it in the `TARGET_WRITTEN` state
+    pub fn scene_target(&self) -> &ImageHandle {
+        &self.targets[0]
+    }
+
+    pub fn scene_target_view(&self) -> vk::ImageView {
+        self.target_views[0]
+    }
+
+    /// Render pass of the last stage, for pipelines of the overlay passed to `add_passes`
+    pub fn output_render_pass(&self) -> vk::RenderPass {
+        self.output_render_pass
+    }
+
+    /// Adds a pass per stage, after the main pass. The last one writes output image `image_index`,
+    /// `overlay` records its draws over the result before that render pass ends.
+    pub fn add_passes<'a, 's>(&'a self, graph: &mut RenderGraph<'a, GpuScope<'s>>, device: &'a ash::Device, resource_manager: &ResourceManager, in_flight_frame: usize, image_index: usize,
+                              overlay: impl FnOnce(vk::CommandBuffer) + 'a) {
+        let mut overlay = Some(overlay);
+        for pass in &self.passes {
+            let (render_pass, framebuffer) = match pass.output {
+                Some(target) => (self.intermediate_render_pass, self.target_framebuffers[target]),
+                None => (self.output_render_pass, self.output_framebuffers[image_index]),
+            };
+            let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+                .render_pass(render_pass)
+                .framebuffer(framebuffer)
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent: self.extent,
+                })
+                .build();
+            let name = pass.stage.name;
+            let pipeline = pass.pipeline;
+            let pipeline_layout = self.pipeline_layout;
+            let descriptor_set = pass.descriptor_sets[in_flight_frame];
+            let overlay = if pass.output.is_none() { overlay.take() } else { None };
+
+            let mut graph_pass = RenderGraph::pass(name, move |frame_scope: &mut GpuScope<'s>, command_buffer| {
+                let _stage_scope = frame_scope.scope(name);
+                unsafe {
+                    device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
+                    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+                    device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline_layout, 0, &[descriptor_set], &[]);
+                    device.cmd_draw(command_buffer, 3, 1, 0, 0);
+                }
+                if let Some(overlay) = overlay {
+                    overlay(command_buffer);
+                }
+                unsafe {
+                    device.cmd_end_render_pass(command_buffer);
+                }
+            })
+                .uses(resource_manager.image(&self.targets[pass.input]), vk::ImageAspectFlags::COLOR, TARGET_SAMPLED);
+            if let Some(target) = pass.output {
+                graph_pass = graph_pass.leaves(resource_manager.image(&self.targets[target]), TARGET_WRITTEN);
+            }
+            graph.add_pass(graph_pass);
+        }
+    }
+}
+
+/// Single color attachment, overwritten by a full-screen triangle
+fn create_render_pass(device: &ash::Device, format: vk::Format, final_layout: vk::ImageLayout) -> vk::RenderPass {
+    let attachments = [vk::AttachmentDescription::builder()
+        .format(format)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(final_layout)
+        .build()];
+    let color_attachment_refs = [vk::AttachmentReference::builder()
+        .attachment(0)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .build()];
+    let subpasses = [vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_attachment_refs)
+        .build()];
+    let dependencies = [vk::SubpassDependency::builder()
+        .src_subpass(vk::SUBPASS_EXTERNAL)
+        .dst_subpass(0)
+        // targets are shared between in-flight frames, the previous frame may still read or write them.
+        // Also orders the swapchain image layout transition after the acquire semaphore wait.
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::FRAGMENT_SHADER)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .build(),
+    // the offscreen output image is read back outside of the frame's render graph
+    vk::SubpassDependency::builder()
+        .src_subpass(0)
+        .dst_subpass(vk::SUBPASS_EXTERNAL)
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+        .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+        .build()];
+    let render_pass_create_info = vk::RenderPassCreateInfo::builder()
+        .attachments(&attachments)
+        .subpasses(&subpasses)
+        .dependencies(&dependencies);
+    unsafe { device.create_render_pass(&render_pass_create_info, None).unwrap() }
+}
+
+/// No vertex input, no depth and no blending, the stage writes every pixel of the viewport once
+fn create_pipeline(device: &ash::Device, vertex_shader_module: vk::ShaderModule, fragment_shader: &str, pipeline_layout: vk::PipelineLayout, render_pass: vk::RenderPass, extent: vk::Extent2D, transfer_function: u32) -> vk::Pipeline {
+    let fragment_shader_code = shaders::load_spirv(fragment_shader);
+    let shader_module_create_info = vk::ShaderModuleCreateInfo::builder()
+        .code(&fragment_shader_code);
+    let fragment_shader_module = unsafe { device.create_shader_module(&shader_module_create_info, None).unwrap() };
+
+    let transfer_function = transfer_function.to_ne_bytes();
+    let specialization_map_entries = [vk::SpecializationMapEntry {
+        constant_id: 0,
+        offset: 0,
+        size: std::mem::size_of::<u32>(),
+    }];
+    let specialization_info = vk::SpecializationInfo::builder()
+        .map_entries(&specialization_map_entries)
+        .data(&transfer_function);
+    let shader_stages = [vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vertex_shader_module)
+        .name(c"main")
+        .build(),
+    vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(fragment_shader_module)
+        .name(c"main")
+        .specialization_info(&specialization_info)
+        .build()];
+
+    let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder();
+    let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let viewports = [vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(extent.width as f32)
+        .height(extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0)
+        .build()];
+    let scissors = [vk::Rect2D::builder()
+        .offset(vk::Offset2D::builder().x(0).y(0).build())
+        .extent(extent)
+        .build()];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(&viewports)
+        .scissors(&scissors);
+
+    let rasterizer = vk::PipelineRasterizationStateCreateInfo::builder()
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE);
+    let multisampling = vk::PipelineMultisampleStateCreateInfo::builder()
+        .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+
+    let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::RGBA)
+        .blend_enable(false)
+        .build()];
+    let color_blending = vk::PipelineColorBlendStateCreateInfo::builder()
+        .attachments(&color_blend_attachments);
+
+    let graphics_pipeline_create_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&shader_stages)
+        .vertex_input_state(&vertex_input_info)
+        .input_assembly_state(&input_assembly)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterizer)
+        .multisample_state(&multisampling)
+        .color_blend_state(&color_blending)
+        .layout(pipeline_layout)
+        .render_pass(render_pass)
+        .subpass(0)
+        .build();
+    let pipelines = unsafe { device.create_graphics_pipelines(vk::PipelineCache::null(), &[graphics_pipeline_create_info], None).unwrap() };
+
+    unsafe { device.destroy_shader_module(fragment_shader_module, None); }
+    pipelines[0]
+}
@@ -0,0 +1,292 @@
+use std::collections::VecDeque;
+
+use ash::prelude::VkResult;
+use ash::vk;
+use log::warn;
+
+use super::resourceManager::ResourceManager;
+
+/// Scopes per frame, each scope takes two timestamp queries
+const MAX_SCOPES_PER_FRAME: u32 = 16;
+
+/// Number of frames kept for the rolling statistics
+const STATS_WINDOW: usize = 120;
+
+#[derive(Debug)]
+pub struct ScopeReport {
+    pub name: &'static str,
+    pub avg_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+}
+
+/// Pipeline statistics of the region wrapped by `GpuScope::pipeline_statistics`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PipelineStatistics {
+    pub vertex_shader_invocations: u64,
+    pub clipping_invocations: u64,
+    pub clipping_primitives: u64,
+    pub fragment_shader_invocations: u64,
+}
+
+/// Results are written in the order of the flag bits
+const PIPELINE_STATISTICS_FLAGS: vk::QueryPipelineStatisticFlags = vk::QueryPipelineStatisticFlags::from_raw(
+    vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw() |
+    vk::QueryPipelineStatisticFlags::CLIPPING_INVOCATIONS.as_raw() |
+    vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw() |
+    vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw());
+
+struct FrameQueries {
+    /// Scope name and index of its first query, relative to the frame's base query
+    scopes: Vec<(&'static str, u32)>,
+    pipeline_statistics_recorded: bool,
+}
+
+struct ScopeStats {
+    name: &'static str,
+    samples_ms: VecDeque<f64>,
+}
+
+/// Timestamp profiler with named scopes.
+/// Every in-flight frame owns its own range of queries, results are collected when the
+/// frame slot is reused, so reading them never stalls the CPU.
+pub struct GpuProfiler {
+    query_pool: vk::QueryPool,
+    timestamp_period_ns: f64,
+
+    /// One query per in-flight frame, None if the device doesn't support pipelineStatisticsQuery
+    pipeline_statistics_pool: Option<vk::QueryPool>,
+    pipeline_statistics: Option<PipelineStatistics>,
+
+    frames: Vec<FrameQueries>,
+    cur_frame: usize,
+
+    stats: Vec<ScopeStats>,
+}
+
+impl GpuProfiler {
+    /// `pipeline_statistics_query` must be true only if the feature was enabled on the device
+    pub fn new(instance: &ash::Instance, physical_device: vk::PhysicalDevice, device: &ash::Device, in_flight_frames: usize, pipeline_statistics_query: bool) -> Self {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+
+        let query_pool_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(MAX_SCOPES_PER_FRAME * 2 * in_flight_frames as u32)
+            .build();
+
+        let query_pool = unsafe { device.create_query_pool(&query_pool_info, None).unwrap() };
+
+        let pipeline_statistics_pool = if pipeline_statistics_query {
+            let query_pool_info = vk::QueryPoolCreateInfo::builder()
+                .query_type(vk::QueryType::PIPELINE_STATISTICS)
+                .pipeline_statistics(PIPELINE_STATISTICS_FLAGS)
+                .query_count(in_flight_frames as u32)
+                .build();
+            Some(unsafe { device.create_query_pool(&query_pool_info, None).unwrap() })
+        } else {
+            warn!("Pipeline statistics queries are not supported");
+            None
+        };
+
+        Self {
+            query_pool,
+            timestamp_period_ns: properties.limits.timestamp_period as f64,
+
+            pipeline_statistics_pool,
+            pipeline_statistics: None,
+
+            frames: (0..in_flight_frames).map(|_| FrameQueries { scopes: Vec::new(), pipeline_statistics_recorded: false }).collect(),
+            cur_frame: 0,
+
+            stats: Vec::new(),
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_query_pool(self.query_pool, None);
+            if let Some(pool) = self.pipeline_statistics_pool {
+                device.destroy_query_pool(pool, None);
+            }
+        }
+    }
+
+    pub fn set_debug_names(&self, resource_manager: &ResourceManager) {
+        resource_manager.set_name(self.query_pool, "profiler_timestamp_pool");
+        if let Some(pool) = self.pipeline_statistics_pool {
+            resource_manager.set_name(pool, "profiler_pipeline_statistics_pool");
+        }
+    }
+
+    fn base_query(&self, frame: usize) -> u32 {
+        frame as u32 * MAX_SCOPES_PER_FRAME * 2
+    }
+
+    /// Collects results of the frame that previously used this slot and resets its queries.
+    /// Call after waiting for the in-flight fence of `frame`, before recording any scope.
+    pub fn begin_frame(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame: usize) -> VkResult<()> {
+        self.cur_frame = frame;
+        let base_query = self.base_query(frame);
+
+        let scopes = std::mem::take(&mut self.frames[frame].scopes);
+        if !scopes.is_empty() {
+            let query_count = scopes.len() as u32 * 2;
+            let mut timestamps = vec![0u64; query_count as usize];
+            let res = unsafe {
+                device.get_query_pool_results(self.query_pool, base_query, query_count, &mut timestamps, vk::QueryResultFlags::TYPE_64)
+            };
+            match res {
+                Ok(()) => {
+                    for (name, first_query) in scopes {
+                        let ticks = timestamps[first_query as usize + 1].wrapping_sub(timestamps[first_query as usize]);
+                        self.add_sample(name, ticks as f64 * self.timestamp_period_ns / 1_000_000.0);
+                    }
+                },
+                // results are not there yet, drop this frame's samples instead of waiting
+                Err(vk::Result::NOT_READY) => {},
+                Err(e) => return Err(e),
+            }
+        }
+
+        unsafe { device.cmd_reset_query_pool(command_buffer, self.query_pool, base_query, MAX_SCOPES_PER_FRAME * 2); }
+
+        if let Some(pool) = self.pipeline_statistics_pool {
+            if std::mem::take(&mut self.frames[frame].pipeline_statistics_recorded) {
+                let mut results = [0u64; 4];
+                let res = unsafe {
+                    device.get_query_pool_results(pool, frame as u32, 1, &mut results, vk::QueryResultFlags::TYPE_64)
+                };
+                match res {
+                    Ok(()) => {
+                        self.pipeline_statistics = Some(PipelineStatistics {
+                            vertex_shader_invocations: results[0],
+                            clipping_invocations: results[1],
+                            clipping_primitives: results[2],
+                            fragment_shader_invocations: results[3],
+                        });
+                    },
+                    Err(vk::Result::NOT_READY) => {},
+                    Err(e) => return Err(e),
+                }
+            }
+            unsafe { device.cmd_reset_query_pool(command_buffer, pool, frame as u32, 1); }
+        }
+        Ok(())
+    }
+
+    /// Statistics of the latest completed frame, None if unsupported or nothing was recorded yet
+    pub fn pipeline_statistics(&self) -> Option<PipelineStatistics> {
+        self.pipeline_statistics
+    }
+
+    /// Starts a named scope, the end timestamp is written when the returned guard is dropped.
+    /// Scopes over the per-frame limit are not measured.
+    pub fn scope<'a>(&'a mut self, device: &'a ash::Device, command_buffer: vk::CommandBuffer, name: &'static str) -> GpuScope<'a> {
+        let frame = &mut self.frames[self.cur_frame];
+        let end_query = if (frame.scopes.len() as u32) < MAX_SCOPES_PER_FRAME {
+            let first_query = frame.scopes.len() as u32 * 2;
+            frame.scopes.push((name, first_query));
+
+            let query = self.base_query(self.cur_frame) + first_query;
+            unsafe { device.cmd_write_timestamp(command_buffer, vk::PipelineStageFlags::TOP_OF_PIPE, self.query_pool, query); }
+            Some(query + 1)
+        } else {
+            None
+        };
+
+        GpuScope {
+            profiler: self,
+            device,
+            command_buffer,
+            end_query,
+        }
+    }
+
+    fn add_sample(&mut self, name: &'static str, ms: f64) {
+        let stats = match self.stats.iter_mut().position(|s| s.name == name) {
+            Some(i) => &mut self.stats[i],
+            None => {
+                self.stats.push(ScopeStats { name, samples_ms: VecDeque::with_capacity(STATS_WINDOW) });
+                self.stats.last_mut().unwrap()
+            }
+        };
+        if stats.samples_ms.len() == STATS_WINDOW {
+            stats.samples_ms.pop_front();
+        }
+        stats.samples_ms.push_back(ms);
+    }
+
+    /// Statistics over the last `STATS_WINDOW` frames, in order of first appearance
+    pub fn report(&self) -> Vec<ScopeReport> {
+        self.stats.iter().filter(|s| !s.samples_ms.is_empty()).map(|s| {
+            let mut sorted: Vec<f64> = s.samples_ms.iter().copied().collect();
+            sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let percentile = |p: f64| sorted[((sorted.len() - 1) as f64 * p).round() as usize];
+
+            ScopeReport {
+                name: s.name,
+                avg_ms: sorted.iter().sum::<f64>() / sorted.len() as f64,
+                p50_ms: percentile(0.5),
+                p95_ms: percentile(0.95),
+                max_ms: *sorted.last().unwrap(),
+            }
+        }).collect()
+    }
+}
+
+pub struct GpuScope<'a> {
+    profiler: &'a mut GpuProfiler,
+    device: &'a ash::Device,
+    command_buffer: vk::CommandBuffer,
+    end_query: Option<u32>,
+}
+
+impl<'a> GpuScope<'a> {
+    /// Nested scope, ends before this one
+    pub fn scope(&mut self, name: &'static str) -> GpuScope<'_> {
+        self.profiler.scope(self.device, self.command_buffer, name)
+    }
+
+    /// Collects pipeline statistics until the returned guard is dropped. Only the first call per frame is recorded.
+    /// The guard must be dropped in the same render pass instance it was created in (or outside of any).
+    pub fn pipeline_statistics(&mut self) -> PipelineStatisticsScope<'_> {
+        let frame = self.profiler.cur_frame;
+        let query = match self.profiler.pipeline_statistics_pool {
+            Some(pool) if !self.profiler.frames[frame].pipeline_statistics_recorded => {
+                self.profiler.frames[frame].pipeline_statistics_recorded = true;
+                unsafe { self.device.cmd_begin_query(self.command_buffer, pool, frame as u32, vk::QueryControlFlags::empty()); }
+                Some((pool, frame as u32))
+            },
+            _ => None,
+        };
+
+        PipelineStatisticsScope {
+            device: self.device,
+            command_buffer: self.command_buffer,
+            query,
+        }
+    }
+}
+
+pub struct PipelineStatisticsScope<'a> {
+    device: &'a ash::Device,
+    command_buffer: vk::CommandBuffer,
+    query: Option<(vk::QueryPool, u32)>,
+}
+
+impl<'a> Drop for PipelineStatisticsScope<'a> {
+    fn drop(&mut self) {
+        if let Some((pool, query)) = self.query {
+            unsafe { self.device.cmd_end_query(self.command_buffer, pool, query); }
+        }
+    }
+}
+
+impl<'a> Drop for GpuScope<'a> {
+    fn drop(&mut self) {
+        if let Some(query) = self.end_query {
+            unsafe { self.device.cmd_write_timestamp(self.command_buffer, vk::PipelineStageFlags::BOTTOM_OF_PIPE, self.profiler.query_pool, query); }
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+
+use ash::vk;
+
+// SPIR-V opcodes, decorations and storage classes read by `reflect`, see the SPIR-V specification
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_PUSH_CONSTANT: u32 = 9;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+const STORAGE_PHYSICAL_STORAGE_BUFFER: u32 = 5349;
+
+const EXECUTION_MODEL_VERTEX: u32 = 0;
+const EXECUTION_MODEL_FRAGMENT: u32 = 4;
+const EXECUTION_MODEL_GL_COMPUTE: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    /// 0 for a runtime sized array, its count is chosen when the layout is created
+    pub count: u32,
+}
+
+/// Resources a shader module declares
+#[derive(Debug, Clone)]
+pub(super) struct ShaderReflection {
+    pub stage: vk::ShaderStageFlags,
+    pub bindings: Vec<DescriptorBinding>,
+    /// Bytes of the push constant block, 0 without one
+    pub push_constant_size: u32,
+    /// Vertex stage inputs by location, built-ins excluded
+    pub inputs: Vec<(u32, vk::Format)>,
+}
+
+#[derive(Debug, Clone)]
+enum Type {
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray { element: u32 },
+    Struct { members: Vec<u32> },
+    Pointer { storage_class: u32, pointee: u32 },
+}
+
+/// Parses the module's types, decorations and global variables. Panics on malformed SPIR-V.
+pub(super) fn reflect(code: &[u32]) -> ShaderReflection {
+    assert!(code.len() >= 5 && code[0] == 0x0723_0203, "Not a SPIR-V module");
+
+    let mut stage = vk::ShaderStageFlags::empty();
+    let mut types = HashMap::new();
+    let mut constants = HashMap::new();
+    let mut decorations: HashMap<(u32, u32), u32> = HashMap::new();
+    let mut member_offsets: HashMap<(u32, u32), u32> = HashMap::new();
+    let mut matrix_strides: HashMap<(u32, u32), u32> = HashMap::new();
+    let mut flags: Vec<(u32, u32)> = Vec::new();
+    let mut variables = Vec::new();
+
+    let mut words = &code[5..];
+    while !words.is_empty() {
+        let word_count = (words[0] >> 16) as usize;
+        let opcode = words[0] & 0xffff;
+        assert!(word_count > 0 && word_count <= words.len(), "Truncated SPIR-V instruction");
+        let operands = &words[1..word_count];
+        words = &words[word_count..];
+
+        match opcode {
+            OP_ENTRY_POINT => stage = match operands[0] {
+                EXECUTION_MODEL_VERTEX => vk::ShaderStageFlags::VERTEX,
+                EXECUTION_MODEL_FRAGMENT => vk::ShaderStageFlags::FRAGMENT,
+                EXECUTION_MODEL_GL_COMPUTE => vk::ShaderStageFlags::COMPUTE,
+                model => panic!("Unsupported execution model {}", model),
+            },
+            OP_TYPE_INT => { types.insert(operands[0], Type::Int { width: operands[1], signed: operands[2] == 1 }); },
+            OP_TYPE_FLOAT => { types.insert(operands[0], Type::Float { width: operands[1] }); },
+            OP_TYPE_VECTOR => { types.insert(operands[0], Type::Vector { component: operands[1], count: operands[2] }); },
+            OP_TYPE_MATRIX => { types.insert(operands[0], Type::Matrix { column: operands[1], count: operands[2] }); },
+            OP_TYPE_IMAGE => { types.insert(operands[0], Type::Image { sampled: operands[6] }); },
+            OP_TYPE_SAMPLER => { types.insert(operands[0], Type::Sampler); },
+            OP_TYPE_SAMPLED_IMAGE => { types.insert(operands[0], Type::SampledImage); },
+            OP_TYPE_ARRAY => { types.insert(operands[0], Type::Array { element: operands[1], length: operands[2] }); },
+            OP_TYPE_RUNTIME_ARRAY => { types.insert(operands[0], Type::RuntimeArray { element: operands[1] }); },
+            OP_TYPE_STRUCT => { types.insert(operands[0], Type::Struct { members: operands[1..].to_vec() }); },
+            OP_TYPE_POINTER => { types.insert(operands[0], Type::Pointer { storage_class: operands[1], pointee: operands[2] }); },
+            // only 32-bit constants are used as array lengths
+            OP_CONSTANT => { constants.insert(operands[1], operands[2]); },
+            OP_VARIABLE => variables.push((operands[1], operands[0], operands[2])),
+            OP_DECORATE => match operands[1] {
+                DECORATION_BUFFER_BLOCK | DECORATION_BUILT_IN => flags.push((operands[0], operands[1])),
+                DECORATION_ARRAY_STRIDE | DECORATION_LOCATION | DECORATION_BINDING | DECORATION_DESCRIPTOR_SET => {
+                    decorations.insert((operands[0], operands[1]), operands[2]);
+                },
+                _ => {},
+            },
+            OP_MEMBER_DECORATE => match operands[2] {
+                DECORATION_OFFSET => { member_offsets.insert((operands[0], operands[1]), operands[3]); },
+                DECORATION_MATRIX_STRIDE => { matrix_strides.insert((operands[0], operands[1]), operands[3]); },
+                DECORATION_BUILT_IN => flags.push((operands[0], DECORATION_BUILT_IN)),
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+
+    let reflection = Reflector { types, constants, decorations, member_offsets, matrix_strides, flags };
+    let mut result = ShaderReflection {
+        stage,
+        bindings: Vec::new(),
+        push_constant_size: 0,
+        inputs: Vec::new(),
+    };
+    for (id, pointer_type, storage_class) in variables {
+        let ty = match reflection.types.get(&pointer_type) {
+            Some(Type::Pointer { pointee, .. }) => *pointee,
+            _ => panic!("Variable {} is not a pointer", id),
+        };
+        match storage_class {
+            STORAGE_UNIFORM_CONSTANT | STORAGE_UNIFORM | STORAGE_STORAGE_BUFFER => {
+                let (element, count) = reflection.array_element(ty);
+                let descriptor_type = match (storage_class, &reflection.types[&element]) {
+                    (STORAGE_UNIFORM_CONSTANT, Type::SampledImage) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    (STORAGE_UNIFORM_CONSTANT, Type::Sampler) => vk::DescriptorType::SAMPLER,
+                    // 2 means read and written without a sampler
+                    (STORAGE_UNIFORM_CONSTANT, Type::Image { sampled: 2 }) => vk::DescriptorType::STORAGE_IMAGE,
+                    (STORAGE_UNIFORM_CONSTANT, Type::Image { .. }) => vk::DescriptorType::SAMPLED_IMAGE,
+                    (STORAGE_UNIFORM, _) if reflection.flags.contains(&(element, DECORATION_BUFFER_BLOCK)) => vk::DescriptorType::STORAGE_BUFFER,
+                    (STORAGE_UNIFORM, _) => vk::DescriptorType::UNIFORM_BUFFER,
+                    (STORAGE_STORAGE_BUFFER, _) => vk::DescriptorType::STORAGE_BUFFER,
+                    (_, ty) => panic!("Unsupported descriptor type {:?}", ty),
+                };
+                result.bindings.push(DescriptorBinding {
+                    set: reflection.decorations.get(&(id, DECORATION_DESCRIPTOR_SET)).copied().unwrap_or(0),
+                    binding: reflection.decorations.get(&(id, DECORATION_BINDING)).copied().unwrap_or(0),
+                    descriptor_type,
+                    count,
+                });
+            },
+            STORAGE_PUSH_CONSTANT => result.push_constant_size = reflection.size(ty),
+            STORAGE_INPUT if stage == vk::ShaderStageFlags::VERTEX => {
+                if reflection.flags.contains(&(id, DECORATION_BUILT_IN)) || reflection.flags.contains(&(ty, DECORATION_BUILT_IN)) {
+                    continue;
+                }
+                let location = reflection.decorations[&(id, DECORATION_LOCATION)];
+                result.inputs.push((location, reflection.vertex_format(ty)));
+            },
+            _ => {},
+        }
+    }
+    result.bindings.sort_by_key(|b| (b.set, b.binding));
+    result.inputs.sort_by_key(|(location, _)| *location);
+    result
+}
+
+struct Reflector {
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    decorations: HashMap<(u32, u32), u32>,
+    member_offsets: HashMap<(u32, u32), u32>,
+    matrix_strides: HashMap<(u32, u32), u32>,
+    /// Decorations without operands, by target
+    flags: Vec<(u32, u32)>,
+}
+
+impl Reflector {
+    /// Element type and descriptor count of an arrayed binding, the type itself and 1 otherwise
+    fn array_element(&self, ty: u32) -> (u32, u32) {
+        match self.types[&ty] {
+            Type::Array { element, length } => (element, self.constants[&length]),
+            Type::RuntimeArray { element } => (element, 0),
+            _ => (ty, 1),
+        }
+    }
+
+    /// Bytes occupied by a value of `ty` in an explicitly laid out block
+    fn size(&self, ty: u32) -> u32 {
+        match &self.types[&ty] {
+            Type::Int { width, .. } | Type::Float { width } => width / 8,
+            // buffer reference, a 64-bit device address
+            Type::Pointer { storage_class: STORAGE_PHYSICAL_STORAGE_BUFFER, .. } => 8,
+            Type::Vector { component, count } => self.size(*component) * count,
+            Type::Matrix { column, count } => self.size(*column) * count,
+            Type::Array { element, length } => {
+                let stride = self.decorations.get(&(ty, DECORATION_ARRAY_STRIDE)).copied().unwrap_or_else(|| self.size(*element));
+                stride * self.constants[length]
+            },
+            Type::Struct { members } => members.iter().enumerate().map(|(i, member)| {
+                let offset = self.member_offsets.get(&(ty, i as u32)).copied().unwrap_or(0);
+                let size = match (self.matrix_strides.get(&(ty, i as u32)), &self.types[member]) {
+                    (Some(stride), Type::Matrix { count, .. }) => stride * count,
+                    _ => self.size(*member),
+                };
+                offset + size
+            }).max().unwrap_or(0),
+            ty => panic!("{:?} has no size", ty),
+        }
+    }
+
+    fn vertex_format(&self, ty: u32) -> vk::Format {
+        let (component, count) = match self.types[&ty] {
+            Type::Vector { component, count } => (component, count),
+            _ => (ty, 1),
+        };
+        let formats = match self.types[&component] {
+            Type::Float { width: 32 } => [vk::Format::R32_SFLOAT, vk::Format::R32G32_SFLOAT, vk::Format::R32G32B32_SFLOAT, vk::Format::R32G32B32A32_SFLOAT],
+            Type::Int { width: 32, signed: true } => [vk::Format::R32_SINT, vk::Format::R32G32_SINT, vk::Format::R32G32B32_SINT, vk::Format::R32G32B32A32_SINT],
+            Type::Int { width: 32, signed: false } => [vk::Format::R32_UINT, vk::Format::R32G32_UINT, vk::Format::R32G32B32_UINT, vk::Format::R32G32B32A32_UINT],
+            ref ty => panic!("Unsupported vertex input type {:?}", ty),
+        };
+        formats[count as usize - 1]
+    }
+}
+
+/// Bindings of descriptor set `set` over all `shaders`, stage flags merged
+pub(super) fn descriptor_set_layout_bindings(shaders: &[&ShaderReflection], set: u32) -> Vec<vk::DescriptorSetLayoutBinding> {
+    let mut bindings: Vec<vk::DescriptorSetLayoutBinding> = Vec::new();
+    for shader in shaders {
+        for b in shader.bindings.iter().filter(|b| b.set == set) {
+            match bindings.iter_mut().find(|existing| existing.binding == b.binding) {
+                Some(existing) => {
+                    assert_eq!(existing.descriptor_type, b.descriptor_type, "Binding {} has different types in different stages", b.binding);
+                    existing.stage_flags |= shader.stage;
+                },
+                None => bindings.push(vk::DescriptorSetLayoutBinding::builder()
+                    .binding(b.binding)
+                    .descriptor_type(b.descriptor_type)
+                    .descriptor_count(b.count)
+                    .stage_flags(shader.stage)
+                    .build()),
+            }
+        }
+    }
+    bindings.sort_by_key(|b| b.binding);
+    bindings
+}
+
+/// One range from offset 0 covering the largest push constant block, visible to every stage that declares one
+pub(super) fn push_constant_ranges(shaders: &[&ShaderReflection]) -> Vec<vk::PushConstantRange> {
+    let size = shaders.iter().map(|s| s.push_constant_size).max().unwrap_or(0);
+    if size == 0 {
+        return Vec::new();
+    }
+    let stage_flags = shaders.iter().filter(|s| s.push_constant_size > 0).fold(vk::ShaderStageFlags::empty(), |flags, s| flags | s.stage);
+    vec![vk::PushConstantRange::builder()
+        .stage_flags(stage_flags)
+        .offset(0)
+        .size(size)
+        .build()]
+}
@@ -0,0 +1,147 @@
+use ash::vk;
+
+use super::resourceManager::ResourceManager;
+
+const WRITE_ACCESS: vk::AccessFlags = vk::AccessFlags::from_raw(
+    vk::AccessFlags::SHADER_WRITE.as_raw() |
+    vk::AccessFlags::COLOR_ATTACHMENT_WRITE.as_raw() |
+    vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw() |
+    vk::AccessFlags::TRANSFER_WRITE.as_raw() |
+    vk::AccessFlags::HOST_WRITE.as_raw() |
+    vk::AccessFlags::MEMORY_WRITE.as_raw());
+
+/// Layout of an image and the last stage and access that touched it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageState {
+    pub layout: vk::ImageLayout,
+    pub stage: vk::PipelineStageFlags,
+    pub access: vk::AccessFlags,
+}
+
+impl ImageState {
+    pub const fn new(layout: vk::ImageLayout, stage: vk::PipelineStageFlags, access: vk::AccessFlags) -> Self {
+        Self { layout, stage, access }
+    }
+}
+
+struct ImageUse {
+    image: vk::Image,
+    aspect: vk::ImageAspectFlags,
+    state: ImageState,
+}
+
+type RecordFn<'a, C> = Box<dyn FnOnce(&mut C, vk::CommandBuffer) + 'a>;
+
+pub struct Pass<'a, C> {
+    name: &'static str,
+    uses: Vec<ImageUse>,
+    /// States the pass leaves images in by itself, like render pass final layouts
+    outputs: Vec<(vk::Image, ImageState)>,
+    record: RecordFn<'a, C>,
+}
+
+impl<'a, C> Pass<'a, C> {
+    /// The graph transitions `image` to `state` before the pass runs
+    pub fn uses(mut self, image: vk::Image, aspect: vk::ImageAspectFlags, state: ImageState) -> Self {
+        self.uses.push(ImageUse { image, aspect, state });
+        self
+    }
+
+    /// The pass itself leaves `image` in `state`, no barrier is recorded for it
+    pub fn leaves(mut self, image: vk::Image, state: ImageState) -> Self {
+        self.outputs.push((image, state));
+        self
+    }
+}
+
+/// Passes of one command buffer with the images they use.
+/// Passes run in the order they were added, the graph records the barriers and layout transitions between them.
+/// `C` is passed to every pass when it's recorded, e.g. the profiler scope of the frame.
+pub struct RenderGraph<'a, C> {
+    passes: Vec<Pass<'a, C>>,
+    states: Vec<(vk::Image, ImageState)>,
+}
+
+impl<'a, C> RenderGraph<'a, C> {
+    pub fn new() -> Self {
+        Self {
+            passes: Vec::new(),
+            states: Vec::new(),
+        }
+    }
+
+    pub fn pass(name: &'static str, record: impl FnOnce(&mut C, vk::CommandBuffer) + 'a) -> Pass<'a, C> {
+        Pass {
+            name,
+            uses: Vec::new(),
+            outputs: Vec::new(),
+            record: Box::new(record),
+        }
+    }
+
+    pub fn add_pass(&mut self, pass: Pass<'a, C>) {
+        self.passes.push(pass);
+    }
+
+    /// Images the graph hasn't seen yet are treated as UNDEFINED, their contents are discarded on first use
+    fn state(&self, image: vk::Image) -> ImageState {
+        self.states.iter().find(|(i, _)| *i == image).map(|(_, state)| *state)
+            .unwrap_or(ImageState::new(vk::ImageLayout::UNDEFINED, vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::empty()))
+    }
+
+    fn set_state(&mut self, image: vk::Image, state: ImageState) {
+        match self.states.iter_mut().find(|(i, _)| *i == image) {
+            Some((_, s)) => *s = state,
+            None => self.states.push((image, state)),
+        }
+    }
+
+    /// Records every pass with its barriers, each pass inside a debug label
+    pub fn execute(mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, resource_manager: &ResourceManager, context: &mut C) {
+        for pass in std::mem::take(&mut self.passes) {
+            let mut src_stage = vk::PipelineStageFlags::empty();
+            let mut dst_stage = vk::PipelineStageFlags::empty();
+            let mut barriers = Vec::new();
+            for image_use in &pass.uses {
+                let prev = self.state(image_use.image);
+                let new = image_use.state;
+                // read after read in the same layout needs no synchronization
+                let hazard = prev.access.intersects(WRITE_ACCESS) || new.access.intersects(WRITE_ACCESS);
+                if prev.layout != new.layout || hazard {
+                    src_stage |= prev.stage;
+                    dst_stage |= new.stage;
+                    barriers.push(vk::ImageMemoryBarrier::builder()
+                        .old_layout(prev.layout)
+                        .new_layout(new.layout)
+                        .src_access_mask(prev.access & WRITE_ACCESS)
+                        .dst_access_mask(new.access)
+                        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .image(image_use.image)
+                        .subresource_range(vk::ImageSubresourceRange {
+                            aspect_mask: image_use.aspect,
+                            base_mip_level: 0,
+                            level_count: 1,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        })
+                        .build());
+                }
+                self.set_state(image_use.image, new);
+            }
+
+            resource_manager.cmd_begin_label(command_buffer, pass.name);
+            if !barriers.is_empty() {
+                unsafe {
+                    device.cmd_pipeline_barrier(command_buffer, src_stage, dst_stage, vk::DependencyFlags::empty(), &[], &[], &barriers);
+                }
+            }
+            (pass.record)(context, command_buffer);
+            resource_manager.cmd_end_label(command_buffer);
+
+            for (image, state) in pass.outputs {
+                self.set_state(image, state);
+            }
+        }
+    }
+}
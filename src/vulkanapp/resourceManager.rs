@@ -1,6 +1,12 @@
+use std::ffi::c_void;
 use std::fmt::Debug;
 
+use ash::prelude::VkResult;
 use ash::vk::{self, CommandBufferUsageFlags};
+use log::{info, warn};
+
+/// Fraction of a heap's budget in use above which allocations warn
+const BUDGET_WARNING_THRESHOLD: f64 = 0.9;
 
 #[derive(Debug)]
 pub enum HostAccessPolicy {
@@ -11,29 +17,126 @@ pub enum HostAccessPolicy {
     SingleBuffer(usize),
 }
 
-#[derive(Clone, Copy)]
-pub struct BufferResource {
-    pub buffer: vk::Buffer,
-    pub memory: vk::DeviceMemory,
-    pub size: vk::DeviceSize,
+/// Buffer owned by `ResourceManager`.
+/// Freed with `ResourceManager::destroy_buffer` or when the manager is dropped.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct BufferHandle(SlotId);
+
+/// Image owned by `ResourceManager`.
+/// Freed with `ResourceManager::destroy_image` or when the manager is dropped.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct ImageHandle(SlotId);
+
+/// Slot index plus the generation of the slot and the id of the manager that created it.
+/// Slots are reused, so a handle is valid only while both still match.
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct SlotId {
+    manager: u32,
+    index: usize,
+    generation: u32,
+}
+
+/// The handle's resource was destroyed, or the handle belongs to another (recreated) `ResourceManager`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaleHandle;
+
+impl std::fmt::Display for StaleHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "resource handle is stale")
+    }
+}
+
+impl std::error::Error for StaleHandle {}
+
+/// Id of the next `ResourceManager`, handles of a destroyed device never match a new manager
+static NEXT_MANAGER_ID: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+struct Slots<T> {
+    manager: u32,
+    slots: Vec<Slot<T>>,
+}
+
+impl<T> Slots<T> {
+    fn new(manager: u32) -> Self {
+        Self { manager, slots: Vec::new() }
+    }
+
+    fn insert(&mut self, value: T) -> SlotId {
+        let index = match self.slots.iter().position(|slot| slot.value.is_none()) {
+            Some(i) => i,
+            None => {
+                self.slots.push(Slot { generation: 0, value: None });
+                self.slots.len() - 1
+            }
+        };
+        self.slots[index].value = Some(value);
+        SlotId { manager: self.manager, index, generation: self.slots[index].generation }
+    }
+
+    fn get(&self, id: &SlotId) -> Result<&T, StaleHandle> {
+        match self.slots.get(id.index) {
+            Some(slot) if id.manager == self.manager && slot.generation == id.generation => slot.value.as_ref().ok_or(StaleHandle),
+            _ => Err(StaleHandle),
+        }
+    }
+
+    /// Bumps the slot generation, so every copy of the id becomes stale
+    fn remove(&mut self, id: &SlotId) -> Result<T, StaleHandle> {
+        self.get(id)?;
+        let slot = &mut self.slots[id.index];
+        slot.generation = slot.generation.wrapping_add(1);
+        Ok(slot.value.take().unwrap())
+    }
+
+    fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.slots.iter_mut().filter_map(|slot| slot.value.take())
+    }
+}
+
+/// Memory usage of one heap, see `ResourceManager::memory_stats`
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub device_local: bool,
+    /// Bytes allocated through the `ResourceManager`
+    pub allocated: vk::DeviceSize,
+    /// Bytes used by the whole process, `allocated` without VK_EXT_memory_budget
+    pub used: vk::DeviceSize,
+    /// Bytes the process can use before allocations fail or slow down, the heap size without VK_EXT_memory_budget
+    pub budget: vk::DeviceSize,
+}
+
+struct BufferResource {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+    allocation_size: vk::DeviceSize,
+    memory_type: usize,
+
+    /// Persistently mapped pointer, set for host visible buffers
+    mapped_ptr: Option<*mut c_void>,
 }
 
-#[derive(Clone, Copy)]
-pub struct ImageResource {
-    pub image: vk::Image,
-    pub memory: vk::DeviceMemory,
-    pub size: vk::DeviceSize,
+struct ImageResource {
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    allocation_size: vk::DeviceSize,
+    memory_type: usize,
 
-    pub width: u32,
-    pub height: u32,
+    width: u32,
+    height: u32,
 }
 
 pub struct ResourceManager {
     pub host_access_policy: HostAccessPolicy,
-    pub buffer_resources: Vec<BufferResource>,
+    buffer_resources: Slots<BufferResource>,
     staging_buffer: Option<BufferResource>,
 
-    pub image_resources: Vec<ImageResource>,
+    image_resources: Slots<ImageResource>,
 
     device: ash::Device,
     queue: vk::Queue,
@@ -41,10 +144,22 @@ pub struct ResourceManager {
     transfer_completed_fence: vk::Fence,
 
     memory_types: Vec<vk::MemoryType>,
+    memory_heaps: Vec<vk::MemoryHeap>,
+    /// Bytes allocated per heap
+    heap_allocated: Vec<vk::DeviceSize>,
+    /// Heaps past `BUDGET_WARNING_THRESHOLD` at their last allocation, warned about once until they drop below
+    heaps_near_budget: Vec<bool>,
+    /// Set when VK_EXT_memory_budget is enabled, used to query the budget
+    memory_budget: Option<(ash::Instance, vk::PhysicalDevice)>,
+
+    /// Set when validation is enabled, used to name objects
+    debug_utils: Option<ash::extensions::ext::DebugUtils>,
 }
 
 impl ResourceManager {
-    pub fn new(instance: &ash::Instance, physical_device: vk::PhysicalDevice, device: ash::Device, queue: vk::Queue, command_buffer: vk::CommandBuffer) -> Self {
+    /// `memory_budget` tells whether VK_EXT_memory_budget is enabled on `device`
+    pub fn new(instance: &ash::Instance, physical_device: vk::PhysicalDevice, device: ash::Device, queue: vk::Queue, command_buffer: vk::CommandBuffer,
+               debug_utils: Option<ash::extensions::ext::DebugUtils>, memory_budget: bool) -> Self {
         //query memory properties info
         let memory_properties = unsafe {instance.get_physical_device_memory_properties(physical_device)};
 
@@ -56,6 +171,12 @@ impl ResourceManager {
                 return true;
             }
             return false;
+        }).or_else(|| {
+            // non-coherent memory is fine too, writes are flushed explicitly
+            memory_properties.memory_types.iter().enumerate().find(|(i, memory_type)| {
+                *i < memory_properties.memory_type_count as usize &&
+                    memory_type.property_flags.contains( vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::HOST_VISIBLE)
+            })
         });
         
 
@@ -93,15 +214,16 @@ impl ResourceManager {
             }
         };
 
-        println!("Host access policy: {:?}", host_access_policy);
+        info!("Host access policy: {:?}", host_access_policy);
 
         let fence = unsafe {device.create_fence(&vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED), None).unwrap()};
 
-        Self {
-            buffer_resources: Vec::new(),
+        let manager_id = NEXT_MANAGER_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let resource_manager = Self {
+            buffer_resources: Slots::new(manager_id),
             host_access_policy,
 
-            image_resources: Vec::new(),
+            image_resources: Slots::new(manager_id),
 
             device,
             queue,
@@ -110,10 +232,113 @@ impl ResourceManager {
             transfer_completed_fence: fence,
 
             memory_types: memory_properties.memory_types.iter().map(|x| *x).collect(),
+            memory_heaps: memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize].to_vec(),
+            heap_allocated: vec![0; memory_properties.memory_heap_count as usize],
+            heaps_near_budget: vec![false; memory_properties.memory_heap_count as usize],
+            memory_budget: memory_budget.then(|| (instance.clone(), physical_device)),
+
+            debug_utils,
+        };
+        resource_manager.set_name(fence, "transfer_completed_fence");
+        resource_manager
+    }
+
+    pub fn buffer(&self, handle: &BufferHandle) -> vk::Buffer {
+        self.buffer_resource(handle).buffer
+    }
+
+    pub fn buffer_size(&self, handle: &BufferHandle) -> vk::DeviceSize {
+        self.buffer_resource(handle).size
+    }
+
+    /// GPU pointer to the start of the buffer for shaders, see `GL_EXT_buffer_reference`.
+    /// The buffer must be created with `SHADER_DEVICE_ADDRESS` usage, which needs the `bufferDeviceAddress` feature.
+    pub fn buffer_device_address(&self, handle: &BufferHandle) -> vk::DeviceAddress {
+        let address_info = vk::BufferDeviceAddressInfo::builder()
+            .buffer(self.buffer(handle));
+        unsafe { self.device.get_buffer_device_address(&address_info) }
+    }
+
+    pub fn image(&self, handle: &ImageHandle) -> vk::Image {
+        self.image_resource(handle).image
+    }
+
+    /// Names any object of this device for validation messages and RenderDoc captures. Does nothing without debug utils.
+    pub fn set_name<T: vk::Handle>(&self, handle: T, name: &str) {
+        let debug_utils = match &self.debug_utils {
+            Some(debug_utils) => debug_utils,
+            None => return,
+        };
+        let name = std::ffi::CString::new(name).unwrap();
+        let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+            .object_type(T::TYPE)
+            .object_handle(handle.as_raw())
+            .object_name(&name);
+        // naming is best effort, a failure must not break rendering
+        let _ = unsafe { debug_utils.set_debug_utils_object_name(self.device.handle(), &name_info) };
+    }
+
+    /// Opens a labeled region in `command_buffer` for GPU captures. Does nothing without debug utils.
+    pub fn cmd_begin_label(&self, command_buffer: vk::CommandBuffer, name: &str) {
+        if let Some(debug_utils) = &self.debug_utils {
+            let name = std::ffi::CString::new(name).unwrap();
+            let label = vk::DebugUtilsLabelEXT::builder().label_name(&name);
+            unsafe { debug_utils.cmd_begin_debug_utils_label(command_buffer, &label) };
+        }
+    }
+
+    pub fn cmd_end_label(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(debug_utils) = &self.debug_utils {
+            unsafe { debug_utils.cmd_end_debug_utils_label(command_buffer) };
+        }
+    }
+
+    /// Opens a labeled region on the queue, for work like present that isn't recorded in a command buffer
+    pub fn queue_begin_label(&self, name: &str) {
+        if let Some(debug_utils) = &self.debug_utils {
+            let name = std::ffi::CString::new(name).unwrap();
+            let label = vk::DebugUtilsLabelEXT::builder().label_name(&name);
+            unsafe { debug_utils.queue_begin_debug_utils_label(self.queue, &label) };
+        }
+    }
+
+    pub fn queue_end_label(&self) {
+        if let Some(debug_utils) = &self.debug_utils {
+            unsafe { debug_utils.queue_end_debug_utils_label(self.queue) };
+        }
+    }
+
+    pub fn set_buffer_name(&self, handle: &BufferHandle, name: &str) {
+        if let Ok(buffer) = self.try_buffer(handle) {
+            self.set_name(buffer, name);
+        }
+    }
+
+    pub fn set_image_name(&self, handle: &ImageHandle, name: &str) {
+        if let Ok(image) = self.try_image(handle) {
+            self.set_name(image, name);
         }
     }
 
-    pub fn create_buffer(&mut self, size: vk::DeviceSize, mut usage: vk::BufferUsageFlags) -> BufferResource {
+    /// Same as `buffer`, but returns an error instead of panicking on a stale handle
+    pub fn try_buffer(&self, handle: &BufferHandle) -> Result<vk::Buffer, StaleHandle> {
+        self.buffer_resources.get(&handle.0).map(|resource| resource.buffer)
+    }
+
+    /// Same as `image`, but returns an error instead of panicking on a stale handle
+    pub fn try_image(&self, handle: &ImageHandle) -> Result<vk::Image, StaleHandle> {
+        self.image_resources.get(&handle.0).map(|resource| resource.image)
+    }
+
+    fn buffer_resource(&self, handle: &BufferHandle) -> &BufferResource {
+        self.buffer_resources.get(&handle.0).unwrap_or_else(|_| panic!("Stale buffer handle {:?}", handle))
+    }
+
+    fn image_resource(&self, handle: &ImageHandle) -> &ImageResource {
+        self.image_resources.get(&handle.0).unwrap_or_else(|_| panic!("Stale image handle {:?}", handle))
+    }
+
+    pub fn create_buffer(&mut self, size: vk::DeviceSize, mut usage: vk::BufferUsageFlags) -> BufferHandle {
         if let HostAccessPolicy::UseStaging { host_memory_type: _, device_memory_type: _ } = self.host_access_policy {
             usage |= vk::BufferUsageFlags::TRANSFER_DST;
         }
@@ -126,64 +351,180 @@ impl ResourceManager {
 
         let memory_requirements = unsafe {self.device.get_buffer_memory_requirements(buffer)};
 
-        let memory_allocate_info = match self.host_access_policy {
-            HostAccessPolicy::SingleBuffer(memory_type) => {
-                vk::MemoryAllocateInfo::builder()
-                    .allocation_size(memory_requirements.size)
-                    .memory_type_index(memory_type as u32)
-            },
-            HostAccessPolicy::UseStaging { host_memory_type: _, device_memory_type } => {
-                vk::MemoryAllocateInfo::builder()
-                    .allocation_size(memory_requirements.size)
-                    .memory_type_index(device_memory_type as u32)
-            }
+        let memory_type = match self.host_access_policy {
+            HostAccessPolicy::SingleBuffer(memory_type) => memory_type,
+            HostAccessPolicy::UseStaging { host_memory_type: _, device_memory_type } => device_memory_type,
         };
+        let mut memory_allocate_flags_info = vk::MemoryAllocateFlagsInfo::builder()
+            .flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS);
+        let mut memory_allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(memory_requirements.size)
+            .memory_type_index(memory_type as u32);
+        if usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) {
+            memory_allocate_info = memory_allocate_info.push_next(&mut memory_allocate_flags_info);
+        }
 
-        let memory = unsafe {self.device.allocate_memory(&memory_allocate_info, None)}.unwrap();
+        let memory = self.allocate_memory(&memory_allocate_info);
 
         unsafe {self.device.bind_buffer_memory(buffer, memory, 0)}.unwrap();
 
+        // map once and keep the pointer for the lifetime of the buffer
+        let mapped_ptr = match self.host_access_policy {
+            HostAccessPolicy::SingleBuffer(_) => Some(unsafe {self.device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())}.unwrap()),
+            HostAccessPolicy::UseStaging { .. } => None,
+        };
+
         let res = BufferResource {
             buffer,
             memory,
             size,
+            allocation_size: memory_requirements.size,
+            memory_type,
+            mapped_ptr,
         };
-        self.buffer_resources.push(res);
 
-        res
+        BufferHandle(self.buffer_resources.insert(res))
+    }
+
+    /// Host visible, persistently mapped buffer for copying data back from the GPU. Read it with `read_buffer`.
+    pub fn create_readback_buffer(&mut self, size: vk::DeviceSize) -> BufferHandle {
+        let buffer_create_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let buffer = unsafe {self.device.create_buffer(&buffer_create_info, None)}.unwrap();
+
+        let memory_requirements = unsafe {self.device.get_buffer_memory_requirements(buffer)};
+
+        //prefer cached memory, reads from uncached memory are slow
+        let find_memory_type = |flags: vk::MemoryPropertyFlags| {
+            self.memory_types.iter().enumerate().position(|(i, memory_type)| {
+                memory_requirements.memory_type_bits & (1 << i) != 0 && memory_type.property_flags.contains(flags)
+            })
+        };
+        let memory_type = find_memory_type(vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_CACHED)
+            .or_else(|| find_memory_type(vk::MemoryPropertyFlags::HOST_VISIBLE))
+            .unwrap();
+
+        let memory_allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(memory_requirements.size)
+            .memory_type_index(memory_type as u32);
+
+        let memory = self.allocate_memory(&memory_allocate_info);
+
+        unsafe {self.device.bind_buffer_memory(buffer, memory, 0)}.unwrap();
+
+        let mapped_ptr = unsafe {self.device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())}.unwrap();
+
+        let res = BufferResource {
+            buffer,
+            memory,
+            size,
+            allocation_size: memory_requirements.size,
+            memory_type,
+            mapped_ptr: Some(mapped_ptr),
+        };
+
+        BufferHandle(self.buffer_resources.insert(res))
+    }
+
+    /// Reads `count` elements from a readback buffer. The caller must make sure the GPU finished writing to it.
+    pub fn read_buffer<T: Copy>(&self, handle: &BufferHandle, count: usize) -> VkResult<Vec<T>> {
+        let resource = self.buffer_resource(handle);
+        assert!((count * std::mem::size_of::<T>()) as vk::DeviceSize <= resource.size);
+        let mem_ptr = resource.mapped_ptr.expect("Buffer is not host visible");
+
+        if !self.memory_types[resource.memory_type].property_flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT) {
+            let memory_range = vk::MappedMemoryRange::builder()
+                .memory(resource.memory)
+                .offset(0)
+                .size(vk::WHOLE_SIZE);
+            unsafe { self.device.invalidate_mapped_memory_ranges(&[memory_range.build()])?; }
+        }
+
+        let data = unsafe { std::slice::from_raw_parts(mem_ptr as *const T, count) };
+        Ok(data.to_vec())
     }
 
-    pub fn fill_buffer<T: Copy + Debug>(&mut self, resource: BufferResource, data: &[T]) {
+    /// Frees the buffer. Waits for the device to go idle, since the buffer may still be used by in-flight frames.
+    /// A stale handle is reported and ignored.
+    pub fn destroy_buffer(&mut self, handle: BufferHandle) {
+        let resource = match self.buffer_resources.remove(&handle.0) {
+            Ok(resource) => resource,
+            Err(e) => {
+                warn!("Not destroying buffer {:?}: {}", handle, e);
+                return;
+            }
+        };
+        unsafe {
+            // ignore the result: resources must be freed even if the device was lost
+            let _ = self.device.device_wait_idle();
+            if resource.mapped_ptr.is_some() {
+                self.device.unmap_memory(resource.memory);
+            }
+            self.device.destroy_buffer(resource.buffer, None);
+        }
+        self.free_memory(resource.memory, resource.allocation_size, resource.memory_type);
+    }
+
+    /// Makes host writes to a persistently mapped buffer visible to the device.
+    /// No-op for host coherent memory and for buffers written through staging.
+    pub fn flush_buffer(&self, handle: &BufferHandle) -> VkResult<()> {
+        let resource = self.buffer_resource(handle);
+        if resource.mapped_ptr.is_none() || self.memory_types[resource.memory_type].property_flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT) {
+            return Ok(());
+        }
+
+        let memory_range = vk::MappedMemoryRange::builder()
+            .memory(resource.memory)
+            .offset(0)
+            .size(vk::WHOLE_SIZE);
+        unsafe { self.device.flush_mapped_memory_ranges(&[memory_range.build()]) }
+    }
+
+    /// Returns the error from waiting on or submitting the transfer, so device loss can be handled by the caller.
+    pub fn fill_buffer<T: Copy + Debug>(&mut self, handle: &BufferHandle, data: &[T]) -> VkResult<()> {
         //size checktransfer_completed_fence
         let size = (data.len() * std::mem::size_of::<T>()) as vk::DeviceSize;
-        assert!(size <= resource.size);
+        let (resource_buffer, resource_mapped_ptr) = {
+            let resource = self.buffer_resource(handle);
+            assert!(size <= resource.size);
+            (resource.buffer, resource.mapped_ptr)
+        };
 
 
         unsafe {
-            self.device.wait_for_fences(&[self.transfer_completed_fence], true, std::u64::MAX).unwrap();
-            self.device.reset_fences(&[self.transfer_completed_fence]).unwrap();
+            self.device.wait_for_fences(&[self.transfer_completed_fence], true, std::u64::MAX)?;
+            self.device.reset_fences(&[self.transfer_completed_fence])?;
             
 
             self.device.begin_command_buffer(self.command_buffer, 
                 &vk::CommandBufferBeginInfo::builder()
                 .flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT)).unwrap();
         }
+        self.cmd_begin_label(self.command_buffer, "resource upload");
         match self.host_access_policy {
             HostAccessPolicy::SingleBuffer(_) => {
                 //write to device_local
                 unsafe {
-                    let mem_ptr = self.device.map_memory(resource.memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()).unwrap();
+                    let mem_ptr = resource_mapped_ptr.expect("SingleBuffer resources are persistently mapped");
                     let mem_slice = std::slice::from_raw_parts_mut(mem_ptr as *mut T, data.len());
                     mem_slice.copy_from_slice(data);
-                    self.device.unmap_memory(resource.memory);
                 }
+                self.flush_buffer(handle)?;
             },
             HostAccessPolicy::UseStaging { host_memory_type, device_memory_type: _ } => {
                 // write to stahing
                 // transfer staging -> device_local
                 //  transfer | vertex_input barrier
                 let staging_buffer: BufferResource;
-                
+
+                // recreated when too small for this upload, the fence wait above finished the last copy from it
+                if let Some(small) = self.staging_buffer.take_if(|staging| staging.size < size) {
+                    unsafe {self.device.destroy_buffer(small.buffer, None)};
+                    self.free_memory(small.memory, small.allocation_size, small.memory_type);
+                }
                 if let Some(staging) = self.staging_buffer.take() {
                     staging_buffer = staging;
                 } else {
@@ -200,14 +541,18 @@ impl ResourceManager {
                         .allocation_size(memory_requirements.size)
                         .memory_type_index(host_memory_type as u32);
                     
-                    let memory = unsafe {self.device.allocate_memory(&memory_allocate_info, None)}.unwrap();
+                    let memory = self.allocate_memory(&memory_allocate_info);
 
                     unsafe {self.device.bind_buffer_memory(buffer, memory, 0)}.unwrap();
+                    self.set_name(buffer, "staging_buffer");
 
                     staging_buffer = BufferResource {
                         buffer,
                         memory,
                         size,
+                        allocation_size: memory_requirements.size,
+                        memory_type: host_memory_type,
+                        mapped_ptr: None,
                     };
                 }
                 unsafe {
@@ -221,15 +566,15 @@ impl ResourceManager {
                     .size(size);
 
                 unsafe {
-                    self.device.cmd_copy_buffer(self.command_buffer, staging_buffer.buffer, resource.buffer, &[copy_region.build()]);
+                    self.device.cmd_copy_buffer(self.command_buffer, staging_buffer.buffer, resource_buffer, &[copy_region.build()]);
                     
                 }
 
-                //barrier transfer write to vertex shader read
+                //barrier transfer write to vertex input, uniform and storage buffer reads, including GPU culling's compute pass
                 let buffer_memory_barrier = vk::BufferMemoryBarrier::builder()
                     .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-                    .dst_access_mask(vk::AccessFlags::VERTEX_ATTRIBUTE_READ)
-                    .buffer(resource.buffer)
+                    .dst_access_mask(vk::AccessFlags::VERTEX_ATTRIBUTE_READ | vk::AccessFlags::UNIFORM_READ | vk::AccessFlags::SHADER_READ)
+                    .buffer(resource_buffer)
                     .offset(0)
                     .size(vk::WHOLE_SIZE);
                 
@@ -237,7 +582,7 @@ impl ResourceManager {
                     self.device.cmd_pipeline_barrier(
                         self.command_buffer,
                         vk::PipelineStageFlags::TRANSFER,
-                        vk::PipelineStageFlags::VERTEX_INPUT,
+                        vk::PipelineStageFlags::VERTEX_INPUT | vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
                         vk::DependencyFlags::empty(),
                         &[],
                         &[buffer_memory_barrier.build()],
@@ -248,61 +593,22 @@ impl ResourceManager {
             }
         }
         
+        self.cmd_end_label(self.command_buffer);
         unsafe {
             self.device.end_command_buffer(self.command_buffer).unwrap();
             let submit_info = vk::SubmitInfo::builder()
                 .command_buffers(&[self.command_buffer])
                 .build();
-            self.device.queue_submit(self.queue, &[submit_info], self.transfer_completed_fence).unwrap();
+            self.device.queue_submit(self.queue, &[submit_info], self.transfer_completed_fence)?;
         }
+        Ok(())
     }
-    pub fn cmd_barrier_after_vertex_buffer_use(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, vertex_buffer: &BufferResource) {
-        match self.host_access_policy {
-            HostAccessPolicy::SingleBuffer(_) => {
-                let buffer_memory_barrier = vk::BufferMemoryBarrier::builder()
-                    .src_access_mask(vk::AccessFlags::VERTEX_ATTRIBUTE_READ)
-                    .dst_access_mask(vk::AccessFlags::HOST_WRITE)
-                    .buffer(vertex_buffer.buffer)
-                    .offset(0)
-                    .size(vk::WHOLE_SIZE);
-                
-                unsafe {
-                    device.cmd_pipeline_barrier(
-                        command_buffer,
-                        vk::PipelineStageFlags::VERTEX_INPUT,
-                        vk::PipelineStageFlags::HOST,
-                        vk::DependencyFlags::empty(),
-                        &[],
-                        &[buffer_memory_barrier.build()],
-                        &[],
-                    );
-                }
-            },
-            HostAccessPolicy::UseStaging { host_memory_type: _, device_memory_type: _ } => {
-                let buffer_memory_barrier = vk::BufferMemoryBarrier::builder()
-                    .src_access_mask(vk::AccessFlags::VERTEX_ATTRIBUTE_READ)
-                    .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-                    .buffer(vertex_buffer.buffer)
-                    .offset(0)
-                    .size(vk::WHOLE_SIZE);
-                
-                unsafe {
-                    device.cmd_pipeline_barrier(
-                        command_buffer,
-                        vk::PipelineStageFlags::VERTEX_INPUT,
-                        vk::PipelineStageFlags::TRANSFER,
-                        vk::DependencyFlags::empty(),
-                        &[],
-                        &[buffer_memory_barrier.build()],
-                        &[],
-                    );
-                }
-            }
-        }
+    pub fn create_image(&mut self, width: u32, height: u32, format: vk::Format, tiling: vk::ImageTiling, usage: vk::ImageUsageFlags) -> ImageHandle {
+        self.create_multisampled_image(width, height, format, tiling, usage, vk::SampleCountFlags::TYPE_1)
     }
 
-
-    pub fn create_image(&mut self, width: u32, height: u32, format: vk::Format, tiling: vk::ImageTiling, usage: vk::ImageUsageFlags) -> ImageResource {
+    /// `create_image` with `samples` per pixel, for render pass attachments
+    pub fn create_multisampled_image(&mut self, width: u32, height: u32, format: vk::Format, tiling: vk::ImageTiling, usage: vk::ImageUsageFlags, samples: vk::SampleCountFlags) -> ImageHandle {
         let image_create_info = vk::ImageCreateInfo::builder()
             .image_type(vk::ImageType::TYPE_2D)
             .format(format)
@@ -313,7 +619,7 @@ impl ResourceManager {
             })
             .mip_levels(1)
             .array_layers(1)
-            .samples(vk::SampleCountFlags::TYPE_1)
+            .samples(samples)
             .tiling(tiling)
             .usage(usage | vk::ImageUsageFlags::TRANSFER_DST)
             .sharing_mode(vk::SharingMode::EXCLUSIVE)
@@ -331,21 +637,47 @@ impl ResourceManager {
             .allocation_size(memory_requirements.size)
             .memory_type_index(memory_type_device as u32);
         
-        let memory = unsafe {self.device.allocate_memory(&memory_allocate_info, None)}.unwrap();
+        let memory = self.allocate_memory(&memory_allocate_info);
 
         unsafe {self.device.bind_image_memory(image, memory, 0)}.unwrap();
 
-        ImageResource {
+        let res = ImageResource {
             image,
             memory,
-            size: memory_requirements.size,
+            allocation_size: memory_requirements.size,
+            memory_type: memory_type_device,
             width,
             height
+        };
+
+        ImageHandle(self.image_resources.insert(res))
+    }
+
+    /// Frees the image. Waits for the device to go idle, since the image may still be used by in-flight frames.
+    /// A stale handle is reported and ignored.
+    pub fn destroy_image(&mut self, handle: ImageHandle) {
+        let resource = match self.image_resources.remove(&handle.0) {
+            Ok(resource) => resource,
+            Err(e) => {
+                warn!("Not destroying image {:?}: {}", handle, e);
+                return;
+            }
+        };
+        unsafe {
+            // ignore the result: resources must be freed even if the device was lost
+            let _ = self.device.device_wait_idle();
+            self.device.destroy_image(resource.image, None);
         }
+        self.free_memory(resource.memory, resource.allocation_size, resource.memory_type);
     }
 
-    // TODO: save buffer or free it
-    pub fn fill_image(&mut self, imageResource: ImageResource, data: &[u8]) {
+    /// Uploads `data` through a staging buffer and leaves the image in SHADER_READ_ONLY_OPTIMAL, blocking until the copy is done.
+    /// Returns the error from waiting on or submitting the transfer, so device loss can be handled by the caller.
+    pub fn fill_image(&mut self, handle: &ImageHandle, data: &[u8]) -> VkResult<()> {
+        let (image, width, height) = {
+            let resource = self.image_resource(handle);
+            (resource.image, resource.width, resource.height)
+        };
         let buffer_create_info = vk::BufferCreateInfo::builder()
             .size(data.len() as u64)
             .usage(vk::BufferUsageFlags::TRANSFER_SRC)
@@ -363,9 +695,10 @@ impl ResourceManager {
             .allocation_size(memory_requirements.size)
             .memory_type_index(memory_type_host as u32);
         
-        let memory = unsafe {self.device.allocate_memory(&memory_allocate_info, None)}.unwrap();
+        let memory = self.allocate_memory(&memory_allocate_info);
 
         unsafe {self.device.bind_buffer_memory(buffer, memory, 0)}.unwrap();
+        self.set_name(buffer, "image_upload_staging_buffer");
 
         unsafe {
             let mem_ptr = self.device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()).unwrap();
@@ -382,12 +715,19 @@ impl ResourceManager {
                 .layer_count(1)
                 .build())
             .image_extent(vk::Extent3D {
-                width: imageResource.width,
-                height: imageResource.height,
+                width,
+                height,
                 depth: 1,
             });
         
-        unsafe {
+        let res = unsafe {
+            // the command buffer may still be pending from the last `fill_buffer`
+            if let Err(e) = self.device.wait_for_fences(&[self.transfer_completed_fence], true, u64::MAX)
+                .and_then(|_| self.device.reset_fences(&[self.transfer_completed_fence])) {
+                self.device.destroy_buffer(buffer, None);
+                self.free_memory(memory, memory_requirements.size, memory_type_host);
+                return Err(e);
+            }
             self.device.begin_command_buffer(self.command_buffer, &vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)).unwrap();
             
             // transition image layout from undefined to transfer destination
@@ -396,7 +736,7 @@ impl ResourceManager {
                 .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
                 .old_layout(vk::ImageLayout::UNDEFINED)
                 .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-                .image(imageResource.image)
+                .image(image)
                 .subresource_range(vk::ImageSubresourceRange::builder()
                     .aspect_mask(vk::ImageAspectFlags::COLOR)
                     .base_mip_level(0)
@@ -407,7 +747,7 @@ impl ResourceManager {
 
             self.device.cmd_pipeline_barrier(self.command_buffer, vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::TRANSFER, vk::DependencyFlags::empty(), &[], &[], &[image_memory_barrier.build()]);
             
-            self.device.cmd_copy_buffer_to_image(self.command_buffer, buffer, imageResource.image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[copy_region.build()]);
+            self.device.cmd_copy_buffer_to_image(self.command_buffer, buffer, image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[copy_region.build()]);
             
             // transition image layout from transfer destination to shader read
             let image_memory_barrier = vk::ImageMemoryBarrier::builder()
@@ -415,7 +755,7 @@ impl ResourceManager {
                 .dst_access_mask(vk::AccessFlags::SHADER_READ)
                 .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
                 .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                .image(imageResource.image)
+                .image(image)
                 .subresource_range(vk::ImageSubresourceRange::builder()
                     .aspect_mask(vk::ImageAspectFlags::COLOR)
                     .base_mip_level(0)
@@ -431,10 +771,111 @@ impl ResourceManager {
             let submit_info = vk::SubmitInfo::builder()
                 .command_buffers(&[self.command_buffer]).build();
 
-            self.device.queue_submit(self.queue, &[submit_info], vk::Fence::null()).unwrap();
+            self.device.queue_submit(self.queue, &[submit_info], self.transfer_completed_fence)
+                .and_then(|_| self.device.wait_for_fences(&[self.transfer_completed_fence], true, u64::MAX))
+        };
+
+        // the copy is done, unless the submit or wait failed and the device is lost anyway
+        unsafe {self.device.destroy_buffer(buffer, None)};
+        self.free_memory(memory, memory_requirements.size, memory_type_host);
+        res
+    }
+
+    /// Copies a color image with 4 bytes per pixel into host memory, blocking until the copy is done.
+    /// The image must be in `layout` and stays in it.
+    pub fn read_image(&mut self, handle: &ImageHandle, layout: vk::ImageLayout) -> VkResult<Vec<u8>> {
+        let (image, width, height) = {
+            let resource = self.image_resource(handle);
+            (resource.image, resource.width, resource.height)
+        };
+        let size = width as usize * height as usize * 4;
+        let buffer = self.create_readback_buffer(size as vk::DeviceSize);
+        self.set_buffer_name(&buffer, "image_readback_buffer");
+
+        let copy_region = vk::BufferImageCopy::builder()
+            .image_subresource(vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(0)
+                .base_array_layer(0)
+                .layer_count(1)
+                .build())
+            .image_extent(vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            });
+
+        let res = unsafe {
+            // the command buffer may still be pending from the last `fill_buffer`
+            if let Err(e) = self.device.wait_for_fences(&[self.transfer_completed_fence], true, u64::MAX)
+                .and_then(|_| self.device.reset_fences(&[self.transfer_completed_fence])) {
+                self.destroy_buffer(buffer);
+                return Err(e);
+            }
+            self.device.begin_command_buffer(self.command_buffer, &vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)).unwrap();
+
+            self.device.cmd_copy_image_to_buffer(self.command_buffer, image, layout, self.buffer(&buffer), &[copy_region.build()]);
+
+            //make transfer write visible to host read
+            let buffer_memory_barrier = vk::BufferMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::HOST_READ)
+                .buffer(self.buffer(&buffer))
+                .offset(0)
+                .size(vk::WHOLE_SIZE);
+            self.device.cmd_pipeline_barrier(self.command_buffer, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::HOST, vk::DependencyFlags::empty(), &[], &[buffer_memory_barrier.build()], &[]);
 
-            self.device.queue_wait_idle(self.queue).unwrap();
+            self.device.end_command_buffer(self.command_buffer).unwrap();
+
+            let submit_info = vk::SubmitInfo::builder()
+                .command_buffers(&[self.command_buffer]).build();
+
+            self.device.queue_submit(self.queue, &[submit_info], self.transfer_completed_fence)
+                .and_then(|_| self.device.wait_for_fences(&[self.transfer_completed_fence], true, u64::MAX))
+                .and_then(|_| self.read_buffer::<u8>(&buffer, size))
+        };
+
+        self.destroy_buffer(buffer);
+        res
+    }
+
+    /// Usage and budget of each memory heap
+    pub fn memory_stats(&self) -> Vec<HeapStats> {
+        let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        if let Some((instance, physical_device)) = &self.memory_budget {
+            let mut memory_properties = vk::PhysicalDeviceMemoryProperties2::builder()
+                .push_next(&mut budget_properties);
+            unsafe { instance.get_physical_device_memory_properties2(*physical_device, &mut memory_properties) };
+        }
+        self.memory_heaps.iter().zip(&self.heap_allocated).enumerate().map(|(i, (heap, &allocated))| {
+            let (used, budget) = match self.memory_budget {
+                Some(_) => (budget_properties.heap_usage[i], budget_properties.heap_budget[i]),
+                None => (allocated, heap.size),
+            };
+            HeapStats { device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL), allocated, used, budget }
+        }).collect()
+    }
+
+    /// Allocates memory and counts it against its heap, warns when the heap gets near its budget
+    fn allocate_memory(&mut self, memory_allocate_info: &vk::MemoryAllocateInfo) -> vk::DeviceMemory {
+        let memory = unsafe {self.device.allocate_memory(memory_allocate_info, None)}.unwrap();
+        let heap = self.memory_types[memory_allocate_info.memory_type_index as usize].heap_index as usize;
+        self.heap_allocated[heap] += memory_allocate_info.allocation_size;
+
+        let stats = self.memory_stats()[heap];
+        let near_budget = stats.used as f64 > stats.budget as f64 * BUDGET_WARNING_THRESHOLD;
+        if near_budget && !self.heaps_near_budget[heap] {
+            warn!("Memory heap {} is near its budget: {} of {} MiB used", heap, stats.used >> 20, stats.budget >> 20);
         }
+        self.heaps_near_budget[heap] = near_budget;
+        memory
+    }
+
+    /// Frees memory from `allocate_memory`
+    fn free_memory(&mut self, memory: vk::DeviceMemory, allocation_size: vk::DeviceSize, memory_type: usize) {
+        unsafe { self.device.free_memory(memory, None) };
+        let heap = self.memory_types[memory_type].heap_index as usize;
+        self.heap_allocated[heap] -= allocation_size;
     }
 
     pub fn create_image_view(&self, image: vk::Image, format: vk::Format, aspect_flags: vk::ImageAspectFlags) -> vk::ImageView {
@@ -453,13 +894,13 @@ impl ResourceManager {
         unsafe {self.device.create_image_view(&image_view_create_info, None)}.unwrap()
     }
 
-    pub fn create_sampler(&self) -> vk::Sampler {
+    pub fn create_sampler(&self, filter: vk::Filter, address_mode: vk::SamplerAddressMode) -> vk::Sampler {
         let sampler_create_info = vk::SamplerCreateInfo::builder()
-            .mag_filter(vk::Filter::LINEAR)
-            .min_filter(vk::Filter::LINEAR)
-            .address_mode_u(vk::SamplerAddressMode::REPEAT)
-            .address_mode_v(vk::SamplerAddressMode::REPEAT)
-            .address_mode_w(vk::SamplerAddressMode::REPEAT)
+            .mag_filter(filter)
+            .min_filter(filter)
+            .address_mode_u(address_mode)
+            .address_mode_v(address_mode)
+            .address_mode_w(address_mode)
             .anisotropy_enable(false)
             .max_anisotropy(16.0)
             .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
@@ -475,3 +916,25 @@ impl ResourceManager {
     }
 }
 
+impl Drop for ResourceManager {
+    fn drop(&mut self) {
+        unsafe {
+            // ignore the result: resources must be freed even if the device was lost
+            let _ = self.device.device_wait_idle();
+
+            for resource in self.buffer_resources.drain().chain(self.staging_buffer.take()) {
+                if resource.mapped_ptr.is_some() {
+                    self.device.unmap_memory(resource.memory);
+                }
+                self.device.destroy_buffer(resource.buffer, None);
+                self.device.free_memory(resource.memory, None);
+            }
+            for resource in self.image_resources.drain() {
+                self.device.destroy_image(resource.image, None);
+                self.device.free_memory(resource.memory, None);
+            }
+
+            self.device.destroy_fence(self.transfer_completed_fence, None);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use glam::Affine2;
+
+use super::draw_list::{Frame, Transform2D};
+use super::material::MaterialId;
+use super::mesh::MeshHandle;
+use super::picking::EntityId;
+
+/// Node added with `Scene::add_node`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+/// What a node draws, in the node's own units
+#[derive(Debug, Clone, Copy)]
+pub struct Drawable {
+    pub mesh: MeshHandle,
+    pub material: MaterialId,
+    /// Written to the object id attachment for picking, None draws an unpickable object
+    pub entity: Option<EntityId>,
+}
+
+struct Node {
+    /// Relative to the parent, or to the world for root nodes
+    transform: Transform2D,
+    drawable: Option<Drawable>,
+    children: Vec<NodeId>,
+}
+
+/// Tree of transforms, moving a node moves everything below it. Drawn into a `Frame` with `Scene::draw`.
+#[derive(Default)]
+pub struct Scene {
+    nodes: Vec<Node>,
+    roots: Vec<NodeId>,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `transform` is relative to `parent`, a node without a drawable only groups its children
+    pub fn add_node(&mut self, parent: Option<NodeId>, transform: Transform2D, drawable: Option<Drawable>) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node { transform, drawable, children: Vec::new() });
+        match parent {
+            Some(parent) => self.nodes[parent.0].children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    pub fn transform(&self, node: NodeId) -> Transform2D {
+        self.nodes[node.0].transform
+    }
+
+    pub fn set_transform(&mut self, node: NodeId, transform: Transform2D) {
+        self.nodes[node.0].transform = transform;
+    }
+
+    /// Draws every node with a drawable, parents before their children
+    pub fn draw(&self, frame: &mut Frame) {
+        for root in &self.roots {
+            self.draw_node(frame, *root, &Affine2::IDENTITY);
+        }
+    }
+
+    fn draw_node(&self, frame: &mut Frame, id: NodeId, parent: &Affine2) {
+        let node = &self.nodes[id.0];
+        let model = *parent * node.transform.matrix();
+        if let Some(drawable) = node.drawable {
+            frame.draw_model(drawable.mesh, drawable.material, model, drawable.entity);
+        }
+        for child in &node.children {
+            self.draw_node(frame, *child, &model);
+        }
+    }
+}
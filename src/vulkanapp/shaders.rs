@@ -0,0 +1,44 @@
+use std::io::Cursor;
+
+use log::{info, warn};
+
+/// SPIR-V of the built-in shaders, embedded at compile time
+const EMBEDDED_SHADERS: [(&str, &[u8]); 15] = [
+    ("vert.spv", include_bytes!(concat!(env!("OUT_DIR"), "/vert.spv"))),
+    ("vert_pulling.spv", include_bytes!(concat!(env!("OUT_DIR"), "/vert_pulling.spv"))),
+    ("frag.spv", include_bytes!(concat!(env!("OUT_DIR"), "/frag.spv"))),
+    ("frag_bindless.spv", include_bytes!(concat!(env!("OUT_DIR"), "/frag_bindless.spv"))),
+    ("post_vert.spv", include_bytes!(concat!(env!("OUT_DIR"), "/post_vert.spv"))),
+    ("tonemap.spv", include_bytes!(concat!(env!("OUT_DIR"), "/tonemap.spv"))),
+    ("output.spv", include_bytes!(concat!(env!("OUT_DIR"), "/output.spv"))),
+    ("fxaa.spv", include_bytes!(concat!(env!("OUT_DIR"), "/fxaa.spv"))),
+    ("debug_line_vert.spv", include_bytes!(concat!(env!("OUT_DIR"), "/debug_line_vert.spv"))),
+    ("debug_line_frag.spv", include_bytes!(concat!(env!("OUT_DIR"), "/debug_line_frag.spv"))),
+    ("text_vert.spv", include_bytes!(concat!(env!("OUT_DIR"), "/text_vert.spv"))),
+    ("text_frag.spv", include_bytes!(concat!(env!("OUT_DIR"), "/text_frag.spv"))),
+    ("gui_vert.spv", include_bytes!(concat!(env!("OUT_DIR"), "/gui_vert.spv"))),
+    ("gui_frag.spv", include_bytes!(concat!(env!("OUT_DIR"), "/gui_frag.spv"))),
+    ("cull.spv", include_bytes!(concat!(env!("OUT_DIR"), "/cull.spv"))),
+];
+
+/// Directory checked for shader overrides, relative to the working directory.
+/// Only its top level, the prebuilt fallbacks of build.rs are in `shaders/prebuilt/`.
+const OVERRIDE_DIR: &str = "shaders";
+
+/// Loads a built-in shader by file name. A file with the same name in `shaders/` overrides the embedded copy.
+pub fn load_spirv(name: &str) -> Vec<u32> {
+    let path = std::path::Path::new(OVERRIDE_DIR).join(name);
+    if let Ok(code) = std::fs::read(&path) {
+        match ash::util::read_spv(&mut Cursor::new(&code)) {
+            Ok(spirv) => {
+                info!("Using shader override {}", path.display());
+                return spirv;
+            },
+            Err(e) => warn!("Ignoring shader override {}: {}", path.display(), e),
+        }
+    }
+
+    let (_, code) = EMBEDDED_SHADERS.iter().find(|(n, _)| *n == name)
+        .unwrap_or_else(|| panic!("Unknown built-in shader {}", name));
+    ash::util::read_spv(&mut Cursor::new(code)).unwrap()
+}
@@ -0,0 +1,17 @@
+//! Helpers for the `#[repr(C)]` structs that mirror shader blocks.
+//! glam's `Vec4` is 16 byte aligned like a std140/std430 vec4, `Vec2` takes 8 bytes in both,
+//! `Vec3` and `Mat3` don't match either layout and must not be used in blocks.
+
+/// Fails to compile when a struct mirroring a shader block no longer has the block's size,
+/// e.g. after a field was added on only one side
+#[macro_export]
+macro_rules! assert_block_size {
+    ($block:ty, $size:expr) => {
+        const _: () = assert!(std::mem::size_of::<$block>() == $size, "Size doesn't match the shader block");
+    };
+}
+
+/// Raw bytes of a block for push constants, `T` must be `#[repr(C)]` without implicit padding
+pub(super) fn bytes_of<T: Copy>(block: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(block as *const T as *const u8, std::mem::size_of::<T>()) }
+}
@@ -0,0 +1,53 @@
+use ash::vk;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayColorSpace {
+    /// 8 bit sRGB
+    Sdr,
+    /// 10 bit BT.2020 with the PQ (ST 2084) transfer function
+    Hdr10,
+    /// 16 bit float linear extended sRGB
+    ScRgb,
+}
+
+impl DisplayColorSpace {
+    fn matches(self, format: &vk::SurfaceFormatKHR) -> bool {
+        match self {
+            DisplayColorSpace::Sdr => format.format == vk::Format::B8G8R8A8_UNORM && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            DisplayColorSpace::Hdr10 => (format.format == vk::Format::A2B10G10R10_UNORM_PACK32 || format.format == vk::Format::A2R10G10B10_UNORM_PACK32)
+                && format.color_space == vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+            DisplayColorSpace::ScRgb => format.format == vk::Format::R16G16B16A16_SFLOAT && format.color_space == vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+        }
+    }
+
+    fn from_color_space(color_space: vk::ColorSpaceKHR) -> Self {
+        match color_space {
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT => DisplayColorSpace::Hdr10,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => DisplayColorSpace::ScRgb,
+            _ => DisplayColorSpace::Sdr,
+        }
+    }
+
+    /// Value of the `transferFunction` specialization constant in output.frag
+    pub fn transfer_function(self) -> u32 {
+        match self {
+            DisplayColorSpace::Sdr => 0,
+            DisplayColorSpace::Hdr10 => 1,
+            DisplayColorSpace::ScRgb => 2,
+        }
+    }
+
+    /// HDR color spaces are only reported by the surface with VK_EXT_swapchain_colorspace enabled
+    pub fn needs_colorspace_extension(self) -> bool {
+        self != DisplayColorSpace::Sdr
+    }
+}
+
+/// Picks the requested color space if the surface supports it, otherwise SDR, otherwise whatever comes first
+pub fn select_surface_format(surface_formats: &[vk::SurfaceFormatKHR], requested: DisplayColorSpace) -> (vk::SurfaceFormatKHR, DisplayColorSpace) {
+    let format = surface_formats.iter().find(|f| requested.matches(f))
+        .or_else(|| surface_formats.iter().find(|f| DisplayColorSpace::Sdr.matches(f)))
+        .unwrap_or_else(|| surface_formats.first().unwrap());
+
+    (*format, DisplayColorSpace::from_color_space(format.color_space))
+}
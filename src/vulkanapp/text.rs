@@ -0,0 +1,341 @@
+use std::mem;
+
+use ash::prelude::VkResult;
+use ash::vk;
+use log::debug;
+
+use super::descriptor_allocator::DescriptorAllocator;
+use super::resourceManager::{BufferHandle, ImageHandle, ResourceManager};
+use super::shaders;
+use super::vertex::VertexFormat;
+
+/// X11 misc-fixed 6x10 (public domain), ASCII 32 to 127 in 16 columns and 6 rows, coverage as gray
+const FONT_ATLAS: &[u8] = include_bytes!("../../fonts/font_6x10.png");
+const ATLAS_COLUMNS: u32 = 16;
+const ATLAS_ROWS: u32 = 6;
+/// Printable range, the first glyph of the atlas is `FIRST_CHAR`
+const FIRST_CHAR: u32 = 32;
+const LAST_CHAR: u32 = 126;
+
+/// Size of a glyph cell in pixels at scale 1, the font is monospaced
+const GLYPH_WIDTH: u32 = 6;
+const GLYPH_HEIGHT: u32 = 10;
+
+/// Triangle list vertex of the text pipeline
+#[repr(C)]
+struct TextVertex {
+    position: [f32; 2],
+    tex_coord: [f32; 2],
+    /// sRGB with straight alpha
+    color: [f32; 4],
+}
+
+crate::vertex_format!(TextVertex {
+    position: vk::Format::R32G32_SFLOAT,
+    tex_coord: vk::Format::R32G32_SFLOAT,
+    color: vk::Format::R32G32B32A32_SFLOAT,
+});
+
+/// Texture coordinate of solid quads, text.frag skips the atlas for negative coordinates
+const SOLID_TEX_COORD: [f32; 2] = [-1.0, -1.0];
+
+/// Floats per `TextVertex`
+const TEXT_VERTEX_FLOATS: usize = 8;
+
+/// Initial size of each in-flight frame's text vertex buffer, room for 1024 glyphs
+const INITIAL_BUFFER_SIZE: vk::DeviceSize = (1024 * 6 * mem::size_of::<TextVertex>()) as vk::DeviceSize;
+
+/// Immediate-mode screen space text, drawn into the output image after post-processing,
+/// so it is neither tonemapped nor anti-aliased. Collected per `Frame` like `DebugDraw`.
+#[derive(Debug, Clone, Default)]
+pub struct TextDraw {
+    /// Positions in pixels from the top-left corner
+    vertices: Vec<f32>,
+}
+
+impl TextDraw {
+    /// `position` is the top-left corner of the first glyph in pixels from the top-left of the screen.
+    /// Glyphs are `scale` times `GLYPH_WIDTH` by `GLYPH_HEIGHT`, `\n` starts a new line and characters
+    /// outside of printable ASCII are drawn as `?`. `color` is sRGB with alpha.
+    pub fn text(&mut self, position: [f32; 2], scale: u32, color: [f32; 4], text: &str) {
+        // whole pixels keep the nearest sampled glyphs sharp
+        let origin = position.map(f32::round);
+        let (glyph_width, glyph_height) = ((GLYPH_WIDTH * scale) as f32, (GLYPH_HEIGHT * scale) as f32);
+        let (atlas_width, atlas_height) = (ATLAS_COLUMNS * GLYPH_WIDTH, ATLAS_ROWS * GLYPH_HEIGHT);
+
+        let mut pen = origin;
+        for c in text.chars() {
+            if c == '\n' {
+                pen = [origin[0], pen[1] + glyph_height];
+                continue;
+            }
+            let code = match c as u32 {
+                code @ FIRST_CHAR..=LAST_CHAR => code,
+                _ => '?' as u32,
+            };
+            if c != ' ' {
+                let index = code - FIRST_CHAR;
+                let uv_min = [(index % ATLAS_COLUMNS * GLYPH_WIDTH) as f32 / atlas_width as f32, (index / ATLAS_COLUMNS * GLYPH_HEIGHT) as f32 / atlas_height as f32];
+                let uv_max = [uv_min[0] + GLYPH_WIDTH as f32 / atlas_width as f32, uv_min[1] + GLYPH_HEIGHT as f32 / atlas_height as f32];
+                self.quad(pen, [pen[0] + glyph_width, pen[1] + glyph_height], uv_min, uv_max, color);
+            }
+            pen[0] += glyph_width;
+        }
+    }
+
+    /// Solid rectangle between the corners `min` and `max` in pixels, for HUD elements like the crosshair
+    pub fn rect(&mut self, min: [f32; 2], max: [f32; 2], color: [f32; 4]) {
+        self.quad(min.map(f32::round), max.map(f32::round), SOLID_TEX_COORD, SOLID_TEX_COORD, color);
+    }
+
+    fn quad(&mut self, min: [f32; 2], max: [f32; 2], uv_min: [f32; 2], uv_max: [f32; 2], color: [f32; 4]) {
+        for (corner, uv) in [(min, uv_min), ([max[0], min[1]], [uv_max[0], uv_min[1]]), (max, uv_max),
+                             (min, uv_min), (max, uv_max), ([min[0], max[1]], [uv_min[0], uv_max[1]])] {
+            self.vertices.extend_from_slice(&corner);
+            self.vertices.extend_from_slice(&uv);
+            self.vertices.extend_from_slice(&color);
+        }
+    }
+
+    pub(super) fn vertex_count(&self) -> u32 {
+        (self.vertices.len() / TEXT_VERTEX_FLOATS) as u32
+    }
+
+    /// Vertices in normalized device coordinates of an output image of size `extent`
+    pub(super) fn vertex_data(&self, extent: vk::Extent2D) -> Vec<f32> {
+        let mut vertices = self.vertices.clone();
+        for vertex in vertices.chunks_exact_mut(TEXT_VERTEX_FLOATS) {
+            vertex[0] = vertex[0] / extent.width as f32 * 2.0 - 1.0;
+            vertex[1] = vertex[1] / extent.height as f32 * 2.0 - 1.0;
+        }
+        vertices
+    }
+}
+
+/// Font atlas, its descriptor set and the per-frame vertex buffers of `TextDraw`.
+/// The pipeline depends on the output render pass and is created with `create_pipeline`.
+pub(super) struct TextRenderer {
+    atlas: ImageHandle,
+    atlas_view: vk::ImageView,
+    sampler: vk::Sampler,
+
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+
+    /// `TextVertex` triangle lists, one copy per in-flight frame, grown on demand
+    vertex_buffers: Vec<BufferHandle>,
+}
+
+impl TextRenderer {
+    pub fn new(device: &ash::Device, resource_manager: &mut ResourceManager, descriptor_allocator: &mut DescriptorAllocator, in_flight_frames: usize) -> Self {
+        let image = image::load_from_memory(FONT_ATLAS).unwrap().to_rgba8();
+        let atlas = resource_manager.create_image(image.width(),
+            image.height(),
+            vk::Format::R8G8B8A8_UNORM,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::SAMPLED);
+        resource_manager.set_image_name(&atlas, "font_atlas");
+        resource_manager.fill_image(&atlas, image.as_raw()).unwrap();
+
+        let atlas_view = resource_manager.create_image_view(resource_manager.image(&atlas), vk::Format::R8G8B8A8_UNORM, vk::ImageAspectFlags::COLOR);
+        resource_manager.set_name(atlas_view, "font_atlas_view");
+        let sampler = resource_manager.create_sampler(vk::Filter::NEAREST, vk::SamplerAddressMode::CLAMP_TO_EDGE);
+        resource_manager.set_name(sampler, "font_sampler");
+
+        let descriptor_set_layout_bindings = [vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build()];
+        let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&descriptor_set_layout_bindings);
+        let descriptor_set_layout = unsafe { device.create_descriptor_set_layout(&descriptor_set_layout_create_info, None).unwrap() };
+        resource_manager.set_name(descriptor_set_layout, "text_descriptor_set_layout");
+
+        // the atlas never changes, one set serves every in-flight frame
+        let descriptor_set = descriptor_allocator.allocate(device, resource_manager, descriptor_set_layout);
+        resource_manager.set_name(descriptor_set, "text_descriptor_set");
+        let descriptor_image_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(atlas_view)
+            .sampler(sampler)
+            .build()];
+        let descriptor_write_set = [vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&descriptor_image_infos)
+            .build()];
+        unsafe { device.update_descriptor_sets(&descriptor_write_set, &[]) };
+
+        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(std::slice::from_ref(&descriptor_set_layout));
+        let pipeline_layout = unsafe { device.create_pipeline_layout(&pipeline_layout_create_info, None).unwrap() };
+        resource_manager.set_name(pipeline_layout, "text_pipeline_layout");
+
+        let vertex_buffers = (0..in_flight_frames).map(|i| {
+            let vertex_buffer = resource_manager.create_buffer(INITIAL_BUFFER_SIZE, vk::BufferUsageFlags::VERTEX_BUFFER);
+            resource_manager.set_buffer_name(&vertex_buffer, &format!("text_vertex_buffer[{}]", i));
+            vertex_buffer
+        }).collect();
+
+        Self {
+            atlas,
+            atlas_view,
+            sampler,
+
+            descriptor_set_layout,
+            descriptor_set,
+            pipeline_layout,
+
+            vertex_buffers,
+        }
+    }
+
+    /// The descriptor set goes with the `DescriptorAllocator`
+    pub fn destroy(self, device: &ash::Device, resource_manager: &mut ResourceManager) {
+        unsafe {
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            device.destroy_sampler(self.sampler, None);
+            device.destroy_image_view(self.atlas_view, None);
+        }
+        resource_manager.destroy_image(self.atlas);
+        for vertex_buffer in self.vertex_buffers {
+            resource_manager.destroy_buffer(vertex_buffer);
+        }
+    }
+
+    /// Writes `vertex_data` from `TextDraw::vertex_data` into the buffer of `in_flight_frame`, growing it if needed
+    pub fn upload(&mut self, resource_manager: &mut ResourceManager, in_flight_frame: usize, vertex_data: &[f32]) -> VkResult<()> {
+        if vertex_data.is_empty() {
+            return Ok(());
+        }
+        let vertex_data_size = mem::size_of_val(vertex_data) as vk::DeviceSize;
+        let buffer_size = resource_manager.buffer_size(&self.vertex_buffers[in_flight_frame]);
+        if vertex_data_size > buffer_size {
+            let size = vertex_data_size.max(buffer_size * 2);
+            debug!("Growing text vertex buffer [{}] to {} bytes", in_flight_frame, size);
+            let vertex_buffer = resource_manager.create_buffer(size, vk::BufferUsageFlags::VERTEX_BUFFER);
+            resource_manager.set_buffer_name(&vertex_buffer, &format!("text_vertex_buffer[{}]", in_flight_frame));
+            let old_buffer = mem::replace(&mut self.vertex_buffers[in_flight_frame], vertex_buffer);
+            resource_manager.destroy_buffer(old_buffer);
+        }
+        resource_manager.fill_buffer(&self.vertex_buffers[in_flight_frame], vertex_data)
+    }
+
+    /// Records the draw of the last upload of `in_flight_frame`, inside a render pass compatible with the pipeline's
+    pub fn cmd_draw(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, resource_manager: &ResourceManager, pipeline: vk::Pipeline, in_flight_frame: usize, vertex_count: u32) {
+        if vertex_count == 0 {
+            return;
+        }
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline_layout, 0, &[self.descriptor_set], &[]);
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[resource_manager.buffer(&self.vertex_buffers[in_flight_frame])], &[0]);
+            device.cmd_draw(command_buffer, vertex_count, 1, 0, 0);
+        }
+    }
+
+    /// Triangle list pipeline blending over the output image. `transfer_function` is the
+    /// specialization constant of output.frag, text.frag encodes its colors the same way.
+    pub fn create_pipeline(&self, device: &ash::Device, render_pass: vk::RenderPass, extent: vk::Extent2D, transfer_function: u32) -> vk::Pipeline {
+        let vertex_shader_code = shaders::load_spirv("text_vert.spv");
+        let fragment_shader_code = shaders::load_spirv("text_frag.spv");
+        let shader_module_create_info = vk::ShaderModuleCreateInfo::builder()
+            .code(&vertex_shader_code);
+        let vertex_shader_module = unsafe { device.create_shader_module(&shader_module_create_info, None).unwrap() };
+        let shader_module_create_info = vk::ShaderModuleCreateInfo::builder()
+            .code(&fragment_shader_code);
+        let fragment_shader_module = unsafe { device.create_shader_module(&shader_module_create_info, None).unwrap() };
+
+        let transfer_function = transfer_function.to_ne_bytes();
+        let specialization_map_entries = [vk::SpecializationMapEntry {
+            constant_id: 0,
+            offset: 0,
+            size: mem::size_of::<u32>(),
+        }];
+        let specialization_info = vk::SpecializationInfo::builder()
+            .map_entries(&specialization_map_entries)
+            .data(&transfer_function);
+        let shader_stages = [vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .module(vertex_shader_module)
+            .name(c"main")
+            .build(),
+        vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(fragment_shader_module)
+            .name(c"main")
+            .specialization_info(&specialization_info)
+            .build()];
+
+        let (vertex_binding_description, vertex_attribute_descriptions) = TextVertex::input_descriptions(0);
+        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(std::slice::from_ref(&vertex_binding_description))
+            .vertex_attribute_descriptions(&vertex_attribute_descriptions);
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+            .primitive_restart_enable(false);
+
+        let viewports = [vk::Viewport::builder()
+            .x(0.0)
+            .y(0.0)
+            .width(extent.width as f32)
+            .height(extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0)
+            .build()];
+        let scissors = [vk::Rect2D::builder()
+            .offset(vk::Offset2D::builder().x(0).y(0).build())
+            .extent(extent)
+            .build()];
+        let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+            .viewports(&viewports)
+            .scissors(&scissors);
+
+        let rasterizer = vk::PipelineRasterizationStateCreateInfo::builder()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .line_width(1.0)
+            .cull_mode(vk::CullModeFlags::NONE);
+        let multisampling = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+
+        // the output image keeps its alpha, read back frames stay opaque
+        let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::RGBA)
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .build()];
+        let color_blending = vk::PipelineColorBlendStateCreateInfo::builder()
+            .attachments(&color_blend_attachments);
+
+        let graphics_pipeline_create_info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&shader_stages)
+            .vertex_input_state(&vertex_input_info)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterizer)
+            .multisample_state(&multisampling)
+            .color_blend_state(&color_blending)
+            .layout(self.pipeline_layout)
+            .render_pass(render_pass)
+            .subpass(0)
+            .build();
+        let pipelines = unsafe { device.create_graphics_pipelines(vk::PipelineCache::null(), &[graphics_pipeline_create_info], None).unwrap() };
+
+        unsafe {
+            device.destroy_shader_module(vertex_shader_module, None);
+            device.destroy_shader_module(fragment_shader_module, None);
+        }
+        pipelines[0]
+    }
+}
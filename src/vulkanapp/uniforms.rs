@@ -0,0 +1,44 @@
+/// Per-frame uniform block at set 0, binding 1, see the `FrameUniforms` block in the shaders
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FrameUniforms {
+    /// Scaled game time in seconds, stops while paused
+    pub game_time: f32,
+    /// Wall clock time since start in seconds
+    pub real_time: f32,
+    /// Scaled time since the previous frame in seconds
+    pub delta_time: f32,
+    /// Set by `VulkanApp` from the display calibration
+    pub exposure: f32,
+    /// Set by `VulkanApp` from the display calibration
+    pub gamma: f32,
+    /// Nonzero while the calibration test pattern is shown
+    pub calibration_pattern: u32,
+    /// Set by `VulkanApp`, see `ColorFilter::shader_value`
+    pub color_filter: u32,
+    /// Set by `VulkanApp`, see `Tonemapper::shader_value`
+    pub tonemapper: u32,
+    /// Multiplies the scene color, 1 is full daylight
+    pub light_intensity: f32,
+    /// Set by `VulkanApp`, see `DebugView::shader_value`
+    pub debug_view: u32,
+}
+
+crate::assert_block_size!(FrameUniforms, 40);
+
+impl Default for FrameUniforms {
+    fn default() -> Self {
+        Self {
+            game_time: 0.0,
+            real_time: 0.0,
+            delta_time: 0.0,
+            exposure: 0.0,
+            gamma: 0.0,
+            calibration_pattern: 0,
+            color_filter: 0,
+            tonemapper: 0,
+            light_intensity: 1.0,
+            debug_view: 0,
+        }
+    }
+}
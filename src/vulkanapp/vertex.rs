@@ -1,8 +1,57 @@
+use ash::vk;
+use glam::{Vec2, Vec3};
 
+/// Vertex of the scene pass, see the inputs of shader.vert
 #[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Vertex {
-    pub position: [f32; 3],
-    pub texCoord: [f32; 2],
+    pub position: Vec3,
+    pub tex_coord: Vec2,
+}
+
+// vert_pulling.spv reads vertices as five tightly packed floats
+crate::assert_block_size!(Vertex, 20);
+
+crate::vertex_format!(Vertex {
+    position: vk::Format::R32G32B32_SFLOAT,
+    tex_coord: vk::Format::R32G32_SFLOAT,
+});
+
+/// Vertex buffer struct a pipeline can read, implemented with `vertex_format!`
+pub(super) trait VertexFormat: Sized {
+    /// Format and byte offset of each attribute, the attribute at index i is read at location i
+    fn attributes() -> Vec<(vk::Format, u32)>;
+
+    /// One `Self` per vertex at `binding`, and the attribute descriptions of that binding
+    fn input_descriptions(binding: u32) -> (vk::VertexInputBindingDescription, Vec<vk::VertexInputAttributeDescription>) {
+        let binding_description = vk::VertexInputBindingDescription::builder()
+            .binding(binding)
+            .stride(std::mem::size_of::<Self>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build();
+        let attribute_descriptions = Self::attributes().into_iter().enumerate().map(|(location, (format, offset))| {
+            vk::VertexInputAttributeDescription::builder()
+                .binding(binding)
+                .location(location as u32)
+                .format(format)
+                .offset(offset)
+                .build()
+        }).collect();
+        (binding_description, attribute_descriptions)
+    }
+}
+
+/// Implements `VertexFormat` for a `#[repr(C)]` struct from its attribute fields in location order,
+/// e.g. `vertex_format!(Vertex { position: vk::Format::R32G32B32_SFLOAT, tex_coord: vk::Format::R32G32_SFLOAT })`
+#[macro_export]
+macro_rules! vertex_format {
+    ($vertex:path { $($field:ident: $format:expr),+ $(,)? }) => {
+        impl $crate::vulkanapp::vertex::VertexFormat for $vertex {
+            fn attributes() -> Vec<(ash::vk::Format, u32)> {
+                vec![$(($format, $crate::offset_of!($vertex, $field) as u32)),+]
+            }
+        }
+    };
 }
 
 #[macro_export]
@@ -10,7 +59,7 @@ macro_rules! offset_of {
     ($base:path, $field:ident) => {{
         #[allow(unused_unsafe)]
         unsafe {
-            let b: $base = mem::zeroed();
+            let b: $base = std::mem::zeroed();
             std::ptr::addr_of!(b.$field) as isize - std::ptr::addr_of!(b) as isize
         }
     }};